@@ -3,13 +3,96 @@
 {{readme}}
 
 ## Usage
-This crate simply appends to your `.bash_profile`.
-It will create a `.bash_profile` file if one is not
+This crate simply appends to your `.bash_profile` or `.bash_login` or `.profile`
+in that order.
+It will create a `.bash_profile` file if none of the above are
 found in your home directory.
 `ie. /Users/me/.bash_profile`.
+On Termux it creates `.profile` instead because Termux
+doesn't ship a `.bash_profile` and creating one would hide it.
+
+If your `$SHELL` is zsh it appends to `.zshenv` or `.zprofile`
+instead, looking in `$ZDOTDIR` if it's set.
+It will create a `.zshenv` if neither is found.
+On macOS zsh is assumed when the shell can't be detected and
+`.zprofile` is preferred because `path_helper` resets `PATH`
+after `.zshenv` runs. Set `ENV_PERM_SHELL=bash` to opt out.
+
+For fish the variables go in their own
+`~/.config/fish/conf.d/env_perm.fish` using `set -gx`.
+
+For nushell they are appended to `env.nu` in nu's config directory
+as `$env.FOO = "..."`.
+
+csh and tcsh get `setenv` lines in `.login`, `.tcshrc` or `.cshrc`
+in that order.
+On FreeBSD `Backend::LoginConf` writes them to the `setenv`
+capability in `~/.login_conf` instead, which every login reads.
+
+elvish gets `set-env` lines in `~/.config/elvish/rc.elv`
+and `PATH` is added to through `$paths`.
+
+xonsh gets `$FOO = "..."` lines in `~/.xonshrc`, with
+values that use other variables written as f-strings like
+`f"{$HOME}/bin"` since xonsh only expands them there.
+
+ksh and mksh append to `.profile`, falling back to the file
+named by `$ENV` or `.kshrc`.
+
+Use `Config` with `Target::InteractiveRc` to write to the files
+non-login shells read like `.bashrc` and `.zshrc` instead,
+or `Target::Both` for both.
+
+`env_perm::append` adds to the end of a variable like `PATH`
+and `env_perm::prepend` adds to the front so it takes precedence.
+`env_perm::append_unique` skips entries that are already there
+and `env_perm::append_with` takes a separator other than `:`.
+
+`env_perm::update` rewrites an existing assignment in place
+instead of adding a second one like `env_perm::set` does.
+
+`env_perm::get_persistent("FOO")` reads back what's persisted,
+which can differ from what the running process has,
+and `env_perm::exists_persistent("FOO")` checks it's there at all.
+`env_perm::list_persistent()` lists everything.
+`Config::check` with `CheckMode::Persisted` makes `check_or_set`
+look at what's persisted rather than this session's environment.
+
+`env_perm::unset("FOO")` removes every assignment to `FOO`
+from the profile, or deletes it from the registry on windows.
+`env_perm::remove_from("PATH", dir)` takes out a single entry
+and `env_perm::dedupe("PATH")` cleans out repeats.
+`env_perm::rename("OLD", "NEW")` moves a variable to a new name.
+
+If your profile is a symlink or managed by chezmoi or yadm
+`Config::dotfiles` lets you refuse, get a warning or write
+to the manager's source file instead.
+
+`env_perm::profile_path()` tells you which file will be changed
+before anything is written and `env_perm::registry_key()`
+does the same on windows.
+
+`env_perm::migrate(Shell::Bash, Shell::Fish, &["PATH"])` copies
+existing assignments from one shell's profiles to another's,
+rewritten in the new shell's syntax.
+
+Users can redirect all of this by setting `ENV_PERM_PROFILE`
+to the file they want written and `ENV_PERM_SHELL` to the
+shell whose syntax should be used.
+`env_perm::overrides()` reports what they've set.
+
+On windows the variables are written to your user environment
+in the registry with `setx`.
+Use `Config::backend` to pick a different `Backend`, e.g.
+`Backend::Profile(Shell::Pwsh)` adds `$env:FOO = "..."` lines
+to your PowerShell `$PROFILE` instead.
+Under Git Bash, MSYS2 or Cygwin use `Config::msys` with
+`MsysMode::Profile` to write `~/.bash_profile` instead,
+or `MsysMode::Both` for both.
+If your `$SHELL` is `pwsh` on macOS or linux they go in
+`~/.config/powershell/Microsoft.PowerShell_profile.ps1`.
 
 I have only tested this on macOS but it should
-work on any unix system. I want to add windows 
-support soon.
+work on any unix system.
 
 Contributions are welcome.
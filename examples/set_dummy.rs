@@ -1,5 +1,3 @@
-use env_perm;
-
 fn main() {
     // Check if DUMMY is set, if not set it to 1
     // export DUMMY=1
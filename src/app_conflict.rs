@@ -0,0 +1,135 @@
+//! Noticing when the blocks of two different
+//! apps in one file manage the same variable.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::profile;
+use crate::Shell;
+
+/// A variable the blocks of more than one app in a file
+/// assign to, from [`app_conflicts`](crate::app_conflicts).
+/// The shell runs the blocks in order so the last
+/// one wins, or for a list like `PATH` its entries
+/// come first or last.
+///
+/// Writing with [`Config::app`](crate::Config::app) also
+/// fails with one when another app's block already sets
+/// the variable to something else, unless
+/// [`Config::allow_app_conflicts`](crate::Config::allow_app_conflicts)
+/// says not to.
+/// ```no_run
+/// use env_perm::{AppConflict, Config};
+///
+/// if let Err(e) = Config::new().app("myapp").set("JAVA_HOME", "/opt/myapp/jre") {
+///     match AppConflict::from_error(&e) {
+///         Some(conflict) => println!("{}", conflict),
+///         None => panic!("{}", e),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AppConflict {
+    /// The variable.
+    pub var: String,
+    /// The file the blocks are in.
+    pub file: PathBuf,
+    /// Each app's assignment to it and the value as written,
+    /// in the order they come in the file.
+    pub apps: Vec<(String, String)>,
+}
+
+impl AppConflict {
+    /// The conflict `e` is about, if it's one.
+    pub fn from_error(e: &io::Error) -> Option<&AppConflict> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for AppConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is managed by more than one app in {}:", self.var, self.file.display())?;
+        for (app, value) in &self.apps {
+            write!(f, " {} gives {:?};", app, value)?;
+        }
+        write!(f, " the last one wins")
+    }
+}
+
+impl Error for AppConflict {}
+
+impl From<AppConflict> for io::Error {
+    fn from(conflict: AppConflict) -> io::Error {
+        io::Error::other(conflict)
+    }
+}
+
+/// Every variable the blocks of more than one
+/// app in the file at `path` assign to.
+pub(crate) fn find(shell: Shell, path: &Path) -> io::Result<Vec<AppConflict>> {
+    let mut vars: Vec<AppConflict> = Vec::new();
+    for (app, var, value) in assignments(shell, &profile::read_lines(path)?) {
+        match vars.iter_mut().find(|found| found.var == var) {
+            Some(found) => found.apps.push((app, value)),
+            None => vars.push(AppConflict { var, file: path.to_path_buf(), apps: vec![(app, value)] }),
+        }
+    }
+    vars.retain(|found| found.apps.iter().any(|(app, _)| *app != found.apps[0].0));
+    Ok(vars)
+}
+
+/// Fails if another app's block in the file at `path` sets
+/// a variable one of `lines` sets for `app` to something else.
+pub(crate) fn check(shell: Shell, path: &Path, app: &str, lines: &[String]) -> io::Result<()> {
+    let existing = assignments(shell, &profile::read_lines(path)?);
+    for line in lines {
+        let (var, value) = match shell.parse_line(line) {
+            Some((var, value)) if is_set(shell, &var, &value) => (var, value),
+            _ => continue,
+        };
+        let others: Vec<(String, String)> = existing
+            .iter()
+            .filter(|(other, name, theirs)| other != app && *name == var && is_set(shell, name, theirs) && *theirs != value)
+            .map(|(other, _, theirs)| (other.clone(), theirs.clone()))
+            .collect();
+        if !others.is_empty() {
+            let mut apps = others;
+            apps.push((app.to_string(), value));
+            return Err(AppConflict { var, file: path.to_path_buf(), apps }.into());
+        }
+    }
+    Ok(())
+}
+
+// Each assignment in an app's block in `lines`
+// as the app, the variable and its value
+fn assignments(shell: Shell, lines: &[String]) -> Vec<(String, String, String)> {
+    let mut found = Vec::new();
+    let mut app: Option<&str> = None;
+    for line in lines {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix("# >>> env_perm (").and_then(|rest| rest.strip_suffix(") >>>")) {
+            app = Some(name);
+            continue;
+        }
+        let current = match app {
+            Some(current) => current,
+            None => continue,
+        };
+        if trimmed == profile::markers(current).1 {
+            app = None;
+        } else if let Some((var, value)) = shell.parse_line(line) {
+            found.push((current.to_string(), var, value));
+        }
+    }
+    found
+}
+
+// Whether `value` replaces `var` outright
+// rather than adding to it
+fn is_set(shell: Shell, var: &str, value: &str) -> bool {
+    shell.appended(var, value).is_none() && shell.prepended(var, value).is_none()
+}
@@ -0,0 +1,570 @@
+//! The different ways a variable can be persisted.
+
+use std::ffi::OsStr;
+use std::io;
+use std::sync::Arc;
+
+use crate::shell::{Shell, PATH_SEPARATOR};
+use crate::{Config, Overrides};
+
+/// Something that can persist environment variables.
+///
+/// The built in [`Backend`]s are implemented with this and
+/// you can plug in your own with
+/// [`Config::custom_backend`](crate::Config::custom_backend),
+/// e.g. to write to a corporate config system.
+/// ```
+/// use std::collections::HashMap;
+/// use std::io;
+/// use std::sync::Mutex;
+/// use env_perm::{Config, EnvBackend};
+///
+/// #[derive(Default)]
+/// struct InMemory(Mutex<HashMap<String, String>>);
+///
+/// impl EnvBackend for InMemory {
+///     fn set(&self, var: &str, value: &str) -> io::Result<()> {
+///         self.0.lock().unwrap().insert(var.to_string(), value.to_string());
+///         Ok(())
+///     }
+///     fn append(&self, var: &str, value: &str) -> io::Result<()> {
+///         let mut vars = self.0.lock().unwrap();
+///         let joined = match vars.get(var) {
+///             Some(current) => format!("{}:{}", current, value),
+///             None => value.to_string(),
+///         };
+///         vars.insert(var.to_string(), joined);
+///         Ok(())
+///     }
+///     fn prepend(&self, var: &str, value: &str) -> io::Result<()> {
+///         let mut vars = self.0.lock().unwrap();
+///         let joined = match vars.get(var) {
+///             Some(current) => format!("{}:{}", value, current),
+///             None => value.to_string(),
+///         };
+///         vars.insert(var.to_string(), joined);
+///         Ok(())
+///     }
+///     fn remove(&self, var: &str) -> io::Result<()> {
+///         self.0.lock().unwrap().remove(var);
+///         Ok(())
+///     }
+///     fn get(&self, var: &str) -> io::Result<Option<String>> {
+///         Ok(self.0.lock().unwrap().get(var).cloned())
+///     }
+/// }
+///
+/// let config = Config::new().custom_backend(InMemory::default());
+/// config.set("DUMMY", "b").unwrap();
+/// config.append("DUMMY", "c").unwrap();
+/// config.prepend("DUMMY", "a").unwrap();
+/// assert_eq!(config.get_persistent("DUMMY").unwrap().as_deref(), Some("a:b:c"));
+/// ```
+pub trait EnvBackend {
+    /// Persists `var` as `value`.
+    fn set(&self, var: &str, value: &str) -> io::Result<()>;
+
+    /// Persists every variable in `vars`.
+    /// By default this calls [`set`](EnvBackend::set) for each,
+    /// backends that can should do it in one go.
+    fn set_many(&self, vars: &[(String, String)]) -> io::Result<()> {
+        vars.iter().try_for_each(|(var, value)| self.set(var, value))
+    }
+
+    /// Persists `value` added to the end
+    /// of the list in `var`.
+    fn append(&self, var: &str, value: &str) -> io::Result<()>;
+
+    /// Persists `value` added to the front
+    /// of the list in `var`.
+    fn prepend(&self, var: &str, value: &str) -> io::Result<()>;
+
+    /// Like [`set`](EnvBackend::set) for a value
+    /// that might not be UTF-8.
+    /// By default UTF-8 values go to `set` and anything
+    /// else fails with [`io::ErrorKind::InvalidData`].
+    fn set_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        self.set(var, utf8(var, value)?)
+    }
+
+    /// Like [`append`](EnvBackend::append) for a value
+    /// that might not be UTF-8, see [`set_os`](EnvBackend::set_os).
+    fn append_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        self.append(var, utf8(var, value)?)
+    }
+
+    /// Like [`prepend`](EnvBackend::prepend) for a value
+    /// that might not be UTF-8, see [`set_os`](EnvBackend::set_os).
+    fn prepend_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        self.prepend(var, utf8(var, value)?)
+    }
+
+    /// Like [`append`](EnvBackend::append) with `separator`
+    /// between the entries instead of the usual one.
+    /// By default this joins what [`get`](EnvBackend::get)
+    /// returns and writes it with [`set`](EnvBackend::set).
+    fn append_with(&self, var: &str, value: &str, separator: char) -> io::Result<()> {
+        let joined = match self.get(var)? {
+            Some(current) if !current.is_empty() => format!("{}{}{}", current, separator, value),
+            _ => value.to_string(),
+        };
+        self.set(var, &joined)
+    }
+
+    /// Like [`append_with`](EnvBackend::append_with) for a value
+    /// that might not be UTF-8, see [`set_os`](EnvBackend::set_os).
+    fn append_with_os(&self, var: &str, value: &OsStr, separator: char) -> io::Result<()> {
+        self.append_with(var, utf8(var, value)?, separator)
+    }
+
+    /// Persists `var` as `value`, replacing an existing
+    /// assignment where it is rather than adding another.
+    /// By default this is the same as [`set`](EnvBackend::set).
+    fn update(&self, var: &str, value: &str) -> io::Result<()> {
+        self.set(var, value)
+    }
+
+    /// Removes whatever is persisted for `var`.
+    /// It's not an error if there's nothing.
+    fn remove(&self, var: &str) -> io::Result<()>;
+
+    /// The persisted value of `var`, if there is one.
+    /// This can differ from what's in the
+    /// environment of the current process.
+    fn get(&self, var: &str) -> io::Result<Option<String>>;
+
+    /// Takes `entry` out of the list persisted in `var`.
+    /// It's not an error if it isn't there.
+    /// By default this rewrites what [`get`](EnvBackend::get)
+    /// returns with [`set`](EnvBackend::set).
+    fn remove_entry(&self, var: &str, entry: &str) -> io::Result<()> {
+        let current = match self.get(var)? {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        let parts: Vec<&str> = current.split(PATH_SEPARATOR).collect();
+        if !parts.contains(&entry) {
+            return Ok(());
+        }
+        let kept: Vec<&str> = parts.into_iter().filter(|part| *part != entry).collect();
+        self.set(var, &kept.join(&PATH_SEPARATOR.to_string()))
+    }
+
+    /// Rewrites the list persisted in `var` without
+    /// repeated entries, keeping the first of each.
+    /// By default this rewrites what [`get`](EnvBackend::get)
+    /// returns with [`set`](EnvBackend::set).
+    fn dedupe(&self, var: &str) -> io::Result<()> {
+        let current = match self.get(var)? {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        let mut kept: Vec<&str> = Vec::new();
+        for part in current.split(PATH_SEPARATOR) {
+            if !kept.contains(&part) {
+                kept.push(part);
+            }
+        }
+        let deduped = kept.join(&PATH_SEPARATOR.to_string());
+        if deduped == current {
+            return Ok(());
+        }
+        self.set(var, &deduped)
+    }
+
+    /// Moves whatever is persisted for `from` over to `to`.
+    /// It's not an error if there's nothing.
+    /// By default this copies the value from [`get`](EnvBackend::get)
+    /// and then [`remove`](EnvBackend::remove)s
+    /// the old name.
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        match self.get(from)? {
+            Some(value) => {
+                self.set(to, &value)?;
+                self.remove(from)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Every persisted variable and its value.
+    /// By default this fails as unsupported.
+    fn list(&self) -> io::Result<Vec<(String, String)>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "This backend can't list its variables",
+        ))
+    }
+
+    /// Whether `entry` is already persisted as part of
+    /// the list in `var`.
+    /// By default this splits what [`get`](EnvBackend::get) returns.
+    fn contains(&self, var: &str, entry: &str) -> io::Result<bool> {
+        Ok(self
+            .get(var)?
+            .is_some_and(|value| value.split(PATH_SEPARATOR).any(|part| part == entry)))
+    }
+}
+
+impl<B: EnvBackend + ?Sized> EnvBackend for Arc<B> {
+    fn set(&self, var: &str, value: &str) -> io::Result<()> {
+        (**self).set(var, value)
+    }
+
+    fn append(&self, var: &str, value: &str) -> io::Result<()> {
+        (**self).append(var, value)
+    }
+
+    fn set_many(&self, vars: &[(String, String)]) -> io::Result<()> {
+        (**self).set_many(vars)
+    }
+
+    fn prepend(&self, var: &str, value: &str) -> io::Result<()> {
+        (**self).prepend(var, value)
+    }
+
+    fn set_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        (**self).set_os(var, value)
+    }
+
+    fn append_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        (**self).append_os(var, value)
+    }
+
+    fn prepend_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        (**self).prepend_os(var, value)
+    }
+
+    fn append_with(&self, var: &str, value: &str, separator: char) -> io::Result<()> {
+        (**self).append_with(var, value, separator)
+    }
+
+    fn append_with_os(&self, var: &str, value: &OsStr, separator: char) -> io::Result<()> {
+        (**self).append_with_os(var, value, separator)
+    }
+
+    fn update(&self, var: &str, value: &str) -> io::Result<()> {
+        (**self).update(var, value)
+    }
+
+    fn remove(&self, var: &str) -> io::Result<()> {
+        (**self).remove(var)
+    }
+
+    fn remove_entry(&self, var: &str, entry: &str) -> io::Result<()> {
+        (**self).remove_entry(var, entry)
+    }
+
+    fn dedupe(&self, var: &str) -> io::Result<()> {
+        (**self).dedupe(var)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        (**self).rename(from, to)
+    }
+
+    fn get(&self, var: &str) -> io::Result<Option<String>> {
+        (**self).get(var)
+    }
+
+    fn list(&self) -> io::Result<Vec<(String, String)>> {
+        (**self).list()
+    }
+
+    fn contains(&self, var: &str, entry: &str) -> io::Result<bool> {
+        (**self).contains(var, entry)
+    }
+}
+
+/// How a variable gets persisted.
+///
+/// By default this is worked out with [`Backend::detect`]
+/// but you can force one with [`Config::backend`](crate::Config::backend).
+/// ```no_run
+/// use env_perm::{Backend, Config, Shell};
+///
+/// // export DUMMY=1 in ~/.profile
+/// Config::new()
+///     .backend(Backend::Profile(Shell::Sh))
+///     .set("DUMMY", 1)
+///     .expect("Failed to set DUMMY");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Backend {
+    /// Lines appended to a shell's startup files.
+    /// `Profile(Shell::Sh)` is plain POSIX `~/.profile`.
+    Profile(Shell),
+    /// The user's environment in the windows registry,
+    /// `HKCU\Environment`, written directly with
+    /// `RegSetValueEx`. Unlike setx values can be up to
+    /// 32,767 characters and no other program is run.
+    /// They're written as UTF-16 so accented and CJK text
+    /// comes back the same, whatever the console's
+    /// code page is. Like setx,
+    /// `WM_SETTINGCHANGE` is broadcast afterwards so
+    /// programs started from Explorer get the change.
+    /// Appends and prepends add to the value as it's
+    /// stored, with its `%VAR%` references left alone, not
+    /// the expanded `PATH` this process has with the
+    /// machine's entries in it.
+    /// ```
+    /// use env_perm::{Backend, Config};
+    ///
+    /// if cfg!(windows) {
+    ///     let config = Config::new().backend(Backend::WindowsRegistry);
+    ///     for value in ["Crème brûlée", "東京都", "Ελληνικά"] {
+    ///         config.set("ENV_PERM_UNICODE", value).unwrap();
+    ///         assert_eq!(config.get_persistent("ENV_PERM_UNICODE").unwrap().as_deref(), Some(value));
+    ///     }
+    ///     config.unset("ENV_PERM_UNICODE").unwrap();
+    /// }
+    /// ```
+    WindowsRegistry,
+    /// The user's environment in the windows registry
+    /// written with `setx.exe`, or the machine's with
+    /// `setx /M` and [`Scope::Machine`](crate::Scope::Machine)
+    /// when the process is elevated.
+    /// Values longer than 1024 characters fail with a
+    /// [`TooLong`](crate::TooLong) rather than being
+    /// truncated.
+    /// Appends and prepends work like the registry
+    /// backend's, and go straight to the registry when
+    /// the value is a `REG_EXPAND_SZ` setx would lose.
+    /// Where there's no `setx.exe`, like on Nano Server,
+    /// values are written to the registry directly. So are
+    /// values setx's command line would change, ones with
+    /// `%`, `^`, `&`, `|`, `<`, `>` or `"` in them, starting
+    /// with `/` or ending with `\`, and ones that aren't
+    /// ASCII, which go in as UTF-16 rather than through
+    /// the console's code page.
+    /// ```
+    /// use env_perm::{Backend, Config};
+    ///
+    /// if cfg!(windows) {
+    ///     let config = Config::new().backend(Backend::WindowsSetx);
+    ///     for value in ["50%", "%NOT_A_VAR", "a^b&c|d", "<in> out", r"C:\Program Files\MyApp", r#"say "hi""#, "/M", "東京都"] {
+    ///         config.set("ENV_PERM_SETX", value).unwrap();
+    ///         assert_eq!(config.get_persistent("ENV_PERM_SETX").unwrap().as_deref(), Some(value));
+    ///     }
+    ///     config.unset("ENV_PERM_SETX").unwrap();
+    /// }
+    /// ```
+    WindowsSetx,
+    /// The `setenv` list in `~/.login_conf` on FreeBSD
+    /// and DragonFly, which applies to every login
+    /// whatever the shell.
+    LoginConf,
+    /// From inside WSL, the windows user's environment in
+    /// `HKCU\Environment`, written by running `powershell.exe`
+    /// through interop. Values keep their type like with
+    /// [`WindowsRegistry`](Backend::WindowsRegistry) and the
+    /// change is broadcast. The journal only has the linux
+    /// side, so undo and dry runs leave windows alone.
+    /// [`Config::wsl_interop`](crate::Config::wsl_interop)
+    /// writes with this as well as the profile.
+    WslInterop,
+}
+
+impl Backend {
+    /// The backend used when none is picked.
+    ///
+    /// On windows this is [`WindowsRegistry`](Backend::WindowsRegistry)
+    /// unless the user has set one of the [`Overrides`], everywhere
+    /// else it's the profile of [`Shell::detect`].
+    pub fn detect() -> Backend {
+        if cfg!(windows) && !Overrides::from_env().is_active() {
+            Backend::WindowsRegistry
+        } else {
+            Backend::Profile(Shell::detect())
+        }
+    }
+
+    /// The shell whose syntax this backend writes, if any.
+    pub fn shell(self) -> Option<Shell> {
+        match self {
+            Backend::Profile(shell) => Some(shell),
+            Backend::WindowsRegistry | Backend::WindowsSetx | Backend::LoginConf | Backend::WslInterop => None,
+        }
+    }
+}
+
+/// A `Backend` used directly writes with
+/// the defaults for everything else.
+impl EnvBackend for Backend {
+    fn set(&self, var: &str, value: &str) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.set(var, value)
+    }
+
+    fn append(&self, var: &str, value: &str) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.append(var, value)
+    }
+
+    fn set_many(&self, vars: &[(String, String)]) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.set_many(vars)
+    }
+
+    fn prepend(&self, var: &str, value: &str) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.prepend(var, value)
+    }
+
+    fn set_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.set_os(var, value)
+    }
+
+    fn append_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.append_os(var, value)
+    }
+
+    fn prepend_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.prepend_os(var, value)
+    }
+
+    fn append_with(&self, var: &str, value: &str, separator: char) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.append_with(var, value, separator)
+    }
+
+    fn append_with_os(&self, var: &str, value: &OsStr, separator: char) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.append_with_os(var, value, separator)
+    }
+
+    fn update(&self, var: &str, value: &str) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.update(var, value)
+    }
+
+    fn remove(&self, var: &str) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.remove(var)
+    }
+
+    fn remove_entry(&self, var: &str, entry: &str) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.remove_entry(var, entry)
+    }
+
+    fn dedupe(&self, var: &str) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.dedupe(var)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        Config::new().backend(*self).resolve()?.rename(from, to)
+    }
+
+    fn get(&self, var: &str) -> io::Result<Option<String>> {
+        Config::new().backend(*self).resolve()?.get(var)
+    }
+
+    fn list(&self) -> io::Result<Vec<(String, String)>> {
+        Config::new().backend(*self).resolve()?.list()
+    }
+
+    fn contains(&self, var: &str, entry: &str) -> io::Result<bool> {
+        Config::new().backend(*self).resolve()?.contains(var, entry)
+    }
+}
+
+/// `value` as a str, or an error saying
+/// `var` can't hold it if it isn't UTF-8.
+pub(crate) fn utf8<'a>(var: &str, value: &'a OsStr) -> io::Result<&'a str> {
+    value.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The value for {} isn't UTF-8 and this backend can only store text", var),
+        )
+    })
+}
+
+/// Writes to every backend in turn.
+/// Reads come from the first one that has a value.
+pub(crate) struct Multi(pub(crate) Vec<Box<dyn EnvBackend>>);
+
+impl EnvBackend for Multi {
+    fn set(&self, var: &str, value: &str) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.set(var, value))
+    }
+
+    fn append(&self, var: &str, value: &str) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.append(var, value))
+    }
+
+    fn set_many(&self, vars: &[(String, String)]) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.set_many(vars))
+    }
+
+    fn prepend(&self, var: &str, value: &str) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.prepend(var, value))
+    }
+
+    fn set_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.set_os(var, value))
+    }
+
+    fn append_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.append_os(var, value))
+    }
+
+    fn prepend_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.prepend_os(var, value))
+    }
+
+    fn append_with(&self, var: &str, value: &str, separator: char) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.append_with(var, value, separator))
+    }
+
+    fn append_with_os(&self, var: &str, value: &OsStr, separator: char) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.append_with_os(var, value, separator))
+    }
+
+    fn update(&self, var: &str, value: &str) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.update(var, value))
+    }
+
+    fn remove(&self, var: &str) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.remove(var))
+    }
+
+    fn remove_entry(&self, var: &str, entry: &str) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.remove_entry(var, entry))
+    }
+
+    fn dedupe(&self, var: &str) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.dedupe(var))
+    }
+
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        self.0.iter().try_for_each(|backend| backend.rename(from, to))
+    }
+
+    fn get(&self, var: &str) -> io::Result<Option<String>> {
+        for backend in &self.0 {
+            if let Some(value) = backend.get(var)? {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    // The first backend's value wins
+    // like it does for get
+    fn list(&self) -> io::Result<Vec<(String, String)>> {
+        let mut vars: Vec<(String, String)> = Vec::new();
+        for backend in &self.0 {
+            for (var, value) in backend.list()? {
+                if !vars.iter().any(|(name, _)| *name == var) {
+                    vars.push((var, value));
+                }
+            }
+        }
+        Ok(vars)
+    }
+
+    // Only true if every backend has it
+    // so the others get written too
+    fn contains(&self, var: &str, entry: &str) -> io::Result<bool> {
+        for backend in &self.0 {
+            if !backend.contains(var, entry)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
@@ -0,0 +1,1884 @@
+//! Options for how and where variables are written.
+
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::profile::{self, ProfileFiles, Writer};
+use crate::shell::{self, Shell, SEPARATORS};
+use crate::windows::{self, RegistryType, RegistryValue, Scope};
+use crate::app_conflict::{self, AppConflict};
+use crate::backend::Multi;
+use crate::confirm::{self, Decision, Hook, PendingChange};
+use crate::doctor::{Diagnosis, ProfileFile, RegistryAccess};
+use crate::dotfiles::DotfilePolicy;
+use crate::journal;
+use crate::lock;
+use crate::plan::{self, Plan};
+use crate::spec::{self, Difference, Spec, Verification, Wanted};
+use crate::provenance::{self, Provenance};
+use crate::report::{self, ChangeReport};
+use crate::snapshot::Snapshot;
+use crate::state::{self, State};
+use crate::tamper::{self, BlockStatus};
+use crate::validate::{Checked, Validator};
+use crate::login_conf::{self, LoginConf};
+use crate::pathext;
+use crate::wsl;
+use crate::wslenv;
+use crate::{Backend, EnvBackend, Outcome, Place, ToEnvValue};
+
+/// Which of a shell's startup files to write to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Target {
+    /// The login profile, e.g. `.bash_profile`.
+    /// This is the default.
+    #[default]
+    LoginProfile,
+    /// The file interactive non-login shells read,
+    /// e.g. `.bashrc` or `.zshrc`.
+    /// Many terminal emulators start shells this way.
+    InteractiveRc,
+    /// Both the login profile and the rc file.
+    Both,
+}
+
+/// Where variables go when running under Git Bash,
+/// MSYS2 or Cygwin on windows. See [`is_msys`](crate::is_msys).
+///
+/// This only applies when no backend was picked
+/// with [`Config::backend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MsysMode {
+    /// The windows registry, like any other windows program.
+    /// This is the default.
+    #[default]
+    Registry,
+    /// The unix style profile of the posix shell,
+    /// e.g. `~/.bash_profile`.
+    Profile,
+    /// Both the registry and the profile.
+    Both,
+}
+
+/// What [`Config::check_or_set`] looks at to decide
+/// whether a variable is already set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CheckMode {
+    /// The environment of this process.
+    /// This is the default.
+    #[default]
+    Environment,
+    /// What's persisted in the profile or registry,
+    /// ignoring anything only set for this session.
+    Persisted,
+    /// Either of them, so nothing is written if it's
+    /// set for this session or persisted already.
+    Either,
+}
+
+/// How values are quoted when they're written to a profile.
+///
+/// The windows registry has no shell reading it so values
+/// are stored verbatim and never quoted, and setx is
+/// given the value as one argument that's only quoted for
+/// its command line. Only with [`Prequoted`](Quoting::Prequoted)
+/// are quotes wrapping the whole value, which callers used
+/// to need for the profiles, taken off rather than stored.
+/// ```
+/// use env_perm::{Backend, Config, Quoting};
+///
+/// if cfg!(windows) {
+///     let config = Config::new().backend(Backend::WindowsRegistry);
+///     config.set("ENV_PERM_QUOTED", "\"/something\"").unwrap();
+///     assert_eq!(config.get_persistent("ENV_PERM_QUOTED").unwrap().as_deref(), Some("\"/something\""));
+///     let config = config.quoting(Quoting::Prequoted);
+///     config.set("ENV_PERM_QUOTED", "\"/something\"").unwrap();
+///     assert_eq!(config.get_persistent("ENV_PERM_QUOTED").unwrap().as_deref(), Some("/something"));
+///     config.unset("ENV_PERM_QUOTED").unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Quoting {
+    /// Written exactly as given so any
+    /// quoting is up to the caller.
+    None,
+    /// In double quotes with nothing escaped,
+    /// so the shell expands whatever it would in there.
+    Double,
+    /// In single quotes, or whatever the shell has
+    /// that's literal, so nothing expands.
+    Single,
+    /// Quoted only where needed and escaped so the value
+    /// comes out as written, except that references like
+    /// `$HOME` still expand. Quotes in the value are part
+    /// of it, even ones wrapped around the whole thing.
+    /// This is the default.
+    #[default]
+    ShellEscape,
+    /// Like [`ShellEscape`](Quoting::ShellEscape) except that
+    /// a value wrapped in quotes is taken to be quoted by the
+    /// caller, the way values had to be written before they
+    /// were escaped. It goes in profiles as it is and
+    /// the quotes are taken off for the windows registry.
+    Prequoted,
+}
+
+/// Overrides end users can set in the environment
+/// to redirect where variables are written.
+///
+/// `ENV_PERM_PROFILE` names a file to write to instead
+/// of the discovered profiles and `ENV_PERM_SHELL`
+/// names the shell whose syntax to use, e.g. `fish`.
+/// A shell or file passed in by the caller still wins.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Overrides {
+    /// The file from `ENV_PERM_PROFILE`.
+    pub profile: Option<PathBuf>,
+    /// The shell from `ENV_PERM_SHELL`.
+    /// This is `None` if it names a shell we don't know.
+    pub shell: Option<Shell>,
+}
+
+impl Overrides {
+    /// Reads the overrides from the environment.
+    pub fn from_env() -> Overrides {
+        Overrides {
+            profile: env::var_os("ENV_PERM_PROFILE")
+                .filter(|profile| !profile.is_empty())
+                .map(PathBuf::from),
+            shell: env::var_os("ENV_PERM_SHELL")
+                .and_then(|shell| Shell::from_path(Path::new(&shell))),
+        }
+    }
+
+    /// Whether either override is in effect.
+    pub fn is_active(&self) -> bool {
+        self.profile.is_some() || self.shell.is_some()
+    }
+}
+
+/// Options for where and how variables are written.
+///
+/// The free functions like [`set`](crate::set) use
+/// the defaults, build one of these to change them.
+/// ```no_run
+/// use env_perm::{Config, Shell, Target};
+///
+/// // export DUMMY=1 in .zshrc
+/// Config::new()
+///     .shell(Shell::Zsh)
+///     .target(Target::InteractiveRc)
+///     .set("DUMMY", 1)
+///     .expect("Failed to set DUMMY");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    backend: Option<Chosen>,
+    target: Target,
+    msys: MsysMode,
+    strict_posix: bool,
+    dotfiles: DotfilePolicy,
+    check: CheckMode,
+    quoting: Quoting,
+    app: Option<String>,
+    backups: Option<usize>,
+    dry_run: bool,
+    annotate: bool,
+    report: Option<Arc<Mutex<ChangeReport>>>,
+    before_write: Option<Hook>,
+    validators: Vec<Validator>,
+    app_conflicts: bool,
+    scope: Scope,
+    elevate: bool,
+    registry_type: RegistryType,
+    wsl_interop: bool,
+}
+
+#[derive(Clone)]
+enum Chosen {
+    Builtin(Backend),
+    Custom(Arc<dyn EnvBackend + Send + Sync>),
+}
+
+impl fmt::Debug for Chosen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chosen::Builtin(backend) => backend.fmt(f),
+            Chosen::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl Config {
+    /// The default config which writes with
+    /// the backend from [`Backend::detect`].
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    /// Write to the profile of this shell
+    /// instead of the detected one.
+    /// This is the same as `backend(Backend::Profile(shell))`.
+    pub fn shell(self, shell: Shell) -> Config {
+        self.backend(Backend::Profile(shell))
+    }
+
+    /// Persist variables with this backend
+    /// instead of the detected one.
+    pub fn backend(mut self, backend: Backend) -> Config {
+        self.backend = Some(Chosen::Builtin(backend));
+        self
+    }
+
+    /// Write the windows registry variables of this
+    /// [`Scope`] instead of the current user's.
+    /// Machine scope needs the process to be elevated
+    /// and only the registry backend can write it.
+    pub fn scope(mut self, scope: Scope) -> Config {
+        self.scope = scope;
+        self
+    }
+
+    /// Write registry values as this type. By default
+    /// each keeps the type it has, so appending to a
+    /// `REG_EXPAND_SZ` `PATH` doesn't turn it into a
+    /// `REG_SZ` and break its `%SystemRoot%` entries.
+    /// ```no_run
+    /// use env_perm::{Backend, Config, RegistryType};
+    ///
+    /// Config::new()
+    ///     .backend(Backend::WindowsRegistry)
+    ///     .registry_type(RegistryType::Expandable)
+    ///     .set("MYAPP_DATA", r"%LOCALAPPDATA%\MyApp")
+    ///     .expect("Failed to set MYAPP_DATA");
+    /// ```
+    pub fn registry_type(mut self, kind: RegistryType) -> Config {
+        self.registry_type = kind;
+        self
+    }
+
+    /// With [`Scope::Machine`] in a process that isn't
+    /// elevated, asks the user with UAC to make each
+    /// change's registry writes as administrator instead of
+    /// failing with [`io::ErrorKind::PermissionDenied`].
+    /// It's the same as making a [`plan`](Config::plan) of
+    /// the change and [`Plan::apply_elevated`], so the
+    /// user is asked once for a whole
+    /// [`apply_spec`](Config::apply_spec). Off by default.
+    pub fn elevate(mut self, elevate: bool) -> Config {
+        self.elevate = elevate;
+        self
+    }
+
+    /// Persist variables with your own [`EnvBackend`]
+    /// instead of one of the built in ones.
+    pub fn custom_backend<B: EnvBackend + Send + Sync + 'static>(mut self, backend: B) -> Config {
+        self.backend = Some(Chosen::Custom(Arc::new(backend)));
+        self
+    }
+
+    /// Choose which startup files to write to.
+    /// This only affects profile backends.
+    pub fn target(mut self, target: Target) -> Config {
+        self.target = target;
+        self
+    }
+
+    /// Write `FOO=bar; export FOO` instead of
+    /// `export FOO=bar` for the posix shells,
+    /// for very old `/bin/sh`s that can't do both at once.
+    /// ```
+    /// use env_perm::{Config, Shell};
+    ///
+    /// let path = std::env::temp_dir().join("env_perm_strict_posix");
+    /// # let _ = std::fs::remove_file(&path);
+    /// Config::new()
+    ///     .shell(Shell::Sh)
+    ///     .strict_posix(true)
+    ///     .set_in(&path, "DUMMY", 1)
+    ///     .expect("Failed to set DUMMY");
+    /// let profile = std::fs::read_to_string(&path).unwrap();
+    /// assert_eq!(profile, "\nDUMMY=1; export DUMMY\n");
+    /// ```
+    pub fn strict_posix(mut self, strict: bool) -> Config {
+        self.strict_posix = strict;
+        self
+    }
+
+    /// Choose where variables go under Git Bash, MSYS2
+    /// or Cygwin on windows.
+    pub fn msys(mut self, mode: MsysMode) -> Config {
+        self.msys = mode;
+        self
+    }
+
+    /// Inside WSL, also write each variable to the windows
+    /// side with [`Backend::WslInterop`], so one call
+    /// persists it for both. Outside WSL this does nothing.
+    /// ```no_run
+    /// use env_perm::Config;
+    ///
+    /// Config::new()
+    ///     .wsl_interop(true)
+    ///     .set("MYAPP_TOKEN_FILE", "~/.myapp/token")
+    ///     .expect("Failed to set MYAPP_TOKEN_FILE");
+    /// ```
+    pub fn wsl_interop(mut self, interop: bool) -> Config {
+        self.wsl_interop = interop;
+        self
+    }
+
+    /// Choose what happens when the profile is
+    /// managed by stow, chezmoi or yadm.
+    /// ```no_run
+    /// use env_perm::{Config, DotfilePolicy};
+    ///
+    /// // Put it in ~/.local/share/chezmoi/dot_bash_profile
+    /// // rather than the copy chezmoi will overwrite
+    /// Config::new()
+    ///     .dotfiles(DotfilePolicy::WriteSource)
+    ///     .set("DUMMY", 1)
+    ///     .expect("Failed to set DUMMY");
+    /// ```
+    pub fn dotfiles(mut self, policy: DotfilePolicy) -> Config {
+        self.dotfiles = policy;
+        self
+    }
+
+    /// Choose how [`check_or_set`](Config::check_or_set)
+    /// decides whether a variable is already set.
+    /// ```no_run
+    /// use env_perm::{CheckMode, Config};
+    ///
+    /// // Still writes DUMMY if it's only been
+    /// // exported in this session
+    /// Config::new()
+    ///     .check(CheckMode::Persisted)
+    ///     .check_or_set("DUMMY", 1)
+    ///     .expect("Failed to find or set DUMMY");
+    /// ```
+    pub fn check(mut self, mode: CheckMode) -> Config {
+        self.check = mode;
+        self
+    }
+
+    /// Choose how values are quoted in the profile.
+    /// The windows registry isn't read by a shell so
+    /// values go in verbatim, except that with
+    /// [`Quoting::Prequoted`] quotes wrapped around
+    /// the whole value are dropped like a shell would.
+    /// ```
+    /// use env_perm::{Config, Quoting, Shell};
+    ///
+    /// let path = std::env::temp_dir().join("env_perm_quoting.sh");
+    /// # let _ = std::fs::remove_file(&path);
+    /// Config::new()
+    ///     .shell(Shell::Bash)
+    ///     .quoting(Quoting::Single)
+    ///     .set_in(&path, "PRICE", "$5")
+    ///     .expect("Failed to set PRICE");
+    /// let profile = std::fs::read_to_string(&path).unwrap();
+    /// assert_eq!(profile, "\nexport PRICE='$5'\n");
+    /// ```
+    pub fn quoting(mut self, quoting: Quoting) -> Config {
+        self.quoting = quoting;
+        self
+    }
+
+    /// Keeps what's written to profiles in a block of
+    /// its own for `app`, between comments like
+    /// `# >>> env_perm (myapp) >>>` and `# <<< env_perm (myapp) <<<`,
+    /// instead of scattering lines through the file.
+    /// Once the block is there writing to it again replaces
+    /// what it set before and skips lines it has already,
+    /// so running an installer twice gives the same profile.
+    /// ```
+    /// use env_perm::Config;
+    ///
+    /// let path = std::env::temp_dir().join("env_perm_app.sh");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let config = Config::new().app("myapp");
+    /// config.set_in(&path, "MYAPP_HOME", "/opt/myapp").unwrap();
+    /// config.append_in(&path, "PATH", "/opt/myapp/bin").unwrap();
+    /// // Running it again changes nothing
+    /// config.set_in(&path, "MYAPP_HOME", "/opt/myapp").unwrap();
+    /// config.append_in(&path, "PATH", "/opt/myapp/bin").unwrap();
+    /// let profile = std::fs::read_to_string(&path).unwrap();
+    /// let lines: Vec<&str> = profile.lines().collect();
+    /// assert_eq!(lines, [
+    ///     "",
+    ///     "# >>> env_perm (myapp) >>>",
+    ///     "export MYAPP_HOME=/opt/myapp",
+    ///     "export PATH=\"$PATH:/opt/myapp/bin\"",
+    ///     "# <<< env_perm (myapp) <<<",
+    /// ]);
+    /// ```
+    pub fn app<T: fmt::Display>(mut self, app: T) -> Config {
+        self.app = Some(app.to_string());
+        self
+    }
+
+    /// How many backups of each profile to keep. Before a
+    /// profile is changed it's copied next to itself as
+    /// `<name>.env_perm.bak.<milliseconds>`, and backups past
+    /// the newest `backups` are deleted. The default is 5
+    /// and 0 turns backups off.
+    pub fn backups(mut self, backups: usize) -> Config {
+        self.backups = Some(backups);
+        self
+    }
+
+    /// Adds everything changes made with this config write
+    /// to `report`, for an installer to show when it's done.
+    /// Clones of the config add to the same one.
+    pub fn report(mut self, report: Arc<Mutex<ChangeReport>>) -> Config {
+        self.report = Some(report);
+        self
+    }
+
+    /// Asks `hook` before each write to a profile that adds or
+    /// changes lines, giving it the lines so the application
+    /// can show them and let the user approve the write, skip
+    /// it or put the lines in another file. Dry runs and
+    /// writes that only rewrite a whole file, like
+    /// [`restore`](Config::restore), don't ask.
+    /// ```
+    /// use env_perm::{Config, Decision};
+    ///
+    /// let path = std::env::temp_dir().join("env_perm_on_before_write.sh");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let config = Config::new().on_before_write(|change| {
+    ///     println!("About to add {:?} to {}", change.added, change.path.display());
+    ///     Decision::Skip
+    /// });
+    /// config.set_in(&path, "DUMMY", 1).unwrap();
+    /// assert!(!path.exists());
+    /// ```
+    pub fn on_before_write<F>(mut self, hook: F) -> Config
+    where F: Fn(&PendingChange) -> Decision + Send + Sync + 'static,
+    {
+        self.before_write = Some(Hook(Arc::new(hook)));
+        self
+    }
+
+    /// Runs `validator` on each variable and value before
+    /// it's written, with the backend it's for, e.g. to
+    /// enforce a policy on names or a length limit. If it
+    /// gives an error nothing is written and the change fails
+    /// with a [`Rejected`](crate::Rejected) saying why.
+    /// Every validator added has to pass. Appends and prepends
+    /// give it the entry being added, not the whole list.
+    /// ```
+    /// use env_perm::{Config, Rejected};
+    ///
+    /// let path = std::env::temp_dir().join("env_perm_validate.sh");
+    /// let config = Config::new().validate(|var, value, _| {
+    ///     if var.ends_with("_TOKEN") {
+    ///         return Err(format!("{} looks like a secret", var));
+    ///     }
+    ///     if value.len() > 1024 {
+    ///         return Err("values are limited to 1024 bytes".to_string());
+    ///     }
+    ///     Ok(())
+    /// });
+    /// let e = config.set_in(&path, "GITHUB_TOKEN", "abc").unwrap_err();
+    /// assert_eq!(Rejected::from_error(&e).unwrap().var, "GITHUB_TOKEN");
+    /// ```
+    pub fn validate<F>(mut self, validator: F) -> Config
+    where F: Fn(&str, &str, Option<Backend>) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.validators.push(Validator(Arc::new(validator)));
+        self
+    }
+
+    /// Puts a comment at the end of every line written to a
+    /// profile saying who wrote it and when, like
+    /// `export MYAPP_HOME=/opt/myapp # added by myapp 2024-05-01 via env_perm`
+    /// with the [`app`](Config::app) if there is one, so users can
+    /// tell where exports they don't recognise came from.
+    /// [`provenance`](Config::provenance) reads them back.
+    pub fn annotate(mut self, annotate: bool) -> Config {
+        self.annotate = annotate;
+        self
+    }
+
+    /// Checks if a environment variable is set.
+    /// If it is then nothing will happen.
+    /// If it's not then it will be added
+    /// to the profile.
+    /// How it checks is up to the [`CheckMode`].
+    pub fn check_or_set<T, U>(&self, var: T, value: U) -> io::Result<Outcome>
+    where T: fmt::Display + AsRef<OsStr>,
+          U: ToEnvValue,
+    {
+        self.run(|| lock::locked(|| {
+            if self.check != CheckMode::Persisted && env::var_os(&var).is_some() {
+                return Ok(Outcome::AlreadySet);
+            }
+            let (backend, outcome) = self.writer()?;
+            if self.check != CheckMode::Environment && backend.get(&var.to_string())?.is_some() {
+                return Ok(Outcome::AlreadySet);
+            }
+            backend.set_os(&var.to_string(), &value.to_env_os_value())?;
+            Ok(outcome)
+        }))
+    }
+
+    /// Appends a value to the end of an environment variable
+    /// On windows it's left out if the user's or the
+    /// machine's value already has it, giving
+    /// [`Outcome::AlreadyPresent`].
+    pub fn append<T: fmt::Display, U: ToEnvValue>(&self, var: T, value: U) -> io::Result<Outcome> {
+        self.run(|| lock::locked(|| {
+            let (var, value) = (var.to_string(), value.to_env_os_value());
+            if let Some(outcome) = self.in_registry(&var, &value)? {
+                return Ok(outcome);
+            }
+            let (backend, outcome) = self.writer()?;
+            backend.append_os(&var, &value)?;
+            Ok(outcome)
+        }))
+    }
+
+    /// Appends a value to an environment variable with
+    /// `separator` between the entries instead of `:`
+    /// (or `;` on windows).
+    /// It has to be one of `:`, `;`, `,`, `|` or a space
+    /// so the line can be read back.
+    ///
+    /// On windows a separator already at the end of the
+    /// value isn't doubled and no space is left before
+    /// the new entry, and it's left out if it's already
+    /// there like with [`append`](Config::append).
+    /// ```
+    /// use env_perm::{Backend, Config};
+    ///
+    /// if cfg!(windows) {
+    ///     let config = Config::new().backend(Backend::WindowsRegistry);
+    ///     config.append("ENV_PERM_LIST", r"C:\a").unwrap();
+    ///     assert_eq!(config.get_persistent("ENV_PERM_LIST").unwrap().as_deref(), Some(r"C:\a"));
+    ///     config.set("ENV_PERM_LIST", r"C:\a; ").unwrap();
+    ///     config.append("ENV_PERM_LIST", r"C:\b").unwrap();
+    ///     config.append_with("ENV_PERM_LIST", "c", ',').unwrap();
+    ///     assert_eq!(config.get_persistent("ENV_PERM_LIST").unwrap().as_deref(), Some(r"C:\a;C:\b,c"));
+    ///     config.unset("ENV_PERM_LIST").unwrap();
+    /// }
+    /// ```
+    pub fn append_with<T: fmt::Display, U: ToEnvValue>(&self, var: T, value: U, separator: char) -> io::Result<Outcome> {
+        self.run(|| lock::locked(|| {
+            if !SEPARATORS.contains(&separator) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} can't be used as a separator", separator),
+                ));
+            }
+            let (var, value) = (var.to_string(), value.to_env_os_value());
+            if let Some(outcome) = self.in_registry(&var, &value)? {
+                return Ok(outcome);
+            }
+            let (backend, outcome) = self.writer()?;
+            backend.append_with_os(&var, &value, separator)?;
+            Ok(outcome)
+        }))
+    }
+
+    /// Appends a value to an environment variable unless
+    /// it's already there, either in the environment of this
+    /// process or in what's persisted.
+    /// Running an installer twice won't add it twice.
+    pub fn append_unique<T: fmt::Display, U: ToEnvValue>(&self, var: T, value: U) -> io::Result<Outcome> {
+        self.run(|| lock::locked(|| {
+            let (var, value) = (var.to_string(), value.to_env_value());
+            if let Some(outcome) = self.in_registry(&var, OsStr::new(&value))? {
+                return Ok(outcome);
+            }
+            if in_environment(&var, &value) {
+                return Ok(Outcome::AlreadySet);
+            }
+            let (backend, outcome) = self.writer()?;
+            if backend.contains(&var, &value)? {
+                return Ok(Outcome::AlreadySet);
+            }
+            backend.append(&var, &value)?;
+            Ok(outcome)
+        }))
+    }
+
+    /// Prepends a value to the front of an environment variable
+    /// On windows it's left out if it's already there
+    /// like with [`append`](Config::append).
+    pub fn prepend<T: fmt::Display, U: ToEnvValue>(&self, var: T, value: U) -> io::Result<Outcome> {
+        self.run(|| lock::locked(|| {
+            let (var, value) = (var.to_string(), value.to_env_os_value());
+            if let Some(outcome) = self.in_registry(&var, &value)? {
+                return Ok(outcome);
+            }
+            let (backend, outcome) = self.writer()?;
+            backend.prepend_os(&var, &value)?;
+            Ok(outcome)
+        }))
+    }
+
+    /// Sets an environment variable without checking
+    /// if it exists.
+    /// [`Outcome::previous`] is what was persisted before.
+    pub fn set<T: fmt::Display, U: ToEnvValue>(&self, var: T, value: U) -> io::Result<Outcome> {
+        self.run(|| lock::locked(|| {
+            let var = var.to_string();
+            let (backend, outcome) = self.writer()?;
+            let old = backend.get(&var)?;
+            backend.set_os(&var, &value.to_env_os_value())?;
+            Ok(outcome.replacing(old))
+        }))
+    }
+
+    /// Like [`set`](Config::set) for a name that's an `OsStr`,
+    /// e.g. one from [`std::env::vars_os`].
+    /// Every backend stores names as text so
+    /// one that isn't UTF-8 is an error.
+    pub fn set_os<T: AsRef<OsStr>, U: ToEnvValue>(&self, var: T, value: U) -> io::Result<Outcome> {
+        self.set(name(var.as_ref())?, value)
+    }
+
+    /// Like [`append`](Config::append) for a name that's
+    /// an `OsStr`, see [`set_os`](Config::set_os).
+    pub fn append_os<T: AsRef<OsStr>, U: ToEnvValue>(&self, var: T, value: U) -> io::Result<Outcome> {
+        self.append(name(var.as_ref())?, value)
+    }
+
+    /// Like [`prepend`](Config::prepend) for a name that's
+    /// an `OsStr`, see [`set_os`](Config::set_os).
+    pub fn prepend_os<T: AsRef<OsStr>, U: ToEnvValue>(&self, var: T, value: U) -> io::Result<Outcome> {
+        self.prepend(name(var.as_ref())?, value)
+    }
+
+    /// Persists the value `var` has in this process's
+    /// environment, so new shells start with it too.
+    /// It's a [`NotFound`](io::ErrorKind::NotFound)
+    /// error if `var` isn't set.
+    pub fn persist_current<T: AsRef<OsStr>>(&self, var: T) -> io::Result<Outcome> {
+        let var = var.as_ref();
+        match env::var_os(var) {
+            Some(value) => self.set_os(var, value),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} isn't set", var.to_string_lossy()),
+            )),
+        }
+    }
+
+    /// Persists every variable in this process's environment
+    /// whose name starts with `prefix` and gives their names.
+    /// An empty prefix is an error rather than
+    /// persisting everything.
+    pub fn persist_matching(&self, prefix: &str) -> io::Result<Vec<String>> {
+        self.run_quietly(|| {
+            if prefix.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "The prefix can't be empty"));
+            }
+            let mut vars: Vec<(String, OsString)> = env::vars_os()
+                .filter_map(|(var, value)| Some((var.into_string().ok()?, value)))
+                .filter(|(var, _)| var.starts_with(prefix))
+                .collect();
+            vars.sort();
+            let backend = self.resolve()?;
+            // The UTF-8 ones go in one write
+            let (text, other): (Vec<_>, Vec<_>) = vars.iter().partition(|(_, value)| value.to_str().is_some());
+            backend.set_many(&pairs(text.into_iter().map(|(var, value)| (var, value.to_string_lossy()))))?;
+            for (var, value) in other {
+                backend.set_os(var, value)?;
+            }
+            Ok(vars.into_iter().map(|(var, _)| var).collect())
+        })
+    }
+
+    /// The value of an environment variable that's
+    /// persisted in the profile or the registry,
+    /// which can differ from what this process has.
+    pub fn get_persistent<T: fmt::Display>(&self, var: T) -> io::Result<Option<String>> {
+        self.resolve()?.get(&var.to_string())
+    }
+
+    /// Every variable persisted in the profile
+    /// or the registry and its value.
+    pub fn list_persistent(&self) -> io::Result<Vec<(String, String)>> {
+        self.resolve()?.list()
+    }
+
+    /// Every variable in the registry for the config's
+    /// [`scope`](Config::scope), `HKCU` or `HKLM`, with whether
+    /// it's a `REG_SZ` or a `REG_EXPAND_SZ`. Whatever the
+    /// backend it reads the registry, so off windows it's an
+    /// [`Unsupported`](io::ErrorKind::Unsupported) error.
+    /// ```
+    /// use env_perm::{Config, RegistryType, Scope};
+    ///
+    /// if cfg!(windows) {
+    ///     let config = Config::new();
+    ///     config.set("ENV_PERM_TYPED", r"%USERPROFILE%\.typed").unwrap();
+    ///     let value = config.registry_value("ENV_PERM_TYPED").unwrap().unwrap();
+    ///     assert_eq!(value.kind, RegistryType::Expandable);
+    ///     assert_eq!(value.value, r"%USERPROFILE%\.typed");
+    ///     config.unset("ENV_PERM_TYPED").unwrap();
+    ///
+    ///     let machine = Config::new().scope(Scope::Machine).registry_values().unwrap();
+    ///     assert!(machine.iter().any(|value| value.var.eq_ignore_ascii_case("Path")));
+    /// } else {
+    ///     assert!(Config::new().registry_values().is_err());
+    /// }
+    /// ```
+    pub fn registry_values(&self) -> io::Result<Vec<RegistryValue>> {
+        windows::reg_values(self.scope)
+    }
+
+    /// The registry value for `var` in the config's
+    /// [`scope`](Config::scope) with its type, like
+    /// [`registry_values`](Config::registry_values).
+    pub fn registry_value<T: fmt::Display>(&self, var: T) -> io::Result<Option<RegistryValue>> {
+        let var = var.to_string();
+        Ok(self.registry_values()?.into_iter().find(|value| value.var.eq_ignore_ascii_case(&var)))
+    }
+
+    /// Every line in the profiles this config writes to
+    /// that has the comment [`annotate`](Config::annotate)
+    /// puts on it, saying who wrote it and when.
+    pub fn provenance(&self) -> io::Result<Vec<Provenance>> {
+        let mut found = Vec::new();
+        for backend in self.backends() {
+            if let Backend::Profile(shell) = backend {
+                for path in self.profiles(shell)? {
+                    found.extend(provenance::read(shell, &path)?);
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Whether an environment variable is persisted
+    /// in the profile or the registry, whatever this
+    /// process has in its environment.
+    pub fn exists_persistent<T: fmt::Display>(&self, var: T) -> io::Result<bool> {
+        Ok(self.get_persistent(var)?.is_some())
+    }
+
+    /// Takes one entry out of a list like PATH,
+    /// whether it was added with [`append`](Config::append)
+    /// or is part of a plain assignment.
+    pub fn remove_from<T: fmt::Display, U: ToEnvValue>(&self, var: T, value: U) -> io::Result<()> {
+        self.run_quietly(|| {
+            self.resolve()?.remove_entry(&var.to_string(), &value.to_env_value())
+        })
+    }
+
+    /// Rewrites a list like PATH without the repeats
+    /// that installing something several times leaves,
+    /// keeping the first copy of each entry.
+    pub fn dedupe<T: fmt::Display>(&self, var: T) -> io::Result<()> {
+        self.run_quietly(|| {
+            self.resolve()?.dedupe(&var.to_string())
+        })
+    }
+
+    /// Moves a persisted variable to a new name,
+    /// keeping its value.
+    pub fn rename<T: fmt::Display, U: fmt::Display>(&self, from: T, to: U) -> io::Result<()> {
+        self.run_quietly(|| {
+            self.resolve()?.rename(&from.to_string(), &to.to_string())
+        })
+    }
+
+    /// Sets an environment variable, rewriting the
+    /// existing assignment in the profile if there is one
+    /// rather than adding a second one after it.
+    /// [`Outcome::previous`] is what was persisted before.
+    pub fn update<T: fmt::Display, U: ToEnvValue>(&self, var: T, value: U) -> io::Result<Outcome> {
+        self.run(|| lock::locked(|| {
+            let var = var.to_string();
+            let (backend, outcome) = self.writer()?;
+            let old = backend.get(&var)?;
+            backend.update(&var, &value.to_env_value())?;
+            Ok(outcome.replacing(old))
+        }))
+    }
+
+    /// Removes every assignment to an environment
+    /// variable from the profile, or deletes it
+    /// from the registry on windows. That's the user's
+    /// value, or the machine's with [`Scope::Machine`],
+    /// and running programs are told like after a set.
+    pub fn unset<T: fmt::Display>(&self, var: T) -> io::Result<()> {
+        self.run_quietly(|| {
+            self.resolve()?.remove(&var.to_string())
+        })
+    }
+
+    /// Adds `entry` to `WSLENV` so the variable it names is
+    /// shared between windows and WSL, e.g. `GOPATH/l`.
+    /// The flags after the `/` are any of `p` to translate
+    /// a path, `l` for a list of paths, `u` for only windows
+    /// to WSL and `w` for only WSL to windows. An entry for
+    /// the same variable gets these flags instead of its own,
+    /// and if it already has them nothing is written.
+    /// On windows it's written to the registry, in WSL to
+    /// the profile. When `WSLENV` isn't set there yet it
+    /// starts from this process's value so the entries
+    /// already shared stay shared.
+    pub fn add_wslenv<T: fmt::Display>(&self, entry: T) -> io::Result<Outcome> {
+        self.run(|| {
+            let (backend, outcome) = self.writer()?;
+            lock::locked(|| add_wslenv(&*backend, outcome, &entry.to_string()))
+        })
+    }
+
+    /// Takes the entry for `var` out of `WSLENV`, and
+    /// deletes `WSLENV` if that was the only one.
+    pub fn remove_wslenv<T: fmt::Display>(&self, var: T) -> io::Result<()> {
+        self.run_quietly(|| {
+            let backend = self.resolve()?;
+            lock::locked(|| remove_wslenv(&*backend, &var.to_string()))
+        })
+    }
+
+    /// Adds `extension`, like `.LUA`, to the end of `PATHEXT`
+    /// so windows runs those files without the extension
+    /// being typed. It's written in upper case with `;`s and
+    /// isn't added again in any case. When `PATHEXT` isn't
+    /// set yet it starts from this process's value, since a
+    /// user's `PATHEXT` replaces the machine's rather than
+    /// adding to it like `PATH` does.
+    pub fn add_pathext<T: fmt::Display>(&self, extension: T) -> io::Result<Outcome> {
+        self.run(|| {
+            let (backend, outcome) = self.writer()?;
+            lock::locked(|| add_pathext(&*backend, outcome, &extension.to_string()))
+        })
+    }
+
+    /// Takes `extension` out of `PATHEXT` in any case,
+    /// and deletes `PATHEXT` if that was the only one.
+    pub fn remove_pathext<T: fmt::Display>(&self, extension: T) -> io::Result<()> {
+        self.run_quietly(|| {
+            let backend = self.resolve()?;
+            lock::locked(|| remove_pathext(&*backend, &extension.to_string()))
+        })
+    }
+
+    /// Adds `line` to the profile unless it's already there,
+    /// for things other than variables like
+    /// `eval "$(tool init bash)"`. It's written as it is
+    /// so it has to be in the profile's shell's syntax.
+    /// Only profiles hold lines, if variables go somewhere
+    /// else it's an [`Unsupported`](io::ErrorKind::Unsupported) error.
+    pub fn ensure_line<T: fmt::Display>(&self, line: T) -> io::Result<Outcome> {
+        let line = line.to_string();
+        self.ensure(|_| line.clone())
+    }
+
+    /// Makes the profile run the script at `path` with
+    /// `.` or the shell's `source`, unless it does already.
+    /// Tools can keep their variables in a file of their
+    /// own that way and only touch the profile once.
+    /// Like [`ensure_line`](Config::ensure_line) it's
+    /// [`Unsupported`](io::ErrorKind::Unsupported) without a profile.
+    pub fn source_file<P: AsRef<Path>>(&self, path: P) -> io::Result<Outcome> {
+        let path = path_text(path.as_ref())?;
+        self.ensure(|shell| shell.source_line(path))
+    }
+
+    // Adds the line `line` gives for each profile's
+    // shell to the profiles that don't have it
+    fn ensure<F: Fn(Shell) -> String>(&self, line: F) -> io::Result<Outcome> {
+        self.run(|| {
+            let mut paths = Vec::new();
+            for backend in self.backends() {
+                if let Backend::Profile(shell) = backend {
+                    paths.extend(self.profiles(shell)?.into_iter().map(|path| (shell, path)));
+                }
+            }
+            if paths.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Lines can only be added to profiles",
+                ));
+            }
+            let mut written = Vec::new();
+            for (shell, path) in paths {
+                if profile::ensure_line(&path, &line(shell), self.file_writer())? {
+                    written.push(path);
+                }
+            }
+            Ok(if written.is_empty() { Outcome::AlreadySet } else { Outcome::added(&written) })
+        })
+    }
+
+    /// Compares what `spec` wants with what's persisted
+    /// where this config reads from: variables that aren't
+    /// there or have another value, list entries that
+    /// are missing, and entries in those lists that the
+    /// spec doesn't name.
+    pub fn verify(&self, spec: &Spec) -> io::Result<Verification> {
+        let mut verification = Verification::default();
+        for config in spec.configs(self) {
+            config.verify_one(spec, &mut verification)?;
+        }
+        Ok(verification)
+    }
+
+    // Adds how what this config reads differs from
+    // `spec` to `verification`, leaving out repeats
+    fn verify_one(&self, spec: &Spec, verification: &mut Verification) -> io::Result<()> {
+        fn add<T: PartialEq>(list: &mut Vec<T>, item: T) {
+            if !list.contains(&item) {
+                list.push(item);
+            }
+        }
+        let backend = self.resolve()?;
+        let mut lists: Vec<&str> = Vec::new();
+        for wanted in &spec.wanted {
+            match wanted {
+                Wanted::Set(var, value) => match backend.get(var)? {
+                    None => add(&mut verification.missing, (var.clone(), value.clone())),
+                    Some(actual) if actual != *value => add(
+                        &mut verification.differing,
+                        Difference { var: var.clone(), wanted: value.clone(), actual },
+                    ),
+                    Some(_) => {}
+                },
+                Wanted::Append(var, entry) | Wanted::Prepend(var, entry) => {
+                    if !backend.contains(var, entry)? {
+                        add(&mut verification.missing, (var.clone(), entry.clone()));
+                    }
+                    if !lists.contains(&var.as_str()) {
+                        lists.push(var);
+                    }
+                }
+            }
+        }
+        for var in lists {
+            let wanted = spec.entries(var);
+            if let Some(value) = backend.get(var)? {
+                for entry in spec::list_entries(var, &value).into_iter().filter(|entry| !wanted.contains(entry)) {
+                    add(&mut verification.extra, (var.to_string(), entry.to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes whatever of `spec` isn't persisted yet, so
+    /// applying it again changes nothing. Variables with
+    /// another value are [`update`](Config::update)d and list
+    /// entries that are missing are appended or prepended.
+    /// The spec's shells, target and app take the
+    /// place of this config's.
+    pub fn apply_spec(&self, spec: &Spec) -> io::Result<Outcome> {
+        self.run(|| lock::locked(|| {
+            let mut outcomes = Vec::new();
+            for config in spec.configs(self) {
+                let backend = config.resolve()?;
+                for wanted in &spec.wanted {
+                    let outcome = match wanted {
+                        Wanted::Set(var, value) if backend.get(var)?.as_ref() == Some(value) => Outcome::AlreadySet,
+                        Wanted::Set(var, value) => config.update(var, value)?,
+                        Wanted::Append(var, entry) | Wanted::Prepend(var, entry) if backend.contains(var, entry)? => {
+                            Outcome::AlreadySet
+                        }
+                        Wanted::Append(var, entry) => config.append(var, entry)?,
+                        Wanted::Prepend(var, entry) => config.prepend(var, entry)?,
+                    };
+                    if outcome.changed() {
+                        outcomes.push(outcome);
+                    }
+                }
+            }
+            Ok(match outcomes.len() {
+                0 => Outcome::AlreadySet,
+                1 => outcomes.remove(0),
+                _ => Outcome::Several(outcomes),
+            })
+        }))
+    }
+
+    /// Looks at where this config would write: the shell,
+    /// the backends, each profile the shells read and
+    /// whether it exists, can be written or is a symlink,
+    /// and whether the registry can be read on windows.
+    /// Anything that stops a write working is in
+    /// [`Diagnosis::problems`] rather than an error.
+    pub fn doctor(&self) -> Diagnosis {
+        let backends = self.backends();
+        let mut profiles: Vec<ProfileFile> = Vec::new();
+        let mut writes_to = Vec::new();
+        let mut problems = Vec::new();
+        for backend in &backends {
+            match backend {
+                Backend::Profile(shell) => {
+                    match shell::home_dir() {
+                        Ok(home) => {
+                            for path in shell.profiles(&home).into_iter().chain(shell.rc_profiles(&home)) {
+                                if !profiles.iter().any(|profile| profile.path == path) {
+                                    profiles.push(ProfileFile::new(path));
+                                }
+                            }
+                        }
+                        Err(e) => problems.push(e.to_string()),
+                    }
+                    match self.profiles(*shell) {
+                        Ok(paths) => writes_to.extend(paths),
+                        Err(e) => problems.push(e.to_string()),
+                    }
+                }
+                Backend::LoginConf => match login_conf::path() {
+                    Ok(path) => writes_to.push(path),
+                    Err(e) => problems.push(e.to_string()),
+                },
+                Backend::WindowsRegistry | Backend::WindowsSetx | Backend::WslInterop => {}
+            }
+        }
+        for path in &writes_to {
+            if !ProfileFile::new(path.clone()).writable {
+                problems.push(format!("{} can't be written to", path.display()));
+            }
+        }
+        let registry = match windows::reg_get(self.scope, "PATH") {
+            Ok(_) => RegistryAccess::Readable,
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => RegistryAccess::Unavailable,
+            Err(e) => RegistryAccess::Failed(e.to_string()),
+        };
+        if let RegistryAccess::Failed(e) = &registry {
+            if self.registry_key().is_some() {
+                problems.push(format!("The registry can't be read: {}", e));
+            }
+        }
+        Diagnosis { shell: Shell::detect(), backends, profiles, writes_to, registry, problems }
+    }
+
+    /// Everything the journal says env_perm has persisted,
+    /// with each variable's value read from where it was
+    /// written. Files are read in the syntax this config
+    /// would write them in. Variables that have been taken
+    /// out since have no value.
+    pub fn export_state(&self) -> io::Result<State> {
+        state::export(self)
+    }
+
+    /// Writes what `state` has, e.g. from [`export_state`](Config::export_state)
+    /// on another machine, wherever this config writes, with
+    /// [`apply_spec`](Config::apply_spec) so what's already
+    /// persisted isn't written again. Each variable goes
+    /// in the block of the app that wrote it.
+    /// Lists like `PATH` are split into their entries, so a
+    /// `$PATH:/opt/bin` from a profile is appended with `;`
+    /// in the registry, and `$HOME` becomes `%USERPROFILE%`
+    /// and back, like other references, between a profile
+    /// and the registry. Variables with no value are left out.
+    pub fn import_state(&self, state: &State) -> io::Result<Outcome> {
+        self.run(|| {
+            let mut outcomes = Vec::new();
+            for spec in state::specs(state, self.registry_key().is_some()) {
+                match self.apply_spec(&spec)? {
+                    Outcome::Several(several) => outcomes.extend(several),
+                    outcome if outcome.changed() => outcomes.push(outcome),
+                    _ => {}
+                }
+            }
+            Ok(match outcomes.len() {
+                0 => Outcome::AlreadySet,
+                1 => outcomes.remove(0),
+                _ => Outcome::Several(outcomes),
+            })
+        })
+    }
+
+    /// Copies every profile the shells this config writes for
+    /// read, the files it writes to and every file in the
+    /// journal, and the registry environment if it writes
+    /// there, saving the copy to `path` as well.
+    /// [`restore`](Config::restore) puts it all back.
+    pub fn snapshot<P: AsRef<Path>>(&self, path: P) -> io::Result<Snapshot> {
+        let snapshot = Snapshot::take(self.snapshot_files()?, self.registry_key().is_some())?;
+        snapshot.save(path)?;
+        Ok(snapshot)
+    }
+
+    /// Puts every file and registry value in `snapshot` back
+    /// the way it was, deleting files and values that weren't
+    /// there. It's backed up and journaled like any other
+    /// write, so it can be undone too.
+    /// Gives the places that changed.
+    pub fn restore(&self, snapshot: &Snapshot) -> io::Result<Vec<Place>> {
+        self.run_quietly(|| snapshot.restore(self.file_writer()))
+    }
+
+    // Every file a snapshot has a copy of
+    fn snapshot_files(&self) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for backend in self.backends() {
+            match backend {
+                Backend::Profile(shell) => {
+                    let home = shell::home_dir()?;
+                    files.extend(shell.profiles(&home));
+                    files.extend(shell.rc_profiles(&home));
+                    files.extend(self.profiles(shell)?);
+                }
+                Backend::LoginConf => files.push(login_conf::path()?),
+                _ => {}
+            }
+        }
+        for change in journal::history()? {
+            if let Place::File(path) = change.place {
+                files.push(path);
+            }
+        }
+        let mut unique: Vec<PathBuf> = Vec::new();
+        for path in files {
+            if !unique.contains(&path) {
+                unique.push(path);
+            }
+        }
+        Ok(unique)
+    }
+
+    /// Removes everything `app` persisted with
+    /// [`Config::app`], as the journal records it: its blocks
+    /// in the profiles this config writes to and in any file
+    /// it wrote to, and the entries it added to registry values.
+    /// Gives the places that changed.
+    pub fn uninstall_all<T: fmt::Display>(&self, app: T) -> io::Result<Vec<Place>> {
+        self.run_quietly(|| {
+            let mut files = Vec::new();
+            for backend in self.backends() {
+                if let Backend::Profile(shell) = backend {
+                    files.extend(self.profiles(shell)?);
+                }
+            }
+            journal::uninstall(&app.to_string(), files, self.file_writer())
+        })
+    }
+
+    /// Whether `app`'s block in each profile this config
+    /// writes to is the way env_perm last wrote it or has
+    /// been edited by hand since, going by the checksum
+    /// the journal keeps of each block.
+    pub fn block_status<T: fmt::Display>(&self, app: T) -> io::Result<Vec<(PathBuf, BlockStatus)>> {
+        let app = app.to_string();
+        let mut statuses = Vec::new();
+        for backend in self.backends() {
+            if let Backend::Profile(shell) = backend {
+                for path in self.profiles(shell)? {
+                    let status = tamper::status(&path, &app)?;
+                    statuses.push((path, status));
+                }
+            }
+        }
+        Ok(statuses)
+    }
+
+    /// Like [`block_status`](Config::block_status)
+    /// but looks at `path` instead of the profile.
+    pub fn block_status_in<P: AsRef<Path>, T: fmt::Display>(&self, path: P, app: T) -> io::Result<BlockStatus> {
+        tamper::status(path.as_ref(), &app.to_string())
+    }
+
+    /// Every variable the blocks of more than one app assign
+    /// to in the profiles this config writes to, with what
+    /// each gives it, e.g. two tools both putting their
+    /// directory at the front of `PATH`.
+    pub fn app_conflicts(&self) -> io::Result<Vec<AppConflict>> {
+        let mut conflicts = Vec::new();
+        for backend in self.backends() {
+            if let Backend::Profile(shell) = backend {
+                for path in self.profiles(shell)? {
+                    conflicts.extend(app_conflict::find(shell, &path)?);
+                }
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// Like [`app_conflicts`](Config::app_conflicts)
+    /// but looks at `path` instead of the profile.
+    pub fn app_conflicts_in<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<AppConflict>> {
+        let path = path.as_ref();
+        app_conflict::find(self.file_shell(path), path)
+    }
+
+    /// Lets the [`app`](Config::app) set a variable another
+    /// app's block already sets to something else, rather
+    /// than failing with an [`AppConflict`]. Off by default.
+    pub fn allow_app_conflicts(mut self, allow: bool) -> Config {
+        self.app_conflicts = allow;
+        self
+    }
+
+    /// Makes every change a dry run: nothing is written to
+    /// files or the registry, but what would have been is
+    /// given as [`Outcome::Planned`] so an installer's
+    /// `--dry-run` can show it. Changes that don't give an
+    /// [`Outcome`] just don't happen.
+    /// ```
+    /// use env_perm::{Config, Outcome};
+    ///
+    /// let path = std::env::temp_dir().join("env_perm_dry_run.sh");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let outcome = Config::new().dry_run(true).set_in(&path, "DUMMY", 1).unwrap();
+    /// match outcome {
+    ///     Outcome::Planned(plan) => assert_eq!(plan.edits()[0].added(), ["export DUMMY=1"]),
+    ///     _ => panic!("{:?}", outcome),
+    /// }
+    /// assert!(!path.exists());
+    /// ```
+    pub fn dry_run(mut self, dry_run: bool) -> Config {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Works out what `f` would write with this config
+    /// without writing anything. Everything it writes to files
+    /// or the registry goes in the [`Plan`] instead, and
+    /// what it reads includes what it has written so far.
+    /// The plan records how each file looked so applying
+    /// it fails with a [`Conflict`](crate::Conflict) if one
+    /// has changed since.
+    pub fn plan<F: FnOnce(&Config) -> io::Result<()>>(&self, f: F) -> io::Result<Plan> {
+        Ok(plan::capture(|| f(self))?.1)
+    }
+
+    // Runs `f`, or in a dry run plans what it writes
+    // and gives that instead if it would change anything
+    fn run<F: FnOnce() -> io::Result<Outcome>>(&self, f: F) -> io::Result<Outcome> {
+        if !self.dry_run || plan::planning() {
+            return self.reported(|| self.elevated(f));
+        }
+        let (outcome, plan) = plan::capture(f)?;
+        Ok(if plan.is_empty() { outcome } else { Outcome::Planned(plan) })
+    }
+
+    // Like `run` for changes that don't give an
+    // Outcome, in a dry run nothing is written
+    fn run_quietly<T, F: FnOnce() -> io::Result<T>>(&self, f: F) -> io::Result<T> {
+        if !self.dry_run || plan::planning() {
+            return self.reported(|| self.elevated(f));
+        }
+        Ok(plan::capture(f)?.0)
+    }
+
+    // Runs `f`, or if it has to be elevated to write
+    // plans it and applies that as administrator
+    fn elevated<T, F: FnOnce() -> io::Result<T>>(&self, f: F) -> io::Result<T> {
+        if !self.elevate || self.scope != Scope::Machine || plan::planning() || windows::is_elevated() {
+            return f();
+        }
+        let (result, plan) = plan::capture(f)?;
+        plan.apply_elevated()?;
+        Ok(result)
+    }
+
+    // Runs `f`, adding what it writes to the report if
+    // there is one. Inside another change that's
+    // being reported it goes in that report.
+    fn reported<T, F: FnOnce() -> io::Result<T>>(&self, f: F) -> io::Result<T> {
+        let f = || confirm::with_hook(self.before_write.as_ref(), f);
+        let report = match &self.report {
+            Some(report) if !report::collecting() => report,
+            _ => return f(),
+        };
+        let (result, changes) = report::collect(f);
+        let mut report = report.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        for change in &changes {
+            report.add(change);
+        }
+        result
+    }
+
+    /// Puts each profile this config writes to back the way
+    /// it was before its last change, from the newest backup
+    /// [`Config::backups`] kept. That backup is used up, so
+    /// calling it again goes one change further back.
+    /// Gives the profiles that had a backup to restore.
+    pub fn restore_backup(&self) -> io::Result<Vec<PathBuf>> {
+        self.run_quietly(|| {
+            let mut restored = Vec::new();
+            for backend in self.backends() {
+                if let Backend::Profile(shell) = backend {
+                    for path in self.profiles(shell)? {
+                        if profile::restore_backup(&path, self.file_writer())? {
+                            restored.push(path);
+                        }
+                    }
+                }
+            }
+            Ok(restored)
+        })
+    }
+
+    /// Sets several environment variables at once.
+    /// Profiles are only opened and written once, and on
+    /// windows the registry values are all written before
+    /// one `WM_SETTINGCHANGE` is broadcast, even with setx.
+    pub fn set_many<I, T, U>(&self, vars: I) -> io::Result<()>
+    where I: IntoIterator<Item = (T, U)>,
+          T: fmt::Display,
+          U: ToEnvValue,
+    {
+        self.run_quietly(|| {
+            self.resolve()?.set_many(&pairs(vars))
+        })
+    }
+
+    /// Like [`check_or_set`](Config::check_or_set)
+    /// but writes to `path` instead of the profile.
+    pub fn check_or_set_in<P, T, U>(&self, path: P, var: T, value: U) -> io::Result<Outcome>
+    where P: AsRef<Path>,
+          T: fmt::Display + AsRef<OsStr>,
+          U: ToEnvValue,
+    {
+        self.run(|| lock::locked(|| {
+            if self.check != CheckMode::Persisted && env::var_os(&var).is_some() {
+                return Ok(Outcome::AlreadySet);
+            }
+            let (backend, paths) = self.checked_file(path.as_ref());
+            if self.check != CheckMode::Environment && backend.get(&var.to_string())?.is_some() {
+                return Ok(Outcome::AlreadySet);
+            }
+            backend.set_os(&var.to_string(), &value.to_env_os_value())?;
+            Ok(Outcome::added(&paths))
+        }))
+    }
+
+    /// Like [`append`](Config::append)
+    /// but writes to `path` instead of the profile.
+    pub fn append_in<P: AsRef<Path>, T: fmt::Display, U: ToEnvValue>(&self, path: P, var: T, value: U) -> io::Result<Outcome> {
+        self.run(|| {
+            let (backend, paths) = self.checked_file(path.as_ref());
+            backend.append_os(&var.to_string(), &value.to_env_os_value())?;
+            Ok(Outcome::added(&paths))
+        })
+    }
+
+    /// Like [`prepend`](Config::prepend)
+    /// but writes to `path` instead of the profile.
+    pub fn prepend_in<P: AsRef<Path>, T: fmt::Display, U: ToEnvValue>(&self, path: P, var: T, value: U) -> io::Result<Outcome> {
+        self.run(|| {
+            let (backend, paths) = self.checked_file(path.as_ref());
+            backend.prepend_os(&var.to_string(), &value.to_env_os_value())?;
+            Ok(Outcome::added(&paths))
+        })
+    }
+
+    /// Like [`ensure_line`](Config::ensure_line)
+    /// but writes to `path` instead of the profile.
+    pub fn ensure_line_in<P: AsRef<Path>, T: fmt::Display>(&self, path: P, line: T) -> io::Result<Outcome> {
+        self.run(|| {
+            let path = path.as_ref();
+            Ok(match profile::ensure_line(path, &line.to_string(), self.file_writer())? {
+                true => Outcome::Added { file: path.to_path_buf() },
+                false => Outcome::AlreadySet,
+            })
+        })
+    }
+
+    /// Like [`source_file`](Config::source_file)
+    /// but writes to `profile` instead of the profile.
+    pub fn source_file_in<P: AsRef<Path>, Q: AsRef<Path>>(&self, profile: P, path: Q) -> io::Result<Outcome> {
+        let line = self.file_shell(profile.as_ref()).source_line(path_text(path.as_ref())?);
+        self.ensure_line_in(profile, line)
+    }
+
+    /// Like [`restore_backup`](Config::restore_backup)
+    /// but restores `path` instead of the profile.
+    /// Gives whether it had a backup.
+    pub fn restore_backup_in<P: AsRef<Path>>(&self, path: P) -> io::Result<bool> {
+        self.run_quietly(|| {
+            profile::restore_backup(path.as_ref(), self.file_writer())
+        })
+    }
+
+    /// Like [`set_many`](Config::set_many)
+    /// but writes to `path` instead of the profile.
+    pub fn set_many_in<P, I, T, U>(&self, path: P, vars: I) -> io::Result<()>
+    where P: AsRef<Path>,
+          I: IntoIterator<Item = (T, U)>,
+          T: fmt::Display,
+          U: ToEnvValue,
+    {
+        self.run_quietly(|| {
+            self.checked_file(path.as_ref()).0.set_many(&pairs(vars))
+        })
+    }
+
+    /// Like [`set`](Config::set)
+    /// but writes to `path` instead of the profile.
+    pub fn set_in<P: AsRef<Path>, T: fmt::Display, U: ToEnvValue>(&self, path: P, var: T, value: U) -> io::Result<Outcome> {
+        self.run(|| lock::locked(|| {
+            let (var, (backend, paths)) = (var.to_string(), self.checked_file(path.as_ref()));
+            let old = backend.get(&var)?;
+            backend.set_os(&var, &value.to_env_os_value())?;
+            Ok(Outcome::added(&paths).replacing(old))
+        }))
+    }
+
+    /// Like [`update`](Config::update)
+    /// but edits `path` instead of the profile.
+    pub fn update_in<P: AsRef<Path>, T: fmt::Display, U: ToEnvValue>(&self, path: P, var: T, value: U) -> io::Result<Outcome> {
+        self.run(|| lock::locked(|| {
+            let (var, (backend, paths)) = (var.to_string(), self.checked_file(path.as_ref()));
+            let old = backend.get(&var)?;
+            backend.update(&var, &value.to_env_value())?;
+            Ok(Outcome::added(&paths).replacing(old))
+        }))
+    }
+
+    /// Like [`remove_from`](Config::remove_from)
+    /// but edits `path` instead of the profile.
+    pub fn remove_from_in<P: AsRef<Path>, T: fmt::Display, U: ToEnvValue>(&self, path: P, var: T, value: U) -> io::Result<()> {
+        self.run_quietly(|| {
+            self.file(path.as_ref()).remove_entry(&var.to_string(), &value.to_env_value())
+        })
+    }
+
+    /// Like [`dedupe`](Config::dedupe)
+    /// but edits `path` instead of the profile.
+    pub fn dedupe_in<P: AsRef<Path>, T: fmt::Display>(&self, path: P, var: T) -> io::Result<()> {
+        self.run_quietly(|| {
+            self.file(path.as_ref()).dedupe(&var.to_string())
+        })
+    }
+
+    /// Like [`rename`](Config::rename)
+    /// but edits `path` instead of the profile.
+    pub fn rename_in<P: AsRef<Path>, T: fmt::Display, U: fmt::Display>(&self, path: P, from: T, to: U) -> io::Result<()> {
+        self.run_quietly(|| {
+            self.checked_file(path.as_ref()).0.rename(&from.to_string(), &to.to_string())
+        })
+    }
+
+    /// Like [`unset`](Config::unset)
+    /// but edits `path` instead of the profile.
+    pub fn unset_in<P: AsRef<Path>, T: fmt::Display>(&self, path: P, var: T) -> io::Result<()> {
+        self.run_quietly(|| {
+            self.file(path.as_ref()).remove(&var.to_string())
+        })
+    }
+
+    /// Like [`add_wslenv`](Config::add_wslenv)
+    /// but edits `path` instead of the profile. It always
+    /// writes a profile-style `export WSLENV=...` line to `path`,
+    /// starting from this process's value if `path` doesn't
+    /// set `WSLENV` yet.
+    pub fn add_wslenv_in<P: AsRef<Path>, T: fmt::Display>(&self, path: P, entry: T) -> io::Result<Outcome> {
+        self.run(|| {
+            let (backend, paths) = self.checked_file(path.as_ref());
+            lock::locked(|| add_wslenv(&backend, Outcome::added(&paths), &entry.to_string()))
+        })
+    }
+
+    /// Like [`remove_wslenv`](Config::remove_wslenv)
+    /// but edits `path` instead of the profile, rewriting
+    /// the `export WSLENV=...` line there.
+    pub fn remove_wslenv_in<P: AsRef<Path>, T: fmt::Display>(&self, path: P, var: T) -> io::Result<()> {
+        self.run_quietly(|| {
+            lock::locked(|| remove_wslenv(&self.file(path.as_ref()), &var.to_string()))
+        })
+    }
+
+    /// Like [`add_pathext`](Config::add_pathext)
+    /// but edits `path` instead of the profile. It always
+    /// writes a profile-style `export PATHEXT=...` line to `path`,
+    /// starting from this process's value if `path` doesn't
+    /// set `PATHEXT` yet.
+    pub fn add_pathext_in<P: AsRef<Path>, T: fmt::Display>(&self, path: P, extension: T) -> io::Result<Outcome> {
+        self.run(|| {
+            let (backend, paths) = self.checked_file(path.as_ref());
+            lock::locked(|| add_pathext(&backend, Outcome::added(&paths), &extension.to_string()))
+        })
+    }
+
+    /// Like [`remove_pathext`](Config::remove_pathext)
+    /// but edits `path` instead of the profile, rewriting
+    /// the `export PATHEXT=...` line there.
+    pub fn remove_pathext_in<P: AsRef<Path>, T: fmt::Display>(&self, path: P, extension: T) -> io::Result<()> {
+        self.run_quietly(|| {
+            lock::locked(|| remove_pathext(&self.file(path.as_ref()), &extension.to_string()))
+        })
+    }
+
+    /// Like [`provenance`](Config::provenance)
+    /// but reads `path` instead of the profile.
+    pub fn provenance_in<P: AsRef<Path>>(&self, path: P) -> io::Result<Vec<Provenance>> {
+        let path = path.as_ref();
+        provenance::read(self.file_shell(path), path)
+    }
+
+    /// The file a [`set`](Config::set) would write to,
+    /// so installers can tell users before changing anything.
+    /// With [`Target::Both`] this is the login profile.
+    ///
+    /// Fails if variables go somewhere other than a file,
+    /// see [`registry_key`](Config::registry_key).
+    pub fn profile_path(&self) -> io::Result<PathBuf> {
+        for backend in self.backends() {
+            match backend {
+                Backend::Profile(shell) => return Ok(self.profiles(shell)?.remove(0)),
+                Backend::LoginConf => return login_conf::path(),
+                _ => {}
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Variables aren't written to a file",
+        ))
+    }
+
+    /// The registry key a [`set`](Config::set) would write to,
+    /// e.g. `HKCU\Environment`, or None if it doesn't
+    /// use the registry.
+    pub fn registry_key(&self) -> Option<&'static str> {
+        self.backends()
+            .into_iter()
+            .find(|backend| matches!(backend, Backend::WindowsRegistry | Backend::WindowsSetx))
+            .map(|_| self.scope.key())
+    }
+
+    /// The backend that does the writing.
+    pub(crate) fn resolve(&self) -> io::Result<Box<dyn EnvBackend>> {
+        Ok(self.writer()?.0)
+    }
+
+    // The backend and what writing with it does
+    fn writer(&self) -> io::Result<(Box<dyn EnvBackend>, Outcome)> {
+        if let Some(Chosen::Custom(backend)) = &self.backend {
+            let backend = self.checked(vec![None], Box::new(backend.clone()));
+            return Ok((Box::new(backend), Outcome::Custom { old: None }));
+        }
+        let mut backends = self.backends();
+        let checked = backends.iter().map(|backend| Some(*backend)).collect();
+        if backends.len() == 1 {
+            let (backend, outcome) = self.builtin(backends.remove(0))?;
+            return Ok((Box::new(self.checked(checked, backend)), outcome));
+        }
+        let (backends, outcomes) = backends
+            .into_iter()
+            .map(|backend| self.builtin(backend))
+            .collect::<io::Result<(Vec<_>, Vec<_>)>>()?;
+        Ok((Box::new(self.checked(checked, Box::new(Multi(backends)))), Outcome::Several(outcomes)))
+    }
+
+    // AlreadyPresent if we only write the registry and the
+    // user's or the machine's `var` already has `entry`
+    fn in_registry(&self, var: &str, entry: &OsStr) -> io::Result<Option<Outcome>> {
+        let entry = match entry.to_str() {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        if self.backends() != [Backend::WindowsRegistry] && self.backends() != [Backend::WindowsSetx] {
+            return Ok(None);
+        }
+        Ok(windows::has_entry(var, entry)?.map(|scope| Outcome::AlreadyPresent { key: scope.key().to_string() }))
+    }
+
+    // `backend` with this config's validators run
+    // for each of `backends` before it writes
+    fn checked<B: EnvBackend + ?Sized>(&self, backends: Vec<Option<Backend>>, backend: Box<B>) -> Checked<B> {
+        Checked { backends, validators: self.validators.clone(), inner: backend }
+    }
+
+    // `file` for a path we were given, checked
+    // by the validators too
+    fn checked_file(&self, path: &Path) -> (Checked<ProfileFiles>, Vec<PathBuf>) {
+        let backend = self.file(path);
+        let paths = backend.paths.clone();
+        (self.checked(vec![Some(Backend::Profile(backend.shell))], Box::new(backend)), paths)
+    }
+
+    // The built in backends we write with,
+    // nothing if a custom one was picked
+    fn backends(&self) -> Vec<Backend> {
+        if let Some(Chosen::Custom(_)) = &self.backend {
+            return Vec::new();
+        }
+        if self.backend.is_none() && windows::is_msys() && !Overrides::from_env().is_active() {
+            let profile = Backend::Profile(Shell::detect());
+            match self.msys {
+                MsysMode::Registry => {}
+                MsysMode::Profile => return vec![profile],
+                MsysMode::Both => return vec![Backend::detect(), profile],
+            }
+        }
+        let backend = self.resolve_backend();
+        if self.wsl_interop && backend != Backend::WslInterop && wsl::is_wsl() {
+            return vec![backend, Backend::WslInterop];
+        }
+        vec![backend]
+    }
+
+    fn builtin(&self, backend: Backend) -> io::Result<(Box<dyn EnvBackend>, Outcome)> {
+        let registry = || Outcome::RegistryWritten { key: self.scope.key().to_string(), old: None };
+        Ok(match backend {
+            Backend::Profile(shell) => {
+                let paths = self.profiles(shell)?;
+                let outcome = Outcome::added(&paths);
+                let backend = ProfileFiles {
+                    shell,
+                    paths,
+                    strict_posix: self.strict_posix,
+                    quoting: self.quoting,
+                    app: self.app.clone(),
+                    backups: self.file_writer().backups,
+                    annotate: self.annotate,
+                    app_conflicts: self.app_conflicts,
+                };
+                (Box::new(backend), outcome)
+            }
+            Backend::WindowsRegistry => {
+                let backend = windows::Registry {
+                    scope: self.scope,
+                    kind: self.registry_type,
+                    quoting: self.quoting,
+                    app: self.app.clone(),
+                };
+                (Box::new(backend), registry())
+            }
+            Backend::WindowsSetx => {
+                let backend = windows::Setx { scope: self.scope, quoting: self.quoting, app: self.app.clone() };
+                (Box::new(backend), registry())
+            }
+            Backend::WslInterop => {
+                let backend = wsl::Interop { kind: self.registry_type, quoting: self.quoting };
+                (Box::new(backend), Outcome::RegistryWritten { key: windows::USER_ENVIRONMENT.to_string(), old: None })
+            }
+            // There's only a path on the BSDs,
+            // elsewhere writing fails before this is reported
+            Backend::LoginConf => {
+                (Box::new(LoginConf), Outcome::Added { file: login_conf::path().unwrap_or_default() })
+            }
+        })
+    }
+
+    /// The built in backend we write with.
+    /// One picked by the caller wins, then [`Overrides`],
+    /// then [`Backend::detect`].
+    pub(crate) fn resolve_backend(&self) -> Backend {
+        match &self.backend {
+            Some(Chosen::Builtin(backend)) => *backend,
+            _ => match Overrides::from_env().profile {
+                Some(profile) => Backend::Profile(self.file_shell(&profile)),
+                None => Backend::detect(),
+            },
+        }
+    }
+
+    /// The files a profile backend for `shell` writes to,
+    /// taking [`Overrides`] and the [`DotfilePolicy`] into account.
+    pub(crate) fn profiles(&self, shell: Shell) -> io::Result<Vec<PathBuf>> {
+        match Overrides::from_env().profile {
+            Some(profile) => Ok(vec![profile]),
+            None => shell
+                .target_paths(self.target)?
+                .into_iter()
+                .map(|path| self.dotfiles.apply(path))
+                .collect(),
+        }
+    }
+
+    // Who writes to files and how many backups they keep
+    fn file_writer(&self) -> Writer<'_> {
+        Writer { app: self.app.as_deref(), backups: self.backups.unwrap_or(profile::BACKUPS) }
+    }
+
+    // A backend for a file we were given
+    pub(crate) fn file(&self, path: &Path) -> ProfileFiles {
+        ProfileFiles {
+            shell: self.file_shell(path),
+            paths: vec![path.to_path_buf()],
+            strict_posix: self.strict_posix,
+            quoting: self.quoting,
+            app: self.app.clone(),
+            backups: self.file_writer().backups,
+            annotate: self.annotate,
+            app_conflicts: self.app_conflicts,
+        }
+    }
+
+    // The syntax to use for a file we were given.
+    // A shell that was picked explicitly wins,
+    // then ENV_PERM_SHELL, then whatever
+    // the file name suggests.
+    pub(crate) fn file_shell(&self, path: &Path) -> Shell {
+        let picked = match &self.backend {
+            Some(Chosen::Builtin(backend)) => backend.shell(),
+            _ => None,
+        };
+        picked
+            .or_else(|| Overrides::from_env().shell)
+            .or_else(|| Shell::from_file(path))
+            .unwrap_or_else(Shell::detect)
+    }
+}
+
+// `var` as a str for the backends
+fn name(var: &OsStr) -> io::Result<&str> {
+    var.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} isn't UTF-8 so it can't be used as a name", var),
+        )
+    })
+}
+
+// `path` as text to go in a profile
+fn path_text(path: &Path) -> io::Result<&str> {
+    path.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} isn't UTF-8 so it can't go in a profile", path.display()),
+        )
+    })
+}
+
+fn pairs<I, T, U>(vars: I) -> Vec<(String, String)>
+where I: IntoIterator<Item = (T, U)>,
+      T: fmt::Display,
+      U: ToEnvValue,
+{
+    vars.into_iter()
+        .map(|(var, value)| (var.to_string(), value.to_env_value()))
+        .collect()
+}
+
+// Adds `entry` to the WSLENV `backend` persists, giving
+// `outcome` for what's written
+fn add_wslenv<B: EnvBackend + ?Sized>(backend: &B, outcome: Outcome, entry: &str) -> io::Result<Outcome> {
+    let old = backend.get("WSLENV")?;
+    let current = match &old {
+        Some(old) => old.clone(),
+        None => env::var("WSLENV").unwrap_or_default(),
+    };
+    match wslenv::with_entry(&current, entry)? {
+        Some(new) => {
+            backend.update("WSLENV", &new)?;
+            Ok(outcome.replacing(old))
+        }
+        None => Ok(Outcome::AlreadySet),
+    }
+}
+
+fn remove_wslenv<B: EnvBackend + ?Sized>(backend: &B, var: &str) -> io::Result<()> {
+    let old = backend.get("WSLENV")?.unwrap_or_default();
+    match wslenv::without(&old, var) {
+        Some(new) if new.is_empty() => backend.remove("WSLENV"),
+        Some(new) => backend.update("WSLENV", &new),
+        None => Ok(()),
+    }
+}
+
+fn add_pathext<B: EnvBackend + ?Sized>(backend: &B, outcome: Outcome, extension: &str) -> io::Result<Outcome> {
+    let old = backend.get("PATHEXT")?;
+    let current = match &old {
+        Some(old) => old.clone(),
+        None => env::var("PATHEXT").unwrap_or_else(|_| pathext::DEFAULT.to_string()),
+    };
+    match pathext::with_extension(&current, extension)? {
+        Some(new) => {
+            backend.update("PATHEXT", &new)?;
+            Ok(outcome.replacing(old))
+        }
+        None => Ok(Outcome::AlreadySet),
+    }
+}
+
+fn remove_pathext<B: EnvBackend + ?Sized>(backend: &B, extension: &str) -> io::Result<()> {
+    let old = backend.get("PATHEXT")?.unwrap_or_default();
+    match pathext::without(&old, extension)? {
+        Some(new) if new.is_empty() => backend.remove("PATHEXT"),
+        Some(new) => backend.update("PATHEXT", &new),
+        None => Ok(()),
+    }
+}
+
+// Whether `entry` is in the list in `var` for this process.
+// Entries starting with $HOME or ~ are compared
+// with it expanded as well.
+fn in_environment(var: &str, entry: &str) -> bool {
+    let current = match env::var_os(var) {
+        Some(current) => current,
+        None => return false,
+    };
+    let mut candidates = vec![PathBuf::from(entry)];
+    if let Some(home) = dirs::home_dir() {
+        for prefix in &["$HOME", "${HOME}", "~"] {
+            match entry.strip_prefix(prefix) {
+                Some("") => candidates.push(home.clone()),
+                Some(rest) if rest.starts_with(['/', '\\']) => {
+                    candidates.push(home.join(&rest[1..]))
+                }
+                _ => {}
+            }
+        }
+    }
+    env::split_paths(&current).any(|part| candidates.contains(&part))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::ALL;
+    use crate::testing::TempDir;
+
+    // What `var` adds up to in the file at `path`
+    fn value_in(config: &Config, path: &Path, var: &str) -> Option<String> {
+        config.file(path).get(var).unwrap()
+    }
+
+    #[test]
+    fn append_goes_last_and_prepend_first() {
+        let dir = TempDir::new("order");
+        for shell in ALL.iter().copied() {
+            let path = dir.path().join(format!("{:?}", shell));
+            let config = Config::new().shell(shell);
+            config.set_in(&path, "LIST", "/a").unwrap();
+            config.append_in(&path, "LIST", "/b").unwrap();
+            config.prepend_in(&path, "LIST", "/c").unwrap();
+            config.append_in(&path, "LIST", "/d").unwrap();
+            config.prepend_in(&path, "LIST", "/e").unwrap();
+            assert_eq!(value_in(&config, &path, "LIST").as_deref(), Some("/e:/c:/a:/b:/d"), "{:?}", shell);
+        }
+    }
+
+    #[test]
+    fn appends_build_on_the_inherited_value() {
+        let dir = TempDir::new("inherited");
+        for shell in ALL.iter().copied() {
+            let path = dir.path().join(format!("{:?}", shell));
+            let config = Config::new().shell(shell);
+            config.append_in(&path, "LIST", "/b").unwrap();
+            config.prepend_in(&path, "LIST", "/a").unwrap();
+            assert_eq!(value_in(&config, &path, "LIST").as_deref(), Some("/a:$LIST:/b"), "{:?}", shell);
+        }
+    }
+
+    #[test]
+    fn order_is_kept_in_the_file() {
+        let dir = TempDir::new("lines");
+        for shell in ALL.iter().copied() {
+            let path = dir.path().join(format!("{:?}", shell));
+            let config = Config::new().shell(shell);
+            config.append_in(&path, "LIST", "/first").unwrap();
+            config.prepend_in(&path, "LIST", "/second").unwrap();
+            let lines = profile::read_lines(&path).unwrap();
+            let values: Vec<String> = lines.iter().filter_map(|line| shell.parse_line(line)).map(|(_, value)| value).collect();
+            let added: Vec<Option<String>> = values.iter().map(|value| shell.appended("LIST", value)).collect();
+            let prepended: Vec<Option<String>> = values.iter().map(|value| shell.prepended("LIST", value)).collect();
+            assert_eq!(added, [Some("/first".to_string()), None], "{:?} wrote {:?}", shell, lines);
+            assert_eq!(prepended, [None, Some("/second".to_string())], "{:?} wrote {:?}", shell, lines);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Asking the application before a profile
+//! is written so the user can have a say.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+thread_local! {
+    // The hook of the config whose change is running
+    static HOOK: RefCell<Option<Hook>> = const { RefCell::new(None) };
+}
+
+/// A write to a profile that's about to happen, given to
+/// the hook from [`Config::on_before_write`](crate::Config::on_before_write).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PendingChange {
+    /// The file about to be written.
+    pub path: PathBuf,
+    /// The lines about to be added, in order.
+    pub added: Vec<String>,
+    /// The lines about to be taken out or replaced.
+    pub removed: Vec<String>,
+    /// The app from [`Config::app`](crate::Config::app), if any.
+    pub app: Option<String>,
+}
+
+/// What to do with a [`PendingChange`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Decision {
+    /// Write it.
+    Write,
+    /// Leave the file alone, as if it had been written.
+    Skip,
+    /// Add the lines to this file instead. Rewrites of
+    /// lines already in a file, like an [`update`](crate::update),
+    /// can't be redirected and fail with
+    /// [`InvalidInput`](std::io::ErrorKind::InvalidInput).
+    Redirect(PathBuf),
+}
+
+/// The hook a config asks before writing.
+#[derive(Clone)]
+pub(crate) struct Hook(pub(crate) Arc<dyn Fn(&PendingChange) -> Decision + Send + Sync>);
+
+impl fmt::Debug for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Hook")
+    }
+}
+
+/// Runs `f` with `hook` asked before each write.
+pub(crate) fn with_hook<T, F: FnOnce() -> T>(hook: Option<&Hook>, f: F) -> T {
+    let previous = HOOK.with(|current| current.replace(hook.cloned()));
+    let restore = Restore(previous);
+    let result = f();
+    drop(restore);
+    result
+}
+
+// Puts back the hook that was there before when dropped,
+// so a panic doesn't leave the wrong one in place
+struct Restore(Option<Hook>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        HOOK.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// What the hook says about writing `after` to the file
+/// at `path` in place of `before`, Write if there isn't one.
+pub(crate) fn ask(path: &Path, before: &[String], after: &[String], app: Option<&str>) -> Decision {
+    let hook = match HOOK.with(|current| current.borrow().clone()) {
+        Some(hook) => hook,
+        None => return Decision::Write,
+    };
+    let change = PendingChange {
+        path: path.to_path_buf(),
+        added: after.iter().filter(|line| !line.is_empty() && !before.contains(line)).cloned().collect(),
+        removed: before.iter().filter(|line| !line.is_empty() && !after.contains(line)).cloned().collect(),
+        app: app.map(String::from),
+    };
+    // Anything the hook writes itself isn't asked about
+    with_hook(None, || (hook.0)(&change))
+}
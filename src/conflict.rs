@@ -0,0 +1,55 @@
+//! Noticing when something else changed a
+//! file between reading and writing it.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::Place;
+
+/// The error a write fails with when the file changed
+/// after it was read, e.g. because the user saved it in
+/// an editor meanwhile, or a [`Plan`](crate::Plan) is
+/// applied after what it planned for changed.
+/// Nothing is written so the caller can read it
+/// again and redo the change.
+/// ```no_run
+/// use env_perm::Conflict;
+///
+/// if let Err(e) = env_perm::update("DUMMY", 2) {
+///     match Conflict::from_error(&e) {
+///         Some(conflict) => println!("{}, try again", conflict),
+///         None => panic!("{}", e),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Conflict {
+    /// The file or registry value that changed.
+    pub place: Place,
+}
+
+impl Conflict {
+    /// The conflict `e` is about, if it's one.
+    pub fn from_error(e: &io::Error) -> Option<&Conflict> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.place {
+            Place::File(path) => write!(f, "{} was changed by something else since it was read", path.display()),
+            Place::Registry { key, var } => write!(f, "{}\\{} was changed by something else since it was read", key, var),
+        }
+    }
+}
+
+impl Error for Conflict {}
+
+impl From<Conflict> for io::Error {
+    fn from(conflict: Conflict) -> io::Error {
+        io::Error::other(conflict)
+    }
+}
@@ -0,0 +1,132 @@
+//! Looking at how variables would be persisted
+//! here, to help users sort out problems.
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use crate::dotfiles::{self, Managed};
+use crate::{Backend, Shell};
+
+/// What [`doctor`](crate::doctor) found out about
+/// where variables go on this machine.
+/// ```no_run
+/// let diagnosis = env_perm::doctor();
+/// print!("{}", diagnosis);
+/// for problem in &diagnosis.problems {
+///     eprintln!("warning: {}", problem);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Diagnosis {
+    /// The shell [`Shell::detect`] picked.
+    pub shell: Shell,
+    /// The backends that would be written to,
+    /// empty for a custom one.
+    pub backends: Vec<Backend>,
+    /// The profiles of the shells being written to,
+    /// in the order each shell reads them.
+    pub profiles: Vec<ProfileFile>,
+    /// The files that would be written to.
+    pub writes_to: Vec<PathBuf>,
+    /// Whether the windows registry can be read.
+    pub registry: RegistryAccess,
+    /// Anything that would stop a write working.
+    pub problems: Vec<String>,
+}
+
+/// A profile a [`Diagnosis`] looked at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProfileFile {
+    /// Where it is.
+    pub path: PathBuf,
+    /// Whether it's there.
+    pub exists: bool,
+    /// Whether it can be written, or for one that
+    /// doesn't exist whether it can be created.
+    pub writable: bool,
+    /// Whether it's a symlink.
+    pub symlink: bool,
+    /// The dotfile manager that looks after it, if any.
+    pub managed: Option<Managed>,
+}
+
+/// Whether the windows registry could be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RegistryAccess {
+    /// It could.
+    Readable,
+    /// There's no registry off windows.
+    Unavailable,
+    /// Reading it failed with this error.
+    Failed(String),
+}
+
+impl ProfileFile {
+    pub(crate) fn new(path: PathBuf) -> ProfileFile {
+        let exists = path.is_file();
+        let writable = if exists {
+            // Opening to append doesn't change anything
+            OpenOptions::new().append(true).open(&path).is_ok()
+        } else {
+            creatable(&path)
+        };
+        ProfileFile {
+            exists,
+            writable,
+            symlink: fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()),
+            managed: dotfiles::managed(&path),
+            path,
+        }
+    }
+}
+
+// Whether a file could be made at `path`, the
+// nearest directory above it that exists being
+// one we can write to
+fn creatable(path: &Path) -> bool {
+    path.ancestors()
+        .skip(1)
+        .find(|dir| dir.is_dir())
+        .and_then(|dir| fs::metadata(dir).ok())
+        .is_some_and(|metadata| !metadata.permissions().readonly())
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "shell: {:?}", self.shell)?;
+        writeln!(f, "backends: {:?}", self.backends)?;
+        for profile in &self.profiles {
+            let mut notes = Vec::new();
+            notes.push(if profile.exists { "exists" } else { "missing" });
+            if !profile.writable {
+                notes.push("not writable");
+            }
+            if profile.symlink {
+                notes.push("symlink");
+            }
+            if self.writes_to.contains(&profile.path) {
+                notes.push("written to");
+            }
+            writeln!(f, "profile: {} ({})", profile.path.display(), notes.join(", "))?;
+            if let Some(managed) = &profile.managed {
+                writeln!(f, "  managed by {:?} from {}", managed.manager, managed.source.display())?;
+            }
+        }
+        for path in self.writes_to.iter().filter(|path| !self.profiles.iter().any(|profile| profile.path == **path)) {
+            writeln!(f, "writes to: {}", path.display())?;
+        }
+        match &self.registry {
+            RegistryAccess::Readable => writeln!(f, "registry: readable")?,
+            RegistryAccess::Unavailable => writeln!(f, "registry: unavailable")?,
+            RegistryAccess::Failed(e) => writeln!(f, "registry: {}", e)?,
+        }
+        for problem in &self.problems {
+            writeln!(f, "problem: {}", problem)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+//! Noticing profiles that belong to a dotfile manager.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use crate::shell;
+
+/// A tool that keeps the user's dotfiles somewhere else
+/// and puts them in place in the home directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum DotfileManager {
+    /// The profile is a symlink, as made by GNU stow,
+    /// home-manager, yadm alternates and plenty of
+    /// hand rolled setups.
+    Symlink,
+    /// chezmoi, which copies files out of its source
+    /// directory and overwrites local changes on `chezmoi apply`.
+    Chezmoi,
+    /// yadm, which tracks the files in place with git.
+    Yadm,
+}
+
+/// A profile that a dotfile manager looks after.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Managed {
+    /// Who manages it.
+    pub manager: DotfileManager,
+    /// The profile in the home directory.
+    pub file: PathBuf,
+    /// The file the manager keeps,
+    /// which is where changes should go.
+    pub source: PathBuf,
+}
+
+/// What to do when the profile we'd write to
+/// is managed by a [`DotfileManager`].
+/// This only applies to discovered profiles,
+/// not files passed in or from `ENV_PERM_PROFILE`.
+#[derive(Clone, Default)]
+pub enum DotfilePolicy {
+    /// Write to the profile anyway.
+    /// This is the default.
+    #[default]
+    Ignore,
+    /// Fail with an error naming the source file.
+    Refuse,
+    /// Call this and then write to the profile anyway.
+    Warn(Arc<dyn Fn(&Managed) + Send + Sync>),
+    /// Write to the manager's source file instead.
+    WriteSource,
+}
+
+impl fmt::Debug for DotfilePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DotfilePolicy::Ignore => f.write_str("Ignore"),
+            DotfilePolicy::Refuse => f.write_str("Refuse"),
+            DotfilePolicy::Warn(_) => f.write_str("Warn"),
+            DotfilePolicy::WriteSource => f.write_str("WriteSource"),
+        }
+    }
+}
+
+impl DotfilePolicy {
+    /// The file to write to in place of `path`.
+    pub(crate) fn apply(&self, path: PathBuf) -> io::Result<PathBuf> {
+        if let DotfilePolicy::Ignore = self {
+            return Ok(path);
+        }
+        let managed = match managed(&path) {
+            Some(managed) => managed,
+            None => return Ok(path),
+        };
+        match self {
+            DotfilePolicy::Ignore => Ok(path),
+            DotfilePolicy::Refuse => {
+                let by = match managed.manager {
+                    DotfileManager::Symlink => "is a symlink",
+                    DotfileManager::Chezmoi => "is managed by chezmoi",
+                    DotfileManager::Yadm => "is managed by yadm",
+                };
+                Err(io::Error::other(format!(
+                    "{} {}, edit {} instead",
+                    managed.file.display(),
+                    by,
+                    managed.source.display()
+                )))
+            }
+            DotfilePolicy::Warn(warn) => {
+                warn(&managed);
+                Ok(path)
+            }
+            DotfilePolicy::WriteSource => Ok(managed.source),
+        }
+    }
+}
+
+/// Checks whether a dotfile manager looks after `path`.
+pub fn managed(path: &Path) -> Option<Managed> {
+    let (manager, source) = symlink(path)
+        .map(|source| (DotfileManager::Symlink, source))
+        .or_else(|| chezmoi(path).map(|source| (DotfileManager::Chezmoi, source)))
+        .or_else(|| yadm(path).map(|source| (DotfileManager::Yadm, source)))?;
+    Some(Managed { manager, file: path.to_path_buf(), source })
+}
+
+// Where a symlinked profile really lives
+fn symlink(path: &Path) -> Option<PathBuf> {
+    if !fs::symlink_metadata(path).ok()?.file_type().is_symlink() {
+        return None;
+    }
+    fs::canonicalize(path).ok().or_else(|| {
+        // A dangling link still tells us where it's meant to go
+        let target = fs::read_link(path).ok()?;
+        Some(path.parent()?.join(target))
+    })
+}
+
+fn chezmoi(path: &Path) -> Option<PathBuf> {
+    // chezmoi knows best, including custom source dirs
+    if let Ok(output) = Command::new("chezmoi").arg("source-path").arg(path).output() {
+        if output.status.success() {
+            let source = String::from_utf8(output.stdout).ok()?;
+            return Some(PathBuf::from(source.trim()));
+        }
+        return None;
+    }
+    let home = shell::home_dir().ok()?;
+    let relative = path.strip_prefix(&home).ok()?;
+    let mut source = home.join(".local").join("share").join("chezmoi");
+    if !source.is_dir() {
+        return None;
+    }
+    for part in relative.iter() {
+        let part = part.to_str()?;
+        let entry = fs::read_dir(&source)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .find(|entry| entry.file_name().to_str().map(chezmoi_target) == Some(part.to_string()))?;
+        source = entry.path();
+    }
+    Some(source)
+}
+
+// The name chezmoi gives the target of a source file,
+// e.g. private_dot_zshrc.tmpl is .zshrc
+fn chezmoi_target(name: &str) -> String {
+    const ATTRIBUTES: &[&str] = &[
+        "create_", "modify_", "encrypted_", "private_", "readonly_",
+        "empty_", "executable_", "exact_", "symlink_",
+    ];
+    let mut name = name.strip_suffix(".tmpl").unwrap_or(name);
+    while let Some(rest) = ATTRIBUTES.iter().find_map(|attribute| name.strip_prefix(attribute)) {
+        name = rest;
+    }
+    match name.strip_prefix("dot_") {
+        Some(rest) => format!(".{}", rest),
+        None => name.to_string(),
+    }
+}
+
+// yadm keeps a bare repo with the home directory as
+// its work tree so the file is its own source
+fn yadm(path: &Path) -> Option<PathBuf> {
+    let home = shell::home_dir().ok()?;
+    let data = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".local").join("share"));
+    let repo = [data.join("yadm"), home.join(".config").join("yadm")]
+        .iter()
+        .map(|dir| dir.join("repo.git"))
+        .find(|repo| repo.is_dir())?;
+    let tracked = Command::new("git")
+        .arg("--git-dir")
+        .arg(&repo)
+        .arg("--work-tree")
+        .arg(&home)
+        .args(["ls-files", "--error-unmatch"])
+        .arg(path)
+        .output()
+        .ok()?
+        .status
+        .success();
+    if tracked {
+        Some(path.to_path_buf())
+    } else {
+        None
+    }
+}
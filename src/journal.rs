@@ -0,0 +1,756 @@
+//! A history of every change written, kept in a state
+//! file so it can be looked back on and undone.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::lock;
+use crate::plan;
+use crate::profile::{self, Writer};
+use crate::report;
+use crate::shell;
+use crate::tamper;
+use crate::Conflict;
+use crate::windows::{self, Scope};
+
+/// A change written to a profile or the registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Change {
+    /// When it was written.
+    pub time: SystemTime,
+    /// The app from [`Config::app`](crate::Config::app), if any.
+    pub app: Option<String>,
+    /// What was changed.
+    pub place: Place,
+    /// The lines added to the file, or for the registry
+    /// the value written. Empty if something was removed.
+    pub added: Vec<String>,
+    /// What the registry value was before, or None if there
+    /// wasn't one. Files only keep the lines that changed
+    /// so it's None for them.
+    pub previous: Option<String>,
+    /// For a file, a checksum of each app's block in it
+    /// afterwards by app, to tell if one is edited by hand.
+    pub blocks: Vec<(String, u64)>,
+    // For a file, what's needed to undo the change
+    edit: Option<FileEdit>,
+}
+
+// How a write changed a file: which lines it took out
+// and put in where, and a checksum of the whole file
+// afterwards to tell if it's been edited since
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct FileEdit {
+    // None if the file was deleted
+    checksum: Option<u64>,
+    // Whether the file didn't exist before
+    created: bool,
+    hunks: Vec<Hunk>,
+}
+
+// A run of lines replaced with `added` others,
+// starting at line `at` of the new file
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Hunk {
+    at: usize,
+    added: usize,
+    removed: Vec<String>,
+}
+
+impl FileEdit {
+    // The file's `lines` with the hunks put back
+    // the way they were, last first
+    fn undo(&self, mut lines: Vec<String>) -> Vec<String> {
+        for hunk in self.hunks.iter().rev() {
+            let end = (hunk.at + hunk.added).min(lines.len());
+            lines.splice(hunk.at.min(end)..end, hunk.removed.iter().cloned());
+        }
+        lines
+    }
+}
+
+impl Change {
+    // The lines the change took out of a file
+    pub(crate) fn removed(&self) -> Vec<String> {
+        self.edit.iter().flat_map(|edit| &edit.hunks).flat_map(|hunk| hunk.removed.iter().cloned()).collect()
+    }
+}
+
+/// Where a [`Change`] was made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Place {
+    /// A profile or other file.
+    File(PathBuf),
+    /// A value in the windows registry.
+    Registry {
+        /// The key it's under, e.g. `HKCU\Environment`.
+        key: String,
+        /// The variable.
+        var: String,
+    },
+}
+
+/// Every change written to a profile or the registry
+/// so far, oldest first.
+/// ```no_run
+/// for change in env_perm::history().unwrap() {
+///     println!("{:?} {:?} added {:?}", change.time, change.place, change.added);
+/// }
+/// ```
+pub fn history() -> io::Result<Vec<Change>> {
+    let contents = match fs::read_to_string(journal_path()?) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            parse(line).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("Line {} of the journal is broken", i + 1))
+            })
+        })
+        .collect()
+}
+
+/// Reverts the last `n` changes in the journal, newest
+/// first, and takes them out of it. Files get back the lines
+/// that were changed, or are deleted if they didn't exist,
+/// and registry values are put back or deleted.
+/// A file that's been edited since is left alone with
+/// a [`Conflict`](crate::Conflict) error, and files
+/// are backed up before they're put back.
+/// Gives the changes that were reverted, newest first.
+/// ```
+/// let dir = std::env::temp_dir().join("env_perm_undo_last");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// std::env::set_var("ENV_PERM_STATE_DIR", &dir);
+/// let path = dir.join("profile.sh");
+/// env_perm::set_in(&path, "DUMMY", 1).unwrap();
+/// env_perm::undo_last(1).unwrap();
+/// assert!(!path.exists());
+/// ```
+pub fn undo_last(n: usize) -> io::Result<Vec<Change>> {
+    lock::locked(|| windows::batched(|| {
+        let history = history()?;
+        let keep = history.len().saturating_sub(n);
+        undo(history, keep, Writer::default())
+    }))
+}
+
+/// Reverts every change in the journal made after `time`,
+/// like [`undo_last`], so a failed install can back out
+/// everything it did since it started.
+/// ```no_run
+/// let started = std::time::SystemTime::now();
+/// if env_perm::append("PATH", "/opt/myapp/bin").is_err() {
+///     env_perm::rollback_to(started).expect("Failed to roll back");
+/// }
+/// ```
+pub fn rollback_to(time: SystemTime) -> io::Result<Vec<Change>> {
+    lock::locked(|| windows::batched(|| {
+        let history = history()?;
+        let keep = history.iter().position(|change| change.time > time).unwrap_or(history.len());
+        undo(history, keep, Writer::default())
+    }))
+}
+
+// Reverts everything after the first `keep` changes.
+// If one fails the journal keeps it and everything
+// before it.
+fn undo(mut history: Vec<Change>, keep: usize, writer: Writer) -> io::Result<Vec<Change>> {
+    let undone = history.split_off(keep);
+    for (i, change) in undone.iter().enumerate().rev() {
+        if let Err(e) = revert(change, writer) {
+            history.extend_from_slice(&undone[..=i]);
+            rewrite(&history)?;
+            return Err(e);
+        }
+    }
+    rewrite(&history)?;
+    Ok(undone.into_iter().rev().collect())
+}
+
+// Puts back what was there before `change`. This
+// writes directly so it isn't journaled itself, but
+// files are backed up first, keeping as many
+// backups as `writer` says.
+fn revert(change: &Change, writer: Writer) -> io::Result<()> {
+    match (&change.place, &change.edit) {
+        (Place::File(path), Some(edit)) => {
+            let current = profile::contents(path)?;
+            let lines = profile::lines_of(current.clone());
+            if current.map(|_| tamper::hash(&lines)) != edit.checksum {
+                return Err(Conflict { place: change.place.clone() }.into());
+            }
+            let lines = edit.undo(lines);
+            let gone = edit.created && lines.iter().all(|line| line.trim().is_empty());
+            profile::put_back(path, if gone { None } else { Some(&lines) }, writer)
+        }
+        (Place::Registry { key, var }, _) => windows::restore(Scope::of(key), var, change.previous.as_deref()),
+        // Every file change is journaled with its edit
+        (Place::File(_), None) => Ok(()),
+    }
+}
+
+/// Takes out everything the journal says `app` persisted:
+/// its blocks in `files` and every file it wrote to, and
+/// the entries it added to registry values.
+/// Files are backed up as `writer` says.
+/// Gives the places that changed.
+pub(crate) fn uninstall(app: &str, mut files: Vec<PathBuf>, writer: Writer) -> io::Result<Vec<Place>> {
+    lock::locked(|| windows::batched(|| {
+        let changes: Vec<Change> = history()?
+            .into_iter()
+            .filter(|change| change.app.as_deref() == Some(app))
+            .collect();
+        let mut vars: Vec<(&str, &str)> = Vec::new();
+        for change in &changes {
+            match &change.place {
+                Place::File(path) if !files.contains(path) => files.push(path.clone()),
+                Place::Registry { key, var } if !vars.contains(&(key.as_str(), var.as_str())) => vars.push((key, var)),
+                _ => {}
+            }
+        }
+        let mut changed = Vec::new();
+        for path in files {
+            let lines = profile::read_lines(&path)?;
+            let kept = profile::remove_block(lines.clone(), app);
+            if kept != lines {
+                profile::write_lines(&path, &kept, Writer { app: Some(app), ..writer })?;
+                changed.push(Place::File(path));
+            }
+        }
+        for (key, var) in vars {
+            let writes: Vec<&Change> = changes
+                .iter()
+                .filter(|change| matches!(&change.place, Place::Registry { key: under, var: name } if under == key && name == var))
+                .collect();
+            // The entries each write brought in that weren't there before
+            let mut added: Vec<String> = Vec::new();
+            for write in &writes {
+                let before: Vec<&str> = write.previous.as_deref().map_or_else(Vec::new, |previous| previous.split(';').collect());
+                for value in &write.added {
+                    added.extend(value.split(';').filter(|entry| !before.contains(entry)).map(String::from));
+                }
+            }
+            let first = writes[0].previous.as_deref();
+            if windows::take_out(Scope::of(key), var, app, first, &added)? {
+                changed.push(Place::Registry { key: key.to_string(), var: var.to_string() });
+            }
+        }
+        Ok(changed)
+    }))
+}
+
+/// The file changes are recorded in,
+/// `$XDG_STATE_HOME/env_perm/journal` which is
+/// `~/.local/state/env_perm/journal` unless that's set.
+/// On windows it's under the local app data directory.
+/// `ENV_PERM_STATE_DIR` names a different directory.
+///
+/// It keeps the last 1000 changes. If the directory can't
+/// be made nothing is journaled but writes still go ahead.
+pub fn journal_path() -> io::Result<PathBuf> {
+    Ok(state_dir()?.join("journal"))
+}
+
+pub(crate) fn state_dir() -> io::Result<PathBuf> {
+    if let Some(dir) = env::var_os("ENV_PERM_STATE_DIR").filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    if cfg!(windows) {
+        if let Some(dir) = dirs::data_local_dir() {
+            return Ok(dir.join("env_perm"));
+        }
+    }
+    match env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Ok(PathBuf::from(dir).join("env_perm")),
+        None => Ok(shell::home_dir()?.join(".local").join("state").join("env_perm")),
+    }
+}
+
+/// Records that the file at `path` was written,
+/// `previous` being what it had before, `lines` what
+/// it has now or None if it was deleted, and `blocks`
+/// the checksums of the blocks it has now.
+/// Lines that weren't there before count as added.
+/// Only the lines that changed are kept, not the whole file.
+pub(crate) fn record_file(
+    path: &Path,
+    app: Option<&str>,
+    previous: Option<String>,
+    lines: Option<&[String]>,
+    blocks: Vec<(String, u64)>,
+) -> io::Result<()> {
+    let old = profile::lines_of(previous.clone());
+    let new = lines.unwrap_or_default();
+    let added = new
+        .iter()
+        .filter(|line| !line.is_empty() && !old.contains(line))
+        .cloned()
+        .collect();
+    let edit = FileEdit { checksum: lines.map(tamper::hash), created: previous.is_none(), hunks: diff(&old, new) };
+    record(&Change {
+        time: SystemTime::now(),
+        app: app.map(String::from),
+        // Relative paths wouldn't mean anything
+        // to whatever reads this later
+        place: Place::File(std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf())),
+        added,
+        previous: None,
+        blocks,
+        edit: Some(edit),
+    })
+}
+
+// Past this the whole run between the lines that
+// are the same at each end is one hunk, rather
+// than working out what changed inside it
+const MAX_DIFF: usize = 1_000_000;
+
+// The hunks that make `new` out of `old`, from
+// the longest run of lines they have in common
+fn diff(old: &[String], new: &[String]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    if a.len().saturating_mul(b.len()) > MAX_DIFF {
+        return vec![Hunk { at: prefix, added: b.len(), removed: a.to_vec() }];
+    }
+    // How many lines the rest of each have in common
+    let mut common = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            common[i][j] = if a[i] == b[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+        }
+    }
+    let mut hunks = Vec::new();
+    let mut hunk: Option<Hunk> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            hunks.extend(hunk.take());
+            i += 1;
+            j += 1;
+            continue;
+        }
+        let current = hunk.get_or_insert_with(|| Hunk { at: prefix + j, added: 0, removed: Vec::new() });
+        if j < b.len() && (i == a.len() || common[i][j + 1] >= common[i + 1][j]) {
+            current.added += 1;
+            j += 1;
+        } else {
+            current.removed.push(a[i].clone());
+            i += 1;
+        }
+    }
+    hunks.extend(hunk);
+    hunks
+}
+
+/// Records that `var` was written to the registry
+/// under `key`, or deleted if `value` is None.
+/// While planning the write is planned instead.
+pub(crate) fn record_registry(
+    key: &str,
+    var: &str,
+    app: Option<&str>,
+    previous: Option<String>,
+    value: Option<String>,
+) -> io::Result<()> {
+    if plan::planning() {
+        plan::write_var(key, var, value, previous, app);
+        return Ok(());
+    }
+    record(&Change {
+        time: SystemTime::now(),
+        app: app.map(String::from),
+        place: Place::Registry { key: key.to_string(), var: var.to_string() },
+        added: value.into_iter().collect(),
+        previous,
+        blocks: Vec::new(),
+        edit: None,
+    })
+}
+
+// Written to a temporary file and renamed over
+// the journal so a crash can't leave half of it
+fn rewrite(changes: &[Change]) -> io::Result<()> {
+    let lines: String = changes.iter().map(|change| render(change) + "\n").collect();
+    profile::replace(&journal_path()?, lines.as_bytes())
+}
+
+// How many changes the journal keeps
+const KEEP: usize = 1000;
+
+fn record(change: &Change) -> io::Result<()> {
+    report::note(change);
+    lock::locked(|| {
+        // The write this is about has happened already,
+        // so having nowhere to keep the journal, e.g. with
+        // no home directory, isn't a reason to fail it
+        let path = match journal_path() {
+            Ok(path) => path,
+            Err(_) => return Ok(()),
+        };
+        if let Some(dir) = path.parent() {
+            if fs::create_dir_all(dir).is_err() {
+                return Ok(());
+            }
+        }
+        let mut journal = OpenOptions::new().append(true).create(true).open(&path)?;
+        writeln!(journal, "{}", render(change))?;
+        journal.flush()?;
+        trim(&path)
+    })
+}
+
+// Drops the oldest changes past the newest `KEEP`
+fn trim(path: &Path) -> io::Result<()> {
+    let contents = fs::read_to_string(path)?;
+    let lines: Vec<&str> = contents.lines().collect();
+    if lines.len() <= KEEP {
+        return Ok(());
+    }
+    let kept: String = lines[lines.len() - KEEP..].iter().map(|line| format!("{}\n", line)).collect();
+    profile::replace(path, kept.as_bytes())
+}
+
+// One tab separated line: the time in milliseconds,
+// the app, the kind of place, the file or key, the
+// variable, the added lines, what was there before,
+// the block checksums, each as hex then the app, and
+// for a file how it was edited.
+// Missing values are `-` and present ones start with `=`.
+fn render(change: &Change) -> String {
+    let millis = change.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let (kind, target, var) = match &change.place {
+        Place::File(path) => ("file", path.to_string_lossy().into_owned(), String::new()),
+        Place::Registry { key, var } => ("registry", key.clone(), var.clone()),
+    };
+    let blocks: Vec<String> = change.blocks.iter().map(|(app, checksum)| format!("{:016x} {}", checksum, app)).collect();
+    [
+        millis.to_string(),
+        optional(change.app.as_deref()),
+        kind.to_string(),
+        escape(&target),
+        escape(&var),
+        escape(&change.added.join("\n")),
+        optional(change.previous.as_deref()),
+        escape(&blocks.join("\n")),
+        optional(change.edit.as_ref().map(render_edit).as_deref()),
+    ]
+    .join("\t")
+}
+
+// The checksum as hex or `-` if the file was deleted,
+// `created` or `existed`, then each hunk as a line of
+// where it is, how many lines it added and how many it
+// removed, space separated, followed by those removed
+fn render_edit(edit: &FileEdit) -> String {
+    let mut lines = vec![
+        edit.checksum.map_or_else(|| "-".to_string(), |checksum| format!("{:016x}", checksum)),
+        if edit.created { "created" } else { "existed" }.to_string(),
+    ];
+    for hunk in &edit.hunks {
+        lines.push(format!("{} {} {}", hunk.at, hunk.added, hunk.removed.len()));
+        lines.extend(hunk.removed.iter().cloned());
+    }
+    lines.join("\n")
+}
+
+fn parse_edit(text: &str) -> Option<FileEdit> {
+    let mut lines = text.split('\n');
+    let checksum = match lines.next()? {
+        "-" => None,
+        checksum => Some(u64::from_str_radix(checksum, 16).ok()?),
+    };
+    let created = match lines.next()? {
+        "created" => true,
+        "existed" => false,
+        _ => return None,
+    };
+    let mut hunks = Vec::new();
+    while let Some(header) = lines.next() {
+        let mut numbers = header.split(' ').map(|number| number.parse::<usize>().ok());
+        let (at, added, removed) = (numbers.next()??, numbers.next()??, numbers.next()??);
+        let removed = (0..removed).map(|_| lines.next().map(String::from)).collect::<Option<_>>()?;
+        hunks.push(Hunk { at, added, removed });
+    }
+    Some(FileEdit { checksum, created, hunks })
+}
+
+fn parse(line: &str) -> Option<Change> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    let (millis, app, kind, target, var, added, previous, blocks, edit) = match fields[..] {
+        [millis, app, kind, target, var, added, previous, blocks, edit] => {
+            (millis, app, kind, target, var, added, previous, blocks, edit)
+        }
+        _ => return None,
+    };
+    let place = match kind {
+        "file" => Place::File(PathBuf::from(unescape(target))),
+        "registry" => Place::Registry { key: unescape(target), var: unescape(var) },
+        _ => return None,
+    };
+    let added = unescape(added);
+    Some(Change {
+        time: UNIX_EPOCH + Duration::from_millis(millis.parse().ok()?),
+        app: parse_optional(app)?,
+        place,
+        added: if added.is_empty() { Vec::new() } else { added.split('\n').map(String::from).collect() },
+        previous: parse_optional(previous)?,
+        blocks: unescape(blocks)
+            .split('\n')
+            .filter(|block| !block.is_empty())
+            .map(|block| {
+                let (checksum, app) = block.split_once(' ')?;
+                Some((app.to_string(), u64::from_str_radix(checksum, 16).ok()?))
+            })
+            .collect::<Option<_>>()?,
+        // Files always have an edit and the registry never does
+        edit: match (kind, parse_optional(edit)?) {
+            ("file", Some(edit)) => Some(parse_edit(&edit)?),
+            ("registry", None) => None,
+            _ => return None,
+        },
+    })
+}
+
+pub(crate) fn optional(value: Option<&str>) -> String {
+    match value {
+        Some(value) => format!("={}", escape(value)),
+        None => "-".to_string(),
+    }
+}
+
+pub(crate) fn parse_optional(field: &str) -> Option<Option<String>> {
+    match field {
+        "-" => Some(None),
+        _ => Some(Some(unescape(field.strip_prefix('=')?))),
+    }
+}
+
+pub(crate) fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+pub(crate) fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split_whitespace().map(String::from).collect()
+    }
+
+    fn undone(old: &str, new: &str) -> Vec<String> {
+        let edit = FileEdit { checksum: None, created: false, hunks: diff(&lines(old), &lines(new)) };
+        edit.undo(lines(new))
+    }
+
+    #[test]
+    fn diff_undoes() {
+        let cases = [
+            ("", ""),
+            ("", "a b c"),
+            ("a b c", ""),
+            ("a b c", "a b c d"),
+            ("a b c", "z a b c"),
+            ("a b c", "a x c"),
+            ("a b c d e", "a c e f"),
+            ("a a a", "a a"),
+            ("x a y a z", "a q a"),
+        ];
+        for (old, new) in cases {
+            assert_eq!(undone(old, new), lines(old), "{:?} -> {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn diff_keeps_only_what_changed() {
+        let hunks = diff(&lines("a b c d e"), &lines("a x c d e f"));
+        assert_eq!(
+            hunks,
+            [Hunk { at: 1, added: 1, removed: lines("b") }, Hunk { at: 5, added: 1, removed: Vec::new() }]
+        );
+        assert_eq!(diff(&lines("a b"), &lines("a b")), []);
+    }
+
+    #[test]
+    fn big_rewrites_are_one_hunk() {
+        let old: Vec<String> = (0..2000).map(|i| format!("old {}", i)).collect();
+        let new: Vec<String> = (0..2000).map(|i| format!("new {}", i)).collect();
+        let hunks = diff(&old, &new);
+        assert_eq!(hunks, [Hunk { at: 0, added: 2000, removed: old.clone() }]);
+        assert_eq!(FileEdit { checksum: None, created: false, hunks }.undo(new), old);
+    }
+
+    #[test]
+    fn file_changes_round_trip() {
+        let change = Change {
+            time: UNIX_EPOCH + Duration::from_millis(1_700_000_000_000),
+            app: Some("myapp".to_string()),
+            place: Place::File(PathBuf::from("/home/me/.profile")),
+            added: lines("export\tA=1"),
+            previous: None,
+            blocks: vec![("myapp".to_string(), 0x1234)],
+            edit: Some(FileEdit {
+                checksum: Some(0xdead_beef),
+                created: false,
+                hunks: vec![
+                    Hunk { at: 0, added: 1, removed: vec!["old\\line".to_string(), "".to_string()] },
+                    Hunk { at: 3, added: 0, removed: vec!["3 1 0".to_string()] },
+                ],
+            }),
+        };
+        let line = render(&change);
+        assert!(!line.contains('\n'));
+        assert_eq!(parse(&line), Some(change));
+    }
+
+    #[test]
+    fn deleted_files_round_trip() {
+        let edit = FileEdit { checksum: None, created: true, hunks: vec![Hunk { at: 0, added: 0, removed: lines("a b") }] };
+        assert_eq!(parse_edit(&render_edit(&edit)), Some(edit));
+    }
+
+    #[test]
+    fn registry_changes_round_trip() {
+        let change = Change {
+            time: UNIX_EPOCH,
+            app: None,
+            place: Place::Registry { key: r"HKCU\Environment".to_string(), var: "PATH".to_string() },
+            added: vec![r"C:\a;C:\b".to_string()],
+            previous: Some(r"C:\a".to_string()),
+            blocks: Vec::new(),
+            edit: None,
+        };
+        assert_eq!(parse(&render(&change)), Some(change));
+    }
+
+    #[test]
+    fn incomplete_changes_dont_parse() {
+        let registry = render(&Change {
+            time: UNIX_EPOCH,
+            app: None,
+            place: Place::Registry { key: r"HKCU\Environment".to_string(), var: "A".to_string() },
+            added: Vec::new(),
+            previous: None,
+            blocks: Vec::new(),
+            edit: None,
+        });
+        assert!(parse(&registry).is_some());
+        let file = registry.replacen("registry", "file", 1);
+        assert_eq!(parse(&file), None);
+        let (fewer, _) = registry.rsplit_once('\t').unwrap();
+        assert_eq!(parse(fewer), None);
+    }
+
+    #[test]
+    fn trimmed_to_the_newest() {
+        let dir = crate::testing::TempDir::new("trim");
+        let path = dir.path().join("journal");
+        let all: String = (0..KEEP + 5).map(|i| format!("{}\n", i)).collect();
+        fs::write(&path, all).unwrap();
+        trim(&path).unwrap();
+        let kept = fs::read_to_string(&path).unwrap();
+        assert_eq!(kept.lines().count(), KEEP);
+        assert_eq!(kept.lines().next(), Some("5"));
+        assert_eq!(kept.lines().last().map(String::from), Some((KEEP + 4).to_string()));
+        trim(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), kept);
+    }
+
+    // Writes `new` over `old` in the file at `path`
+    // and gives the change that records it
+    fn written(path: &Path, old: Option<&str>, new: Option<&str>) -> Change {
+        let (old, new) = (old.map(lines), new.map(lines));
+        match &new {
+            Some(new) => fs::write(path, new.iter().map(|line| format!("{}\n", line)).collect::<String>()).unwrap(),
+            None => drop(fs::remove_file(path)),
+        }
+        let (before, after) = (old.clone().unwrap_or_default(), new.clone().unwrap_or_default());
+        Change {
+            time: SystemTime::now(),
+            app: None,
+            place: Place::File(path.to_path_buf()),
+            added: Vec::new(),
+            previous: None,
+            blocks: Vec::new(),
+            edit: Some(FileEdit { checksum: new.as_deref().map(tamper::hash), created: old.is_none(), hunks: diff(&before, &after) }),
+        }
+    }
+
+    #[test]
+    fn revert_puts_back_changed_lines() {
+        let dir = crate::testing::TempDir::new("revert");
+        let path = dir.path().join("profile");
+        let change = written(&path, Some("a b c"), Some("a x c d"));
+        revert(&change, Writer::default()).unwrap();
+        assert_eq!(profile::read_lines(&path).unwrap(), lines("a b c"));
+        assert_eq!(fs::read_to_string(profile::backups(&path).unwrap().pop().unwrap()).unwrap(), "a\nx\nc\nd\n");
+    }
+
+    #[test]
+    fn revert_deletes_only_files_it_made() {
+        let dir = crate::testing::TempDir::new("revert-created");
+        let path = dir.path().join("profile");
+        let change = written(&path, None, Some("a b"));
+        revert(&change, Writer::default()).unwrap();
+        assert!(!path.exists());
+        let change = written(&path, Some("a"), None);
+        revert(&change, Writer::default()).unwrap();
+        assert_eq!(profile::read_lines(&path).unwrap(), lines("a"));
+    }
+
+    #[test]
+    fn revert_leaves_edited_files_alone() {
+        let dir = crate::testing::TempDir::new("revert-edited");
+        let path = dir.path().join("profile");
+        for (old, new) in [(Some("a"), Some("a b")), (None, Some("a b")), (Some("a b"), None)] {
+            let change = written(&path, old, new);
+            fs::write(&path, "a\nb\nmine\n").unwrap();
+            let e = revert(&change, Writer::default()).unwrap_err();
+            assert_eq!(Conflict::from_error(&e).map(|conflict| &conflict.place), Some(&change.place));
+            assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\nmine\n");
+        }
+    }
+
+    #[test]
+    fn broken_edits_dont_parse() {
+        for text in ["", "xyz\ncreated", "-\nmaybe", "-\ncreated\n0 1", "-\ncreated\n0 1 2\nonly one"] {
+            assert_eq!(parse_edit(text), None, "{:?}", text);
+        }
+    }
+}
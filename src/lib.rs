@@ -6,40 +6,184 @@
 //! // export DUMMY=1
 //! env_perm::check_or_set("DUMMY", 1).expect("Failed to find or set DUMMY");
 //! // Append $HOME/some/cool/bin to $PATH
-//! // export PATH= "$HOME/some/cool/bin:$PATH"
+//! // export PATH="$PATH:$HOME/some/cool/bin"
 //! env_perm::append("PATH", "$HOME/some/cool/bin").expect("Couldn't find PATH");
 //! // Sets a variable without checking if it exists.
-//! // Note you need to use a raw string literal to include ""
-//! // export DUMMY="/something"
-//! env_perm::set("DUMMY", r#""/something""#).expect("Failed to set DUMMY");
+//! // Values are quoted and escaped for you
+//! // export DUMMY="hello world"
+//! env_perm::set("DUMMY", "hello world").expect("Failed to set DUMMY");
 //! ```
+//!
+//! # Appending and prepending
+//! [`append`] always adds to the end of a list like `PATH`
+//! and [`prepend`] always adds to the front, on every platform.
+//! Entries earlier in `PATH` win so use `prepend` when your
+//! program should take precedence over what's installed already.
+//!
+//! | | profile | windows registry |
+//! |---|---|---|
+//! | `append("PATH", "/x")` | `export PATH="$PATH:/x"` | `current;/x` |
+//! | `prepend("PATH", "/x")` | `export PATH="/x:$PATH"` | `/x;current` |
+//!
+//! # Typed values
+//! Values can be anything that implements [`ToEnvValue`],
+//! which covers strings, numbers, `bool`s, paths and lists of them.
+//! Lists are joined with `:`, or `;` on windows.
+//! ```rust,no_run
+//! use std::path::PathBuf;
+//!
+//! // export VERBOSE=true
+//! env_perm::set("VERBOSE", true).expect("Failed to set VERBOSE");
+//! // export TOOL_DIRS=/opt/a/bin:/opt/b/bin
+//! let dirs = [PathBuf::from("/opt/a/bin"), PathBuf::from("/opt/b/bin")];
+//! env_perm::set("TOOL_DIRS", &dirs[..]).expect("Failed to set TOOL_DIRS");
+//! ```
+//!
+//! # What happened
+//! The functions that write return an [`Outcome`] saying
+//! where the variable went, so installers can tell the user.
+//! ```rust,no_run
+//! use env_perm::Outcome;
+//!
+//! match env_perm::check_or_set("DUMMY", 1).expect("Failed to set DUMMY") {
+//!     Outcome::AlreadySet => println!("DUMMY was already set"),
+//!     outcome => println!("Wrote DUMMY to {:?}", outcome),
+//! }
+//! ```
+//!
+//! With the `serde` feature [`Outcome`], [`Plan`], [`Shell`]
+//! and the other public types can be serialized, e.g. to
+//! report what an installer did as JSON.
 
-use std::io::{self, Write};
-use std::fs::{File, OpenOptions};
-use std::path::PathBuf;
-use std::env;
+use std::ffi::OsStr;
+use std::io;
 use std::fmt;
-use dirs;
+use std::path::{Path, PathBuf};
+
+mod app_conflict;
+mod backend;
+mod config;
+mod confirm;
+mod conflict;
+mod doctor;
+mod dotfiles;
+mod journal;
+mod lock;
+mod login_conf;
+mod manifest;
+mod outcome;
+mod pathext;
+mod plan;
+mod policy;
+mod profile;
+mod provenance;
+mod report;
+mod service;
+mod shell;
+mod snapshot;
+mod spec;
+mod state;
+mod sync;
+mod tamper;
+#[cfg(test)]
+mod testing;
+mod validate;
+mod user_hive;
+mod value;
+mod windows;
+mod wsl;
+mod wslenv;
+
+pub use crate::app_conflict::AppConflict;
+pub use crate::backend::{Backend, EnvBackend};
+pub use crate::config::{CheckMode, Config, MsysMode, Overrides, Quoting, Target};
+pub use crate::confirm::{Decision, PendingChange};
+pub use crate::conflict::Conflict;
+pub use crate::doctor::{Diagnosis, ProfileFile, RegistryAccess};
+pub use crate::dotfiles::{managed, DotfileManager, DotfilePolicy, Managed};
+pub use crate::journal::{history, journal_path, rollback_to, undo_last, Change, Place};
+pub use crate::outcome::Outcome;
+pub use crate::plan::{Edit, Plan};
+pub use crate::policy::{PolicyAction, PolicySetting};
+pub use crate::provenance::Provenance;
+pub use crate::report::ChangeReport;
+pub use crate::service::ServiceEnvironment;
+pub use crate::shell::Shell;
+pub use crate::snapshot::Snapshot;
+pub use crate::spec::{Difference, Spec, Verification};
+pub use crate::state::{ManagedVar, State};
+pub use crate::sync::{migrate, Sync, Written};
+pub use crate::tamper::BlockStatus;
+pub use crate::user_hive::UserHive;
+pub use crate::validate::Rejected;
+pub use crate::value::ToEnvValue;
+pub use crate::windows::{is_elevated, is_msys, RegistryType, RegistryValue, Scope, TooLong};
+pub use crate::wsl::is_wsl;
+
+/// Reports any [`Overrides`] the user has set
+/// in the environment, so installers can tell
+/// them where their variables are going.
+pub fn overrides() -> Overrides {
+    Overrides::from_env()
+}
+
+/// The file [`set`] would write to,
+/// e.g. `~/.bash_profile`.
+/// On windows variables go in the registry instead,
+/// see [`registry_key`].
+pub fn profile_path() -> io::Result<PathBuf> {
+    Config::new().profile_path()
+}
+
+/// The registry key [`set`] would write to on windows,
+/// `HKCU\Environment`, or None if variables go in a file.
+pub fn registry_key() -> Option<&'static str> {
+    Config::new().registry_key()
+}
 
 /// Checks if a environment variable is set.
 /// If it is then nothing will happen.
 /// If it's not then it will be added
 /// to your profile.
-pub fn check_or_set<T, U>(var: T, value: U) -> io::Result<()>
-where T: fmt::Display + AsRef<std::ffi::OsStr>,
-      U: fmt::Display,
+pub fn check_or_set<T, U>(var: T, value: U) -> io::Result<Outcome>
+where T: fmt::Display + AsRef<OsStr>,
+      U: ToEnvValue,
 {
-    env::var(&var)
-        .map(|_|())
-        .or_else(|_| set(var, value))
+    Config::new().check_or_set(var, value)
+}
+
+/// Appends a value to the end of an environment variable
+/// Useful for appending a value to PATH.
+/// Anything already in PATH with the same name wins.
+pub fn append<T: fmt::Display, U: ToEnvValue>(var: T, value: U) -> io::Result<Outcome> {
+    Config::new().append(var, value)
+}
+
+/// Like [`append`] with `separator` between the
+/// entries, for lists that aren't separated with `:`.
+/// ```no_run
+/// // export APP_PLUGINS="$APP_PLUGINS,extra"
+/// env_perm::append_with("APP_PLUGINS", "extra", ',')
+///     .expect("Failed to append to APP_PLUGINS");
+/// ```
+pub fn append_with<T: fmt::Display, U: ToEnvValue>(var: T, value: U, separator: char) -> io::Result<Outcome> {
+    Config::new().append_with(var, value, separator)
+}
+
+/// Like [`append`] but does nothing if the value is
+/// already in the variable, either in the current
+/// environment or in your profile.
+/// Use this for PATH entries so running an
+/// installer twice doesn't add them twice.
+pub fn append_unique<T: fmt::Display, U: ToEnvValue>(var: T, value: U) -> io::Result<Outcome> {
+    Config::new().append_unique(var, value)
 }
 
-/// Appends a value to an environment variable
-/// Useful for appending a value to PATH
-pub fn append<T: fmt::Display>(var: T, value: T) -> io::Result<()> {
-    let mut profile = get_profile()?;
-    writeln!(profile, "\nexport {}=\"{}:${}\"", var, value, var)?;
-    profile.flush()
+/// Prepends a value to the front of an environment variable
+/// Useful when your program should win over anything
+/// with the same name already in PATH.
+pub fn prepend<T: fmt::Display, U: ToEnvValue>(var: T, value: U) -> io::Result<Outcome> {
+    Config::new().prepend(var, value)
 }
 
 /// Sets an environment variable without checking
@@ -48,39 +192,807 @@ pub fn append<T: fmt::Display>(var: T, value: T) -> io::Result<()> {
 /// assignments in your profile.
 /// It's recommended to use `check_or_set`
 /// unless you are certain it doesn't exist.
-pub fn set<T: fmt::Display, U: fmt::Display>(var: T, value: U) -> io::Result<()> {
-    let mut profile = get_profile()?;
-    writeln!(profile, "\nexport {}={}", var, value)?;
-    profile.flush()
-}
-
-fn get_profile() -> io::Result<File> {
-    dirs::home_dir()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "No home directory"))
-        .and_then(find_profile)
-}
-
-#[cfg(target_family = "unix")]
-fn find_profile(mut profile: PathBuf) -> io::Result<File> {
-    profile.push(".bash_profile");
-    let mut oo = OpenOptions::new();
-    oo.append(true)
-        .create(false);
-    oo.open(profile.clone())
-        .or_else(|_|{
-            profile.pop();
-            profile.push(".bash_login");
-            oo.open(profile.clone())
-        })
-        .or_else(|_|{
-            profile.pop();
-            profile.push(".profile");
-            oo.open(profile.clone())
-        })
-        .or_else(|_|{
-            profile.pop();
-            profile.push(".bash_profile");
-            oo.create(true);
-            oo.open(profile.clone())
-        })
+///
+/// Values are quoted and escaped for the shell so
+/// spaces, quotes and `$` come through as written,
+/// while references like `$HOME` still expand.
+/// Quotes wrapped around the whole value are kept
+/// as part of it too, [`Quoting::Prequoted`] writes
+/// those as they are like older versions did.
+/// [`Config::quoting`] picks other ways of quoting.
+///
+/// The [`Outcome`] has the value that was persisted
+/// before, see [`Outcome::previous`].
+///
+/// Values can span several lines, the newlines are
+/// escaped where the shell can do it. Plain `sh` and
+/// csh profiles can't so those fail with
+/// [`InvalidInput`](io::ErrorKind::InvalidInput).
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_set_escaped.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// env_perm::set_in(&path, "GREETING", "hello world$").unwrap();
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\nexport GREETING=\"hello world\\$\"\n");
+/// ```
+pub fn set<T: fmt::Display, U: ToEnvValue>(var: T, value: U) -> io::Result<Outcome> {
+    Config::new().set(var, value)
+}
+
+/// Like [`set`] for a name that's an `OsStr`,
+/// e.g. one from [`std::env::vars_os`].
+///
+/// Values don't have to be UTF-8 either.
+/// bash, zsh, ksh and fish profiles and the windows
+/// registry can store any value, the rest fail with
+/// [`io::ErrorKind::InvalidData`] if it isn't UTF-8.
+/// ```no_run
+/// for (var, value) in std::env::vars_os() {
+///     if var.to_string_lossy().starts_with("MYAPP_") {
+///         env_perm::set_os(&var, &value).expect("Failed to persist");
+///     }
+/// }
+/// ```
+pub fn set_os<T: AsRef<OsStr>, U: ToEnvValue>(var: T, value: U) -> io::Result<Outcome> {
+    Config::new().set_os(var, value)
+}
+
+/// Like [`append`] for a name that's an `OsStr`,
+/// see [`set_os`].
+pub fn append_os<T: AsRef<OsStr>, U: ToEnvValue>(var: T, value: U) -> io::Result<Outcome> {
+    Config::new().append_os(var, value)
+}
+
+/// Like [`prepend`] for a name that's an `OsStr`,
+/// see [`set_os`].
+pub fn prepend_os<T: AsRef<OsStr>, U: ToEnvValue>(var: T, value: U) -> io::Result<Outcome> {
+    Config::new().prepend_os(var, value)
+}
+
+/// Persists the value a variable has right now,
+/// e.g. to keep `SSH_AUTH_SOCK` from this session
+/// for new shells.
+/// It's an error if the variable isn't set.
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_persist_current.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// std::env::set_var("ENV_PERM_PROFILE", &path);
+/// std::env::set_var("SESSION_DIR", "/tmp/session");
+/// env_perm::persist_current("SESSION_DIR").unwrap();
+/// let persisted = env_perm::get_persistent("SESSION_DIR").unwrap();
+/// assert_eq!(persisted.as_deref(), Some("/tmp/session"));
+/// ```
+pub fn persist_current<T: AsRef<OsStr>>(var: T) -> io::Result<Outcome> {
+    Config::new().persist_current(var)
+}
+
+/// Persists every variable from this session whose
+/// name starts with `prefix`, e.g. all the `MYAPP_`
+/// settings a tool reads, and gives their names.
+/// ```no_run
+/// let saved = env_perm::persist_matching("MYAPP_").expect("Failed to persist");
+/// println!("Saved {}", saved.join(", "));
+/// ```
+pub fn persist_matching(prefix: &str) -> io::Result<Vec<String>> {
+    Config::new().persist_matching(prefix)
+}
+
+/// The value of an environment variable as it's
+/// persisted in your profile, or in the registry on
+/// windows. This is what new shells will get, which can
+/// differ from [`std::env::var`] in this one.
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_get_persistent.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// // Send everything to a scratch file
+/// std::env::set_var("ENV_PERM_PROFILE", &path);
+/// env_perm::set("DUMMY", "/a").unwrap();
+/// env_perm::append("DUMMY", "/b").unwrap();
+/// let persisted = env_perm::get_persistent("DUMMY").unwrap();
+/// assert_eq!(persisted.as_deref(), Some("/a:/b"));
+/// ```
+pub fn get_persistent<T: fmt::Display>(var: T) -> io::Result<Option<String>> {
+    Config::new().get_persistent(var)
+}
+
+/// Every variable persisted in your profile, or in
+/// `HKCU\Environment` on windows, and its value.
+/// ```no_run
+/// for (var, value) in env_perm::list_persistent().unwrap() {
+///     println!("{}={}", var, value);
+/// }
+/// ```
+pub fn list_persistent() -> io::Result<Vec<(String, String)>> {
+    Config::new().list_persistent()
+}
+
+/// Every variable in the windows registry for `scope`
+/// and its type, see [`Config::registry_values`].
+/// ```no_run
+/// use env_perm::Scope;
+///
+/// for value in env_perm::registry_values(Scope::Machine).unwrap() {
+///     println!("{}={} ({:?})", value.var, value.value, value.kind);
+/// }
+/// ```
+pub fn registry_values(scope: Scope) -> io::Result<Vec<RegistryValue>> {
+    Config::new().scope(scope).registry_values()
+}
+
+/// Who wrote the lines in your profile that say so,
+/// see [`Config::annotate`].
+/// ```no_run
+/// for line in env_perm::provenance().unwrap() {
+///     println!("{} was added by {:?} on {}", line.var, line.app, line.date);
+/// }
+/// ```
+pub fn provenance() -> io::Result<Vec<Provenance>> {
+    Config::new().provenance()
+}
+
+/// Whether an environment variable is persisted in
+/// your profile, or in the registry on windows.
+/// Unlike checking [`std::env::var`] this ignores
+/// anything only set for the current session.
+pub fn exists_persistent<T: fmt::Display>(var: T) -> io::Result<bool> {
+    Config::new().exists_persistent(var)
+}
+
+/// Adds a line that isn't a variable to your profile,
+/// e.g. to hook a tool into the shell.
+/// Like [`check_or_set`] it's only added once however
+/// many times it's called.
+/// Windows has no profile for it to go in so
+/// there it fails unless `ENV_PERM_PROFILE` is set.
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_ensure_line.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// std::env::set_var("ENV_PERM_PROFILE", &path);
+/// env_perm::ensure_line("eval \"$(tool init bash)\"").unwrap();
+/// env_perm::ensure_line("eval \"$(tool init bash)\"").unwrap();
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\neval \"$(tool init bash)\"\n");
+/// ```
+pub fn ensure_line<T: fmt::Display>(line: T) -> io::Result<Outcome> {
+    Config::new().ensure_line(line)
+}
+
+/// Makes your profile run the script at `path`,
+/// the way rustup adds `. "$HOME/.cargo/env"`.
+/// A tool can keep its own variables in that file and
+/// only touch the profile once, later calls do nothing.
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_source_file.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// std::env::set_var("ENV_PERM_PROFILE", &path);
+/// env_perm::source_file("$HOME/.mytool/env").unwrap();
+/// env_perm::source_file("$HOME/.mytool/env").unwrap();
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\n. \"$HOME/.mytool/env\"\n");
+/// ```
+pub fn source_file<P: AsRef<Path>>(path: P) -> io::Result<Outcome> {
+    Config::new().source_file(path)
+}
+
+/// Removes everything an app persisted with
+/// [`Config::app`], for uninstallers: the block it keeps
+/// in your profile and any other file it wrote to, and
+/// the entries it added to registry values.
+/// ```
+/// use env_perm::Config;
+///
+/// let dir = std::env::temp_dir().join("env_perm_uninstall_all");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// std::env::set_var("ENV_PERM_STATE_DIR", &dir);
+/// let path = dir.join("profile.sh");
+/// std::fs::create_dir_all(&dir).unwrap();
+/// std::fs::write(&path, "export OTHER=1\n").unwrap();
+/// let config = Config::new().app("myapp");
+/// config.set_in(&path, "MYAPP_HOME", "/opt/myapp").unwrap();
+/// config.append_in(&path, "PATH", "/opt/myapp/bin").unwrap();
+/// env_perm::uninstall_all("myapp").unwrap();
+/// assert_eq!(std::fs::read_to_string(&path).unwrap(), "export OTHER=1\n");
+/// ```
+pub fn uninstall_all<T: fmt::Display>(app: T) -> io::Result<Vec<Place>> {
+    Config::new().uninstall_all(app)
+}
+
+/// Whether `app`'s block in your profile has been edited
+/// by hand since env_perm last wrote it.
+/// See [`Config::block_status`].
+pub fn block_status<T: fmt::Display>(app: T) -> io::Result<Vec<(PathBuf, BlockStatus)>> {
+    Config::new().block_status(app)
+}
+
+/// Like [`block_status`] but looks at `path`
+/// instead of the discovered profile.
+/// ```
+/// use env_perm::{BlockStatus, Config};
+///
+/// let dir = std::env::temp_dir().join("env_perm_block_status_in");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// std::env::set_var("ENV_PERM_STATE_DIR", &dir);
+/// let path = dir.join("profile.sh");
+/// Config::new().app("myapp").set_in(&path, "MYAPP_HOME", "/opt/myapp").unwrap();
+/// assert_eq!(env_perm::block_status_in(&path, "myapp").unwrap(), BlockStatus::Intact);
+/// let edited = std::fs::read_to_string(&path).unwrap().replace("/opt/myapp", "/srv/myapp");
+/// std::fs::write(&path, edited).unwrap();
+/// assert_eq!(env_perm::block_status_in(&path, "myapp").unwrap(), BlockStatus::Tampered);
+/// assert_eq!(env_perm::block_status_in(&path, "other").unwrap(), BlockStatus::Missing);
+/// ```
+pub fn block_status_in<P: AsRef<Path>, T: fmt::Display>(path: P, app: T) -> io::Result<BlockStatus> {
+    Config::new().block_status_in(path, app)
+}
+
+/// Every variable the blocks of more than one app
+/// in your profile assign to.
+/// See [`Config::app_conflicts`].
+pub fn app_conflicts() -> io::Result<Vec<AppConflict>> {
+    Config::new().app_conflicts()
+}
+
+/// Like [`app_conflicts`] but looks at `path`
+/// instead of the discovered profile.
+/// ```
+/// use env_perm::Config;
+///
+/// let path = std::env::temp_dir().join("env_perm_app_conflicts_in.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// Config::new().app("jdk8").set_in(&path, "JAVA_HOME", "/opt/jdk8").unwrap();
+/// let e = Config::new().app("jdk17").set_in(&path, "JAVA_HOME", "/opt/jdk17").unwrap_err();
+/// assert!(env_perm::AppConflict::from_error(&e).is_some());
+///
+/// Config::new().app("jdk17").allow_app_conflicts(true).set_in(&path, "JAVA_HOME", "/opt/jdk17").unwrap();
+/// let conflicts = env_perm::app_conflicts_in(&path).unwrap();
+/// assert_eq!(conflicts[0].var, "JAVA_HOME");
+/// assert_eq!(conflicts[0].apps, vec![
+///     ("jdk8".to_string(), "/opt/jdk8".to_string()),
+///     ("jdk17".to_string(), "/opt/jdk17".to_string()),
+/// ]);
+/// ```
+pub fn app_conflicts_in<P: AsRef<Path>>(path: P) -> io::Result<Vec<AppConflict>> {
+    Config::new().app_conflicts_in(path)
+}
+
+/// Reads the TOML manifest at `path` and writes whatever
+/// it describes that isn't persisted yet, so a team can
+/// keep one manifest in their repo and everyone can apply
+/// it as often as they like. See [`Spec::from_toml`] for
+/// what goes in it and [`Config::apply_spec`] for more.
+/// ```
+/// let dir = std::env::temp_dir().join("env_perm_apply_spec");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// std::fs::create_dir_all(&dir).unwrap();
+/// let manifest = dir.join("env.toml");
+/// std::fs::write(&manifest, "[set]\nMYAPP_HOME = \"/opt/myapp\"\n\n[append]\nPATH = [\"/opt/myapp/bin\"]\n").unwrap();
+/// let profile = dir.join("profile.sh");
+/// std::env::set_var("ENV_PERM_PROFILE", &profile);
+/// assert!(env_perm::apply_spec(&manifest).unwrap().changed());
+/// assert!(!env_perm::apply_spec(&manifest).unwrap().changed());
+/// ```
+pub fn apply_spec<P: AsRef<Path>>(path: P) -> io::Result<Outcome> {
+    Config::new().apply_spec(&Spec::read(path)?)
+}
+
+/// Looks at where variables would be persisted with the
+/// default [`Config`], for apps to show when something's
+/// not working. See [`Config::doctor`].
+pub fn doctor() -> Diagnosis {
+    Config::new().doctor()
+}
+
+/// Everything env_perm has persisted on this machine as
+/// the journal records it: each variable, where it is,
+/// what it's set to there now and who wrote it when.
+/// See [`Config::export_state`].
+/// ```
+/// let dir = std::env::temp_dir().join("env_perm_export_state");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// std::env::set_var("ENV_PERM_STATE_DIR", &dir);
+/// let path = dir.join("profile.sh");
+/// env_perm::Config::new().app("myapp").set_in(&path, "MYAPP_HOME", "/opt/myapp").unwrap();
+/// let state = env_perm::export_state().unwrap();
+/// assert_eq!(state.vars[0].var, "MYAPP_HOME");
+/// assert_eq!(state.vars[0].value.as_deref(), Some("/opt/myapp"));
+/// assert_eq!(state.vars[0].app.as_deref(), Some("myapp"));
+/// ```
+pub fn export_state() -> io::Result<State> {
+    Config::new().export_state()
+}
+
+/// Writes what another machine's [`export_state`] gave
+/// where variables go on this one, translating lists and
+/// references between profiles and the registry.
+/// See [`Config::import_state`].
+/// ```
+/// use env_perm::{Backend, ManagedVar, Place, Shell, State};
+///
+/// let dir = std::env::temp_dir().join("env_perm_import_state");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// std::env::set_var("ENV_PERM_STATE_DIR", &dir);
+/// let profile = dir.join("profile.sh");
+/// std::env::set_var("ENV_PERM_PROFILE", &profile);
+/// let state = State {
+///     vars: vec![ManagedVar {
+///         var: "PATH".to_string(),
+///         value: Some("%USERPROFILE%/bin;%USERPROFILE%/tools".to_string()),
+///         place: Place::Registry { key: r"HKCU\Environment".to_string(), var: "PATH".to_string() },
+///         backend: Backend::WindowsRegistry,
+///         app: None,
+///         written: std::time::SystemTime::now(),
+///     }],
+/// };
+/// env_perm::import_state(&state).unwrap();
+/// assert!(std::fs::read_to_string(&profile).unwrap().contains("$HOME/bin"));
+/// ```
+pub fn import_state(state: &State) -> io::Result<Outcome> {
+    Config::new().import_state(state)
+}
+
+/// Copies every profile and, on windows, the registry
+/// environment, and saves the copy to `path`, for
+/// [`restore`] to put back if a risky setup script goes
+/// wrong. See [`Config::snapshot`].
+/// ```
+/// let dir = std::env::temp_dir().join("env_perm_snapshot");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// std::env::set_var("ENV_PERM_STATE_DIR", &dir);
+/// let profile = dir.join("profile.sh");
+/// std::env::set_var("ENV_PERM_PROFILE", &profile);
+/// env_perm::set("KEPT", 1).unwrap();
+/// env_perm::snapshot(dir.join("env.snapshot")).unwrap();
+/// env_perm::set("DUMMY", 1).unwrap();
+/// let snapshot = env_perm::Snapshot::read(dir.join("env.snapshot")).unwrap();
+/// env_perm::restore(&snapshot).unwrap();
+/// assert_eq!(std::fs::read_to_string(&profile).unwrap(), "\nexport KEPT=1\n");
+/// ```
+pub fn snapshot<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+    Config::new().snapshot(path)
+}
+
+/// Puts back everything in `snapshot`,
+/// see [`Config::restore`].
+pub fn restore(snapshot: &Snapshot) -> io::Result<Vec<Place>> {
+    Config::new().restore(snapshot)
+}
+
+/// Checks what's persisted against `spec` with
+/// the default [`Config`], e.g. for a `doctor` command.
+/// ```
+/// use env_perm::Spec;
+///
+/// let path = std::env::temp_dir().join("env_perm_verify.sh");
+/// std::fs::write(&path, "export MYAPP_HOME=/opt/old\nexport PATH=\"$PATH:/usr/local/bin\"\n").unwrap();
+/// std::env::set_var("ENV_PERM_PROFILE", &path);
+/// let spec = Spec::new()
+///     .var("MYAPP_HOME", "/opt/myapp")
+///     .append("PATH", "/opt/myapp/bin");
+/// let verification = env_perm::verify(&spec).unwrap();
+/// assert_eq!(verification.missing, [("PATH".to_string(), "/opt/myapp/bin".to_string())]);
+/// assert_eq!(verification.differing[0].actual, "/opt/old");
+/// assert_eq!(verification.extra, [("PATH".to_string(), "/usr/local/bin".to_string())]);
+/// ```
+pub fn verify(spec: &Spec) -> io::Result<Verification> {
+    Config::new().verify(spec)
+}
+
+/// Works out what `f` would write with the default
+/// [`Config`] without writing any of it, as a [`Plan`]
+/// to show the user and [`apply`](Plan::apply) later.
+/// ```no_run
+/// let plan = env_perm::plan(|config| {
+///     config.set("MYAPP_HOME", "/opt/myapp")?;
+///     config.append("PATH", "/opt/myapp/bin")?;
+///     Ok(())
+/// })
+/// .unwrap();
+/// print!("{}", plan);
+/// plan.apply().expect("Failed to write the plan");
+/// ```
+pub fn plan<F: FnOnce(&Config) -> io::Result<()>>(f: F) -> io::Result<Plan> {
+    Config::new().plan(f)
+}
+
+/// Puts your profile back the way it was before its last
+/// change, from the backup made before every write.
+/// See [`Config::backups`] for how many are kept.
+/// ```no_run
+/// env_perm::set("DUMMY", "oops").unwrap();
+/// env_perm::restore_backup().expect("Failed to restore the profile");
+/// ```
+pub fn restore_backup() -> io::Result<Vec<PathBuf>> {
+    Config::new().restore_backup()
+}
+
+/// Sets several environment variables at once,
+/// writing your profile once rather than once for each.
+/// On windows the change is broadcast once at the end.
+/// ```no_run
+/// env_perm::set_many(vec![("APP_HOME", "/opt/app"), ("APP_LOG", "debug")])
+///     .expect("Failed to set variables");
+/// ```
+pub fn set_many<I, T, U>(vars: I) -> io::Result<()>
+where I: IntoIterator<Item = (T, U)>,
+      T: fmt::Display,
+      U: ToEnvValue,
+{
+    Config::new().set_many(vars)
+}
+
+/// Sets an environment variable, rewriting the
+/// existing assignment in your profile if there is one.
+/// Unlike [`set`] you won't end up with two.
+/// [`Outcome::previous`] is the value it replaced.
+pub fn update<T: fmt::Display, U: ToEnvValue>(var: T, value: U) -> io::Result<Outcome> {
+    Config::new().update(var, value)
+}
+
+/// Removes an environment variable that was set
+/// with [`set`] or [`append`].
+/// Every assignment to it in your profile is
+/// deleted, on windows it's deleted from the user's
+/// registry environment and the change is broadcast.
+/// It stays set in shells that are already running.
+pub fn unset<T: fmt::Display>(var: T) -> io::Result<()> {
+    Config::new().unset(var)
+}
+
+/// Takes one entry out of a list like PATH,
+/// e.g. when uninstalling a tool that appended
+/// its `bin` directory.
+/// Other entries and assignments are left alone.
+pub fn remove_from<T: fmt::Display, U: ToEnvValue>(var: T, value: U) -> io::Result<()> {
+    Config::new().remove_from(var, value)
+}
+
+/// Removes repeated entries from a list like PATH,
+/// keeping the first copy of each.
+/// Running an installer that uses [`append`] over and
+/// over leaves lots of these behind.
+pub fn dedupe<T: fmt::Display>(var: T) -> io::Result<()> {
+    Config::new().dedupe(var)
+}
+
+/// Moves a persisted variable to a new name,
+/// keeping its value.
+/// In your profile the lines are rewritten where they
+/// are, on windows the registry value is copied
+/// and the old one deleted.
+pub fn rename<T: fmt::Display, U: fmt::Display>(from: T, to: U) -> io::Result<()> {
+    Config::new().rename(from, to)
+}
+
+/// Adds `entry`, like `GOPATH/l`, to `WSLENV` so the
+/// variable is shared between windows and WSL, see
+/// [`Config::add_wslenv`] for the flags. It's only
+/// added once, an entry for the same variable with
+/// other flags gets these instead.
+/// ```no_run
+/// env_perm::set("GOPATH", r"C:\Users\me\go").unwrap();
+/// env_perm::add_wslenv("GOPATH/p").expect("Failed to share GOPATH with WSL");
+/// ```
+pub fn add_wslenv<T: fmt::Display>(entry: T) -> io::Result<Outcome> {
+    Config::new().add_wslenv(entry)
+}
+
+/// Takes the entry for `var` out of `WSLENV`.
+pub fn remove_wslenv<T: fmt::Display>(var: T) -> io::Result<()> {
+    Config::new().remove_wslenv(var)
+}
+
+/// Adds `extension`, like `.LUA`, to `PATHEXT` so windows
+/// runs those files by name, see [`Config::add_pathext`].
+/// It's only added once whatever its case.
+/// ```no_run
+/// env_perm::add_pathext(".lua").expect("Failed to add .LUA to PATHEXT");
+/// ```
+pub fn add_pathext<T: fmt::Display>(extension: T) -> io::Result<Outcome> {
+    Config::new().add_pathext(extension)
+}
+
+/// Takes `extension` out of `PATHEXT`.
+pub fn remove_pathext<T: fmt::Display>(extension: T) -> io::Result<()> {
+    Config::new().remove_pathext(extension)
+}
+
+/// Like [`ensure_line`] but writes to `path`
+/// instead of the discovered profile.
+pub fn ensure_line_in<P: AsRef<Path>, T: fmt::Display>(path: P, line: T) -> io::Result<Outcome> {
+    Config::new().ensure_line_in(path, line)
+}
+
+/// Like [`source_file`] but writes to `profile`
+/// instead of the discovered profile.
+pub fn source_file_in<P: AsRef<Path>, Q: AsRef<Path>>(profile: P, path: Q) -> io::Result<Outcome> {
+    Config::new().source_file_in(profile, path)
+}
+
+/// Like [`restore_backup`] but restores `path`
+/// instead of the discovered profile.
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_restore_backup.sh");
+/// std::fs::write(&path, "export OTHER=1\n").unwrap();
+/// env_perm::set_in(&path, "DUMMY", 1).unwrap();
+/// assert!(env_perm::restore_backup_in(&path).unwrap());
+/// assert_eq!(std::fs::read_to_string(&path).unwrap(), "export OTHER=1\n");
+/// ```
+pub fn restore_backup_in<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    Config::new().restore_backup_in(path)
+}
+
+/// Like [`check_or_set`] but writes to `path`
+/// instead of the discovered profile.
+/// The syntax is picked from the file name,
+/// e.g. `.fish` files get `set -gx`,
+/// falling back to the detected shell.
+pub fn check_or_set_in<P, T, U>(path: P, var: T, value: U) -> io::Result<Outcome>
+where P: AsRef<Path>,
+      T: fmt::Display + AsRef<OsStr>,
+      U: ToEnvValue,
+{
+    Config::new().check_or_set_in(path, var, value)
+}
+
+/// Like [`append`] but writes to `path`
+/// instead of the discovered profile.
+/// The syntax is picked the same way as [`check_or_set_in`].
+/// ```
+/// let path = std::env::temp_dir().join(format!("env_perm_append_in-{}.sh", std::process::id()));
+/// # let _ = std::fs::remove_file(&path);
+/// env_perm::append_in(&path, "PATH", "/opt/a/bin").unwrap();
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\nexport PATH=\"$PATH:/opt/a/bin\"\n");
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn append_in<P: AsRef<Path>, T: fmt::Display, U: ToEnvValue>(path: P, var: T, value: U) -> io::Result<Outcome> {
+    Config::new().append_in(path, var, value)
+}
+
+/// Like [`prepend`] but writes to `path`
+/// instead of the discovered profile.
+/// The syntax is picked the same way as [`check_or_set_in`].
+/// ```
+/// let path = std::env::temp_dir().join(format!("env_perm_prepend_in-{}.sh", std::process::id()));
+/// # let _ = std::fs::remove_file(&path);
+/// env_perm::prepend_in(&path, "PATH", "/opt/a/bin").unwrap();
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\nexport PATH=\"/opt/a/bin:$PATH\"\n");
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn prepend_in<P: AsRef<Path>, T: fmt::Display, U: ToEnvValue>(path: P, var: T, value: U) -> io::Result<Outcome> {
+    Config::new().prepend_in(path, var, value)
+}
+
+/// Like [`set`] but writes to `path`
+/// instead of the discovered profile.
+/// The syntax is picked the same way as [`check_or_set_in`].
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_set_in.sh");
+/// env_perm::set_in(&path, "DUMMY", 1).expect("Failed to set DUMMY");
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert!(profile.ends_with("\nexport DUMMY=1\n"));
+/// ```
+pub fn set_in<P: AsRef<Path>, T: fmt::Display, U: ToEnvValue>(path: P, var: T, value: U) -> io::Result<Outcome> {
+    Config::new().set_in(path, var, value)
+}
+
+/// Like [`set_many`] but writes to `path`
+/// instead of the discovered profile.
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_set_many_in.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// env_perm::set_many_in(&path, vec![("A", 1), ("B", 2)]).unwrap();
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\nexport A=1\nexport B=2\n");
+/// ```
+pub fn set_many_in<P, I, T, U>(path: P, vars: I) -> io::Result<()>
+where P: AsRef<Path>,
+      I: IntoIterator<Item = (T, U)>,
+      T: fmt::Display,
+      U: ToEnvValue,
+{
+    Config::new().set_many_in(path, vars)
+}
+
+/// The Group Policy preferences on this machine that set
+/// or delete `var`, for the machine or the user. Windows
+/// applies them again at each refresh, so after writing
+/// the variable an installer can warn that it may not
+/// stick and say why. It's empty off windows.
+/// ```no_run
+/// env_perm::set("JAVA_HOME", r"C:\Program Files\Java\jdk-17").unwrap();
+/// for setting in env_perm::policy_managed("JAVA_HOME").unwrap() {
+///     eprintln!("warning: {}", setting);
+/// }
+/// ```
+pub fn policy_managed<T: fmt::Display>(var: T) -> io::Result<Vec<PolicySetting>> {
+    policy::find(&var.to_string())
+}
+
+/// Like [`add_wslenv`] but edits `path`
+/// instead of the discovered profile. It always writes
+/// a profile-style `export WSLENV=...` line to `path`,
+/// starting from this process's value if `path`
+/// doesn't set `WSLENV` yet.
+/// ```
+/// use env_perm::Outcome;
+///
+/// let path = std::env::temp_dir().join("env_perm_add_wslenv_in.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// env_perm::add_wslenv_in(&path, "GOPATH/l").unwrap();
+/// env_perm::add_wslenv_in(&path, "MYAPP_HOME/p").unwrap();
+/// assert_eq!(env_perm::add_wslenv_in(&path, "GOPATH/l").unwrap(), Outcome::AlreadySet);
+/// env_perm::add_wslenv_in(&path, "MYAPP_HOME/pu").unwrap();
+/// env_perm::remove_wslenv_in(&path, "GOPATH").unwrap();
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\nexport WSLENV=MYAPP_HOME/pu\n");
+/// ```
+pub fn add_wslenv_in<P: AsRef<Path>, T: fmt::Display>(path: P, entry: T) -> io::Result<Outcome> {
+    Config::new().add_wslenv_in(path, entry)
+}
+
+/// Like [`remove_wslenv`] but edits `path`
+/// instead of the discovered profile, rewriting
+/// the `export WSLENV=...` line there.
+pub fn remove_wslenv_in<P: AsRef<Path>, T: fmt::Display>(path: P, var: T) -> io::Result<()> {
+    Config::new().remove_wslenv_in(path, var)
+}
+
+/// Like [`add_pathext`] but edits `path`
+/// instead of the discovered profile. It always writes
+/// a profile-style `export PATHEXT=...` line to `path`,
+/// starting from this process's value if `path`
+/// doesn't set `PATHEXT` yet.
+/// ```
+/// use env_perm::Outcome;
+///
+/// let path = std::env::temp_dir().join("env_perm_add_pathext_in.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// std::env::set_var("PATHEXT", ".COM;.EXE;;.bat");
+/// env_perm::add_pathext_in(&path, "lua").unwrap();
+/// assert_eq!(env_perm::add_pathext_in(&path, ".Lua").unwrap(), Outcome::AlreadySet);
+/// assert_eq!(env_perm::add_pathext_in(&path, ".EXE").unwrap(), Outcome::AlreadySet);
+/// env_perm::add_pathext_in(&path, ".PY").unwrap();
+/// env_perm::remove_pathext_in(&path, ".exe").unwrap();
+/// assert!(env_perm::add_pathext_in(&path, ".L;UA").is_err());
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\nexport PATHEXT=\".COM;.bat;.LUA;.PY\"\n");
+/// ```
+pub fn add_pathext_in<P: AsRef<Path>, T: fmt::Display>(path: P, extension: T) -> io::Result<Outcome> {
+    Config::new().add_pathext_in(path, extension)
+}
+
+/// Like [`remove_pathext`] but edits `path`
+/// instead of the discovered profile, rewriting
+/// the `export PATHEXT=...` line there.
+pub fn remove_pathext_in<P: AsRef<Path>, T: fmt::Display>(path: P, extension: T) -> io::Result<()> {
+    Config::new().remove_pathext_in(path, extension)
+}
+
+/// Like [`update`] but edits `path`
+/// instead of the discovered profile.
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_update_in.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// env_perm::set_in(&path, "DUMMY", 1).expect("Failed to set DUMMY");
+/// env_perm::update_in(&path, "DUMMY", 2).expect("Failed to update DUMMY");
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\nexport DUMMY=2\n");
+/// ```
+pub fn update_in<P: AsRef<Path>, T: fmt::Display, U: ToEnvValue>(path: P, var: T, value: U) -> io::Result<Outcome> {
+    Config::new().update_in(path, var, value)
+}
+
+/// Like [`unset`] but edits `path`
+/// instead of the discovered profile.
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_unset_in.sh");
+/// env_perm::set_in(&path, "DUMMY", 1).expect("Failed to set DUMMY");
+/// env_perm::unset_in(&path, "DUMMY").expect("Failed to unset DUMMY");
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert!(!profile.contains("DUMMY"));
+/// ```
+pub fn unset_in<P: AsRef<Path>, T: fmt::Display>(path: P, var: T) -> io::Result<()> {
+    Config::new().unset_in(path, var)
+}
+
+/// Like [`provenance`] but reads `path`
+/// instead of the discovered profile.
+/// ```
+/// use env_perm::Config;
+///
+/// let path = std::env::temp_dir().join("env_perm_provenance_in.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// Config::new().app("myapp").annotate(true).set_in(&path, "MYAPP_HOME", "/opt/myapp").unwrap();
+/// assert!(std::fs::read_to_string(&path).unwrap().contains("export MYAPP_HOME=/opt/myapp # added by myapp "));
+/// let lines = env_perm::provenance_in(&path).unwrap();
+/// assert_eq!(lines[0].var, "MYAPP_HOME");
+/// assert_eq!(lines[0].app.as_deref(), Some("myapp"));
+/// // The comment isn't part of the value
+/// let outcome = env_perm::update_in(&path, "MYAPP_HOME", "/opt/other").unwrap();
+/// assert_eq!(outcome.previous(), Some("/opt/myapp"));
+/// ```
+pub fn provenance_in<P: AsRef<Path>>(path: P) -> io::Result<Vec<Provenance>> {
+    Config::new().provenance_in(path)
+}
+
+/// Like [`remove_from`] but edits `path`
+/// instead of the discovered profile.
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_remove_from_in.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// env_perm::append_in(&path, "PATH", "/opt/a/bin").unwrap();
+/// env_perm::append_in(&path, "PATH", "/opt/b/bin").unwrap();
+/// env_perm::remove_from_in(&path, "PATH", "/opt/a/bin").unwrap();
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\nexport PATH=\"$PATH:/opt/b/bin\"\n");
+/// ```
+pub fn remove_from_in<P: AsRef<Path>, T: fmt::Display, U: ToEnvValue>(path: P, var: T, value: U) -> io::Result<()> {
+    Config::new().remove_from_in(path, var, value)
+}
+
+/// Like [`dedupe`] but edits `path`
+/// instead of the discovered profile.
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_dedupe_in.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// env_perm::append_in(&path, "PATH", "/opt/a/bin").unwrap();
+/// env_perm::append_in(&path, "PATH", "/opt/a/bin").unwrap();
+/// env_perm::dedupe_in(&path, "PATH").unwrap();
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\nexport PATH=\"$PATH:/opt/a/bin\"\n");
+/// ```
+pub fn dedupe_in<P: AsRef<Path>, T: fmt::Display>(path: P, var: T) -> io::Result<()> {
+    Config::new().dedupe_in(path, var)
+}
+
+/// Like [`rename`] but edits `path`
+/// instead of the discovered profile.
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_rename_in.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// env_perm::set_in(&path, "OLD_HOME", "/opt/app").unwrap();
+/// env_perm::rename_in(&path, "OLD_HOME", "APP_HOME").unwrap();
+/// let profile = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(profile, "\nexport APP_HOME=/opt/app\n");
+/// ```
+pub fn rename_in<P: AsRef<Path>, T: fmt::Display, U: fmt::Display>(path: P, from: T, to: U) -> io::Result<()> {
+    Config::new().rename_in(path, from, to)
+}
+
+/// Sets an environment variable in the profile
+/// of every shell that looks set up on this machine
+/// using each shell's own syntax.
+/// The detected shell is always included.
+/// Returns the files that were written to.
+///
+/// If `ENV_PERM_PROFILE` is set only that file is written.
+pub fn set_all_shells<T: fmt::Display, U: ToEnvValue>(var: T, value: U) -> io::Result<Vec<PathBuf>> {
+    Ok(files(Sync::new().var(var, value).apply()?))
+}
+
+/// Appends a value to an environment variable in
+/// the profile of every shell that looks set up on
+/// this machine using each shell's own syntax.
+/// The detected shell is always included.
+/// Returns the files that were written to.
+///
+/// If `ENV_PERM_PROFILE` is set only that file is written.
+pub fn append_all_shells<T: fmt::Display, U: ToEnvValue>(var: T, value: U) -> io::Result<Vec<PathBuf>> {
+    Ok(files(Sync::new().append(var, value).apply()?))
+}
+
+/// Prepends a value to an environment variable in
+/// the profile of every shell that looks set up on
+/// this machine using each shell's own syntax.
+/// The detected shell is always included.
+/// Returns the files that were written to.
+///
+/// If `ENV_PERM_PROFILE` is set only that file is written.
+pub fn prepend_all_shells<T: fmt::Display, U: ToEnvValue>(var: T, value: U) -> io::Result<Vec<PathBuf>> {
+    Ok(files(Sync::new().prepend(var, value).apply()?))
+}
+
+fn files(written: Vec<Written>) -> Vec<PathBuf> {
+    written.into_iter().map(|w| w.file).collect()
 }
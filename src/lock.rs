@@ -0,0 +1,132 @@
+//! Keeping installers that run at the same time
+//! from mixing up each other's edits.
+
+use std::cell::Cell;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+use crate::journal;
+
+thread_local! {
+    // How many `locked` calls this thread is inside
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Runs `f` holding an exclusive lock on the `lock` file
+/// in the state directory, waiting for any other process or
+/// thread holding it to finish. It's advisory, flock on
+/// unix and LockFileEx on windows, so it only keeps out
+/// others that take it too. Calls inside `f` reuse it.
+pub(crate) fn locked<T, F: FnOnce() -> io::Result<T>>(f: F) -> io::Result<T> {
+    if DEPTH.with(Cell::get) > 0 {
+        return f();
+    }
+    let file = open()?;
+    lock(&file)?;
+    let _held = Held::new();
+    let result = f();
+    // Closing the file releases the lock
+    // but an error there is worth hearing about
+    unlock(&file)?;
+    result
+}
+
+#[cfg(unix)]
+fn lock(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // Safety: the descriptor is open for as long as `file` is
+    while unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn unlock(file: &File) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // Safety: as for `lock`
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn lock(file: &File) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK};
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    let mut overlapped = OVERLAPPED::default();
+    // Safety: the handle is open for as long as `file` is
+    // and `overlapped` is a valid pointer for the call,
+    // which waits since the handle isn't asynchronous
+    let locked = unsafe { LockFileEx(file.as_raw_handle(), LOCKFILE_EXCLUSIVE_LOCK, 0, u32::MAX, u32::MAX, &mut overlapped) };
+    if locked == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn unlock(file: &File) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::UnlockFileEx;
+    use windows_sys::Win32::System::IO::OVERLAPPED;
+
+    let mut overlapped = OVERLAPPED::default();
+    // Safety: as for `lock`
+    if unsafe { UnlockFileEx(file.as_raw_handle(), 0, u32::MAX, u32::MAX, &mut overlapped) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Nothing else runs installers side by side
+#[cfg(not(any(unix, windows)))]
+fn lock(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn unlock(_file: &File) -> io::Result<()> {
+    Ok(())
+}
+
+// Marks this thread as holding the lock until
+// it's dropped, even if `f` panics
+struct Held;
+
+impl Held {
+    fn new() -> Held {
+        DEPTH.with(|depth| depth.set(depth.get() + 1));
+        Held
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+// The lock file, or one in the temp directory if the
+// state directory can't be made so writing a profile
+// doesn't fail just because there's nowhere to keep state
+fn open() -> io::Result<File> {
+    journal::state_dir()
+        .and_then(|dir| open_in(&dir))
+        .or_else(|_| open_in(&env::temp_dir().join("env_perm")))
+}
+
+fn open_in(dir: &Path) -> io::Result<File> {
+    fs::create_dir_all(dir)?;
+    OpenOptions::new().create(true).truncate(false).write(true).open(dir.join("lock"))
+}
@@ -0,0 +1,256 @@
+//! Persisting variables in `~/.login_conf` on FreeBSD.
+//!
+//! Variables live in the `setenv` capability of the
+//! `me` entry, which every login reads whatever the shell:
+//! ```text
+//! me:\
+//!     :setenv=FOO=bar,EDITOR=vi:
+//! ```
+
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::profile::{self, Writer};
+use crate::shell;
+use crate::EnvBackend;
+
+/// The `me` entry of `~/.login_conf`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LoginConf;
+
+impl EnvBackend for LoginConf {
+    fn set(&self, var: &str, value: &str) -> io::Result<()> {
+        let value = escape(value)?;
+        update(|vars| match vars.iter_mut().find(|(name, _)| name == var) {
+            Some(entry) => entry.1 = value,
+            None => vars.push((var.to_string(), value)),
+        })
+    }
+
+    /// login.conf can't refer to other variables so if
+    /// nothing is persisted yet the current value is copied in.
+    fn append(&self, var: &str, value: &str) -> io::Result<()> {
+        match self.current(var)? {
+            Some(current) if !current.is_empty() => self.set(var, &format!("{}:{}", current, value)),
+            _ => self.set(var, value),
+        }
+    }
+
+    fn append_with(&self, var: &str, value: &str, separator: char) -> io::Result<()> {
+        match self.current(var)? {
+            Some(current) if !current.is_empty() => {
+                self.set(var, &format!("{}{}{}", current, separator, value))
+            }
+            _ => self.set(var, value),
+        }
+    }
+
+    fn prepend(&self, var: &str, value: &str) -> io::Result<()> {
+        match self.current(var)? {
+            Some(current) if !current.is_empty() => self.set(var, &format!("{}:{}", value, current)),
+            _ => self.set(var, value),
+        }
+    }
+
+    fn remove(&self, var: &str) -> io::Result<()> {
+        update(|vars| vars.retain(|(name, _)| name != var))
+    }
+
+    fn get(&self, var: &str) -> io::Result<Option<String>> {
+        let lines = profile::read_lines(&path()?)?;
+        Ok(Entry::find(&lines)
+            .and_then(|entry| entry.vars().into_iter().find(|(name, _)| name == var))
+            .map(|(_, value)| unescape(&value)))
+    }
+
+    fn list(&self) -> io::Result<Vec<(String, String)>> {
+        let lines = profile::read_lines(&path()?)?;
+        Ok(Entry::find(&lines)
+            .map(|entry| entry.vars())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, value)| (name, unescape(&value)))
+            .collect())
+    }
+}
+
+impl LoginConf {
+    // What's persisted, or failing that
+    // what's in the environment now
+    fn current(&self, var: &str) -> io::Result<Option<String>> {
+        match self.get(var)? {
+            Some(current) => Ok(Some(current)),
+            None => Ok(env::var(var).ok()),
+        }
+    }
+}
+
+pub(crate) fn path() -> io::Result<PathBuf> {
+    if cfg!(any(target_os = "freebsd", target_os = "dragonfly")) {
+        Ok(shell::home_dir()?.join(".login_conf"))
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "~/.login_conf is only read on FreeBSD and DragonFly",
+        ))
+    }
+}
+
+/// The variables a write to `~/.login_conf` set, from
+/// the lines it took out and the lines it added.
+pub(crate) fn written(removed: &[String], added: &[String]) -> Vec<String> {
+    let before: Vec<(&str, &str)> = setenv(removed).collect();
+    setenv(added)
+        .filter(|pair| !before.contains(pair))
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+// The `name=value`s in the setenv capabilities among `lines`
+fn setenv(lines: &[String]) -> impl Iterator<Item = (&str, &str)> {
+    lines
+        .iter()
+        .filter_map(|line| {
+            line.trim()
+                .trim_start_matches(':')
+                .trim_end_matches('\\')
+                .trim_end_matches(':')
+                .strip_prefix("setenv=")
+        })
+        .flat_map(|list| list.split(',').filter_map(|var| var.split_once('=')))
+}
+
+// Edits the setenv list of the me entry,
+// creating the file and entry if needed
+fn update<F: FnOnce(&mut Vec<(String, String)>)>(f: F) -> io::Result<()> {
+    let path = path()?;
+    let mut lines = profile::read_lines(&path)?;
+    let (start, end, mut entry) = match Entry::find(&lines) {
+        Some(entry) => (entry.start, entry.end, entry),
+        None => {
+            let entry = Entry { start: lines.len(), end: lines.len(), names: "me".to_string(), caps: Vec::new() };
+            (entry.start, entry.end, entry)
+        }
+    };
+    let mut vars = entry.vars();
+    f(&mut vars);
+    entry.set_vars(&vars);
+    lines.splice(start..end, entry.render());
+    profile::write_lines(&path, &lines, Writer::default())?;
+    rebuild_db(&path)
+}
+
+// If there's a compiled database it's used in
+// preference to the text file so it has to be rebuilt
+fn rebuild_db(path: &Path) -> io::Result<()> {
+    if !path.with_file_name(".login_conf.db").exists() {
+        return Ok(());
+    }
+    let status = Command::new("cap_mkdb").arg(path).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other("cap_mkdb failed"))
+    }
+}
+
+// A record in the capability file
+struct Entry {
+    // The lines it spans
+    start: usize,
+    end: usize,
+    names: String,
+    caps: Vec<String>,
+}
+
+impl Entry {
+    fn find(lines: &[String]) -> Option<Entry> {
+        let mut i = 0;
+        while i < lines.len() {
+            let start = i;
+            let mut record = String::new();
+            loop {
+                let line = lines[i].trim_start();
+                i += 1;
+                match line.strip_suffix('\\') {
+                    Some(line) if i < lines.len() => record.push_str(line),
+                    _ => {
+                        record.push_str(line.trim_end_matches('\\'));
+                        break;
+                    }
+                }
+            }
+            if lines[start].starts_with(|c: char| c == '#' || c.is_whitespace()) || record.is_empty() {
+                continue;
+            }
+            let mut fields = record.split(':');
+            let names = fields.next().unwrap_or("");
+            if names.split('|').any(|name| name == "me") {
+                return Some(Entry {
+                    start,
+                    end: i,
+                    names: names.to_string(),
+                    caps: fields.filter(|cap| !cap.is_empty()).map(String::from).collect(),
+                });
+            }
+        }
+        None
+    }
+
+    fn vars(&self) -> Vec<(String, String)> {
+        self.caps
+            .iter()
+            .find_map(|cap| cap.strip_prefix("setenv="))
+            .map(|list| {
+                list.split(',')
+                    .filter_map(|var| var.split_once('='))
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn set_vars(&mut self, vars: &[(String, String)]) {
+        self.caps.retain(|cap| !cap.starts_with("setenv="));
+        if !vars.is_empty() {
+            let list: Vec<String> = vars.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+            self.caps.insert(0, format!("setenv={}", list.join(",")));
+        }
+    }
+
+    fn render(&self) -> Vec<String> {
+        if self.caps.is_empty() {
+            return Vec::new();
+        }
+        let mut lines = vec![format!("{}:\\", self.names)];
+        let last = self.caps.len() - 1;
+        for (i, cap) in self.caps.iter().enumerate() {
+            let end = if i == last { "" } else { "\\" };
+            lines.push(format!("\t:{}:{}", cap, end));
+        }
+        lines
+    }
+}
+
+// `:` separates capabilities so it's written in octal.
+// login.conf swaps `$` for the user name and `~` for the
+// home directory, so `$HOME` is turned into `~`.
+fn escape(value: &str) -> io::Result<String> {
+    if value.contains(',') || value.contains('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "~/.login_conf can't hold values with commas or newlines",
+        ));
+    }
+    Ok(value
+        .replace("${HOME}", "~")
+        .replace("$HOME", "~")
+        .replace('\\', "\\\\")
+        .replace(':', "\\072"))
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("\\072", ":").replace("\\\\", "\\")
+}
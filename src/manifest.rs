@@ -0,0 +1,467 @@
+//! Reading a [`Spec`] from a TOML manifest.
+//!
+//! Only the parts of TOML a manifest needs are read:
+//! tables, bare and quoted keys, basic and literal
+//! strings, arrays of them, numbers and booleans.
+//! Anything else, like multi-line strings, dotted keys
+//! or inline tables, is an error rather than misread,
+//! and so is a key or table that's there twice.
+
+use std::io;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+
+use crate::spec::{Spec, Wanted};
+use crate::{Shell, Target};
+
+// A value on the right of `=`
+enum Value {
+    Text(String),
+    List(Vec<String>),
+}
+
+/// Reads the manifest in `text`, see [`Spec::from_toml`].
+pub(crate) fn parse(text: &str) -> io::Result<Spec> {
+    let mut parser = Parser { chars: text.chars().peekable(), line: 1 };
+    let mut spec = Spec::new();
+    let mut table: Option<String> = None;
+    let mut tables: Vec<String> = Vec::new();
+    let mut keys: Vec<(Option<String>, String)> = Vec::new();
+    loop {
+        parser.skip_blank();
+        let line = parser.line;
+        match parser.peek() {
+            None => break,
+            Some('[') => {
+                parser.next();
+                if parser.peek() == Some('[') {
+                    return Err(parser.error("Arrays of tables aren't supported"));
+                }
+                parser.skip_space();
+                let name = parser.key()?;
+                parser.skip_space();
+                parser.expect(']')?;
+                parser.end_of_line()?;
+                if !["set", "append", "prepend"].contains(&name.as_str()) {
+                    return Err(error(line, format!("There's no [{}] table in a manifest", name)));
+                }
+                if tables.contains(&name) {
+                    return Err(error(line, format!("There's more than one [{}] table", name)));
+                }
+                tables.push(name.clone());
+                table = Some(name);
+            }
+            Some(_) => {
+                let key = parser.key()?;
+                parser.skip_space();
+                parser.expect('=')?;
+                parser.skip_space();
+                let value = parser.value()?;
+                parser.end_of_line()?;
+                let place = (table.clone(), key.clone());
+                if keys.contains(&place) {
+                    return Err(error(line, format!("{} is there more than once", key)));
+                }
+                keys.push(place);
+                spec = entry(spec, table.as_deref(), key, value).map_err(|e| error(line, e))?;
+            }
+        }
+    }
+    Ok(spec)
+}
+
+/// Reads the manifest at `path`.
+pub(crate) fn read(path: &Path) -> io::Result<Spec> {
+    parse(&std::fs::read_to_string(path)?).map_err(|e| {
+        io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+    })
+}
+
+fn error<T: std::fmt::Display>(line: usize, message: T) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {}", line, message))
+}
+
+// Adds `key = value` in `table` to `spec`
+fn entry(mut spec: Spec, table: Option<&str>, key: String, value: Value) -> Result<Spec, String> {
+    match (table, value) {
+        (Some("set"), Value::Text(value)) => spec.wanted.push(Wanted::Set(key, value)),
+        (Some("set"), Value::List(_)) => return Err(format!("{} can only be set to one value", key)),
+        (Some("append"), value) => {
+            spec.wanted.extend(entries(value).into_iter().map(|entry| Wanted::Append(key.clone(), entry)))
+        }
+        (Some("prepend"), value) => {
+            spec.wanted.extend(entries(value).into_iter().map(|entry| Wanted::Prepend(key.clone(), entry)))
+        }
+        (_, Value::Text(app)) if key == "app" => spec.app = Some(app),
+        (_, value) if key == "shells" => {
+            for name in entries(value) {
+                match Shell::from_path(Path::new(&name)) {
+                    Some(shell) => spec.shells.push(shell),
+                    None => return Err(format!("{:?} isn't a shell env_perm knows", name)),
+                }
+            }
+        }
+        (_, Value::Text(target)) if key == "target" => {
+            spec.target = Some(match target.as_str() {
+                "login" => Target::LoginProfile,
+                "interactive" => Target::InteractiveRc,
+                "both" => Target::Both,
+                _ => return Err(format!("The target has to be login, interactive or both, not {:?}", target)),
+            })
+        }
+        _ => return Err(format!("{} isn't something a manifest can have", key)),
+    }
+    Ok(spec)
+}
+
+fn entries(value: Value) -> Vec<String> {
+    match value {
+        Value::Text(entry) => vec![entry],
+        Value::List(entries) => entries,
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn error<T: std::fmt::Display>(&self, message: T) -> io::Error {
+        error(self.line, message)
+    }
+
+    fn expect(&mut self, expected: char) -> io::Result<()> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.next();
+                Ok(())
+            }
+            Some('\r' | '\n') | None => Err(self.error(format!("Expected {:?} before the end of the line", expected))),
+            Some(c) => Err(self.error(format!("Expected {:?} but found {:?}", expected, c))),
+        }
+    }
+
+    // Spaces and tabs
+    fn skip_space(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.next();
+        }
+    }
+
+    // Whitespace including newlines, and comments
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t' | '\r' | '\n') => {
+                    self.next();
+                }
+                Some('#') => self.skip_comment(),
+                _ => break,
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while !matches!(self.peek(), Some('\n') | None) {
+            self.next();
+        }
+    }
+
+    // Nothing but a comment until the end of the line
+    fn end_of_line(&mut self) -> io::Result<()> {
+        self.skip_space();
+        if self.peek() == Some('#') {
+            self.skip_comment();
+        }
+        if self.peek() == Some('\r') {
+            self.next();
+        }
+        match self.peek() {
+            Some('\n') | None => {
+                self.next();
+                Ok(())
+            }
+            Some(c) => Err(self.error(format!("Unexpected {:?}", c))),
+        }
+    }
+
+    fn key(&mut self) -> io::Result<String> {
+        match self.peek() {
+            Some('"' | '\'') => self.string(),
+            _ => {
+                let mut key = String::new();
+                while let Some(c) = self.peek().filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-') {
+                    key.push(c);
+                    self.next();
+                }
+                match self.peek() {
+                    _ if key.is_empty() => Err(self.error("Expected a key")),
+                    Some('.') => Err(self.error("Dotted keys aren't supported")),
+                    _ => Ok(key),
+                }
+            }
+        }
+    }
+
+    fn value(&mut self) -> io::Result<Value> {
+        match self.peek() {
+            Some('"' | '\'') => Ok(Value::Text(self.string()?)),
+            Some('{') => Err(self.error("Inline tables aren't supported")),
+            Some('[') => {
+                self.next();
+                let mut list = Vec::new();
+                loop {
+                    self.skip_blank();
+                    if self.peek() == Some(']') {
+                        self.next();
+                        return Ok(Value::List(list));
+                    }
+                    match self.value()? {
+                        Value::Text(text) => list.push(text),
+                        Value::List(_) => return Err(self.error("Arrays inside arrays aren't supported")),
+                    }
+                    self.skip_blank();
+                    match self.peek() {
+                        Some(',') => {
+                            self.next();
+                        }
+                        Some(']') => {}
+                        _ => return Err(self.error("Expected ',' or ']' in the array")),
+                    }
+                }
+            }
+            _ => {
+                // Numbers and booleans are kept as they're written
+                let mut token = String::new();
+                while let Some(c) = self.peek().filter(|c| !matches!(c, ' ' | '\t' | '\r' | '\n' | ',' | ']' | '#')) {
+                    token.push(c);
+                    self.next();
+                }
+                let number = token.starts_with(|c: char| c.is_ascii_digit() || c == '+' || c == '-');
+                if token == "true" || token == "false" || number {
+                    Ok(Value::Text(token))
+                } else if token.is_empty() {
+                    Err(self.error("Expected a value"))
+                } else {
+                    Err(self.error(format!("{:?} isn't a value, strings need quotes", token)))
+                }
+            }
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let quote = self.next();
+        if self.peek() == quote {
+            self.next();
+            if self.peek() == quote {
+                return Err(self.error("Multi-line strings aren't supported"));
+            }
+            return Ok(String::new());
+        }
+        let mut text = String::new();
+        // The newline is left for the error to be on its line
+        while let Some(c) = self.peek().filter(|&c| c != '\n') {
+            self.next();
+            if Some(c) == quote {
+                return Ok(text);
+            }
+            if c == '\\' && quote == Some('"') {
+                text.push(self.escape()?);
+            } else {
+                text.push(c);
+            }
+        }
+        Err(self.error("The string doesn't end"))
+    }
+
+    // What follows a `\` in a basic string
+    fn escape(&mut self) -> io::Result<char> {
+        Ok(match self.next() {
+            Some('b') => '\u{8}',
+            Some('t') => '\t',
+            Some('n') => '\n',
+            Some('f') => '\u{c}',
+            Some('r') => '\r',
+            Some('e') => '\u{1b}',
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some(kind @ ('u' | 'U')) => {
+                let digits: String = (0..if kind == 'u' { 4 } else { 8 }).filter_map(|_| self.next()).collect();
+                u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error(format!("\\{}{} isn't a character", kind, digits)))?
+            }
+            other => return Err(self.error(format!("\\{} isn't an escape", other.unwrap_or(' ')))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fails(text: &str) -> String {
+        let e = parse(text).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        e.to_string()
+    }
+
+    fn wanted(text: &str) -> Vec<Wanted> {
+        parse(text).unwrap().wanted
+    }
+
+    fn set(var: &str, value: &str) -> Wanted {
+        Wanted::Set(var.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn whole_manifest() {
+        let text = "\
+# A comment
+app = \"myapp\"
+shells = [\"bash\", \"zsh\", \"fish\"]
+target = \"both\"
+
+[set]
+MYAPP_HOME = \"/opt/myapp\" # trailing comment
+\"QUOTED KEY\" = 'literal'
+
+[append]
+PATH = [\"/opt/myapp/bin\", \"/opt/myapp/tools\"]
+
+[prepend]
+PATH = \"/opt/myapp/shims\"
+";
+        let spec = parse(text).unwrap();
+        assert_eq!(spec.app.as_deref(), Some("myapp"));
+        assert_eq!(spec.shells, [Shell::Bash, Shell::Zsh, Shell::Fish]);
+        assert_eq!(spec.target, Some(Target::Both));
+        assert_eq!(
+            spec.wanted,
+            [
+                set("MYAPP_HOME", "/opt/myapp"),
+                set("QUOTED KEY", "literal"),
+                Wanted::Append("PATH".to_string(), "/opt/myapp/bin".to_string()),
+                Wanted::Append("PATH".to_string(), "/opt/myapp/tools".to_string()),
+                Wanted::Prepend("PATH".to_string(), "/opt/myapp/shims".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_manifests() {
+        assert_eq!(parse("").unwrap(), Spec::new());
+        assert_eq!(parse("\n# just a comment\r\n\n").unwrap(), Spec::new());
+    }
+
+    #[test]
+    fn targets() {
+        assert_eq!(parse("target = \"login\"").unwrap().target, Some(Target::LoginProfile));
+        assert_eq!(parse("target = \"interactive\"").unwrap().target, Some(Target::InteractiveRc));
+        assert_eq!(fails("target = \"all\""), "Line 1: The target has to be login, interactive or both, not \"all\"");
+    }
+
+    #[test]
+    fn shells() {
+        assert_eq!(parse("shells = \"nu\"").unwrap().shells, [Shell::Nu]);
+        assert_eq!(fails("shells = [\"cmd\"]"), "Line 1: \"cmd\" isn't a shell env_perm knows");
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(wanted("[set]\nA = \"tab\\there \\\"q\\\" \\\\ \\u00e9\\U0001F600\""), [set("A", "tab\there \"q\" \\ é😀")]);
+        assert_eq!(wanted("[set]\nA = \"\\b\\f\\r\\n\\e\""), [set("A", "\u{8}\u{c}\r\n\u{1b}")]);
+        assert_eq!(wanted("[set]\nA = 'C:\\no\\escapes'"), [set("A", "C:\\no\\escapes")]);
+        assert_eq!(wanted("[set]\nA = \"\"\nB = ''"), [set("A", ""), set("B", "")]);
+        assert_eq!(wanted("[set]\nA = \"# not a comment\""), [set("A", "# not a comment")]);
+    }
+
+    #[test]
+    fn numbers_and_booleans_are_kept_as_written() {
+        assert_eq!(
+            wanted("[set]\nA = 1\nB = -2.5\nC = true\nD = false\nE = +3"),
+            [set("A", "1"), set("B", "-2.5"), set("C", "true"), set("D", "false"), set("E", "+3")]
+        );
+        assert_eq!(fails("[set]\nA = yes"), "Line 2: \"yes\" isn't a value, strings need quotes");
+        assert_eq!(fails("[set]\nA ="), "Line 2: Expected a value");
+    }
+
+    #[test]
+    fn arrays() {
+        let wanted = wanted("[append]\nPATH = [\n  \"/a\", # first\n  '/b',\n]\nLIST = []");
+        assert_eq!(
+            wanted,
+            [Wanted::Append("PATH".to_string(), "/a".to_string()), Wanted::Append("PATH".to_string(), "/b".to_string())]
+        );
+        assert_eq!(fails("[append]\nPATH = [\"/a\" \"/b\"]"), "Line 2: Expected ',' or ']' in the array");
+        assert_eq!(fails("[append]\nPATH = [[\"/a\"]]"), "Line 2: Arrays inside arrays aren't supported");
+        assert_eq!(fails("[set]\nA = [\"1\", \"2\"]"), "Line 2: A can only be set to one value");
+    }
+
+    #[test]
+    fn bad_strings() {
+        assert_eq!(fails("[set]\nA = \"open\nB = 1"), "Line 2: The string doesn't end");
+        assert_eq!(fails("[set]\nA = \"\\q\""), "Line 2: \\q isn't an escape");
+        assert_eq!(fails("[set]\nA = \"\\uZZZZ\""), "Line 2: \\uZZZZ isn't a character");
+        assert_eq!(fails("[set]\nA = \"\\uD800\""), "Line 2: \\uD800 isn't a character");
+    }
+
+    #[test]
+    fn bad_lines() {
+        assert_eq!(fails("[set]\nA = \"1\" \"2\""), "Line 2: Unexpected '\"'");
+        assert_eq!(fails("[set]\nA \"1\""), "Line 2: Expected '=' but found '\"'");
+        assert_eq!(fails("[set]\nA"), "Line 2: Expected '=' before the end of the line");
+        assert_eq!(fails("[set\nA = 1"), "Line 1: Expected ']' before the end of the line");
+        assert_eq!(fails("= 1"), "Line 1: Expected a key");
+        assert_eq!(fails("[set]\nA = 1 ]"), "Line 2: Unexpected ']'");
+    }
+
+    #[test]
+    fn unknown_tables_and_keys() {
+        assert_eq!(fails("[env]\nA = 1"), "Line 1: There's no [env] table in a manifest");
+        assert_eq!(fails("name = \"x\""), "Line 1: name isn't something a manifest can have");
+        assert_eq!(fails("app = [\"x\"]"), "Line 1: app isn't something a manifest can have");
+    }
+
+    #[test]
+    fn unsupported_toml() {
+        assert_eq!(fails("[set]\nA = \"\"\"\nmany\n\"\"\""), "Line 2: Multi-line strings aren't supported");
+        assert_eq!(fails("[set]\nA = '''x'''"), "Line 2: Multi-line strings aren't supported");
+        assert_eq!(fails("[set]\nA.B = 1"), "Line 2: Dotted keys aren't supported");
+        assert_eq!(fails("[set]\nA = { B = 1 }"), "Line 2: Inline tables aren't supported");
+        assert_eq!(fails("[[set]]\nA = 1"), "Line 1: Arrays of tables aren't supported");
+    }
+
+    #[test]
+    fn nothing_twice() {
+        assert_eq!(fails("[set]\nA = 1\nA = 2"), "Line 3: A is there more than once");
+        assert_eq!(fails("app = \"a\"\napp = \"b\""), "Line 2: app is there more than once");
+        assert_eq!(fails("[set]\nA = 1\n[append]\nA = \"/a\"\n[set]\nB = 2"), "Line 5: There's more than one [set] table");
+        // The same name in different tables is fine
+        assert_eq!(wanted("[set]\nA = 1\n[append]\nA = \"/a\"").len(), 2);
+    }
+
+    #[test]
+    fn read_names_the_file() {
+        let dir = crate::testing::TempDir::new("manifest");
+        let path = dir.path().join("env.toml");
+        std::fs::write(&path, "bad").unwrap();
+        let e = read(&path).unwrap_err();
+        assert_eq!(e.to_string(), format!("{}: Line 1: Expected '=' before the end of the line", path.display()));
+    }
+}
@@ -0,0 +1,128 @@
+//! What persisting a variable did.
+
+use std::path::PathBuf;
+
+use crate::{Place, Plan};
+
+/// What a [`set`](crate::set), [`append`](crate::append) or
+/// the like did, so installers can tell the user.
+/// ```
+/// use env_perm::Outcome;
+///
+/// let path = std::env::temp_dir().join("env_perm_outcome.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// let outcome = env_perm::set_in(&path, "DUMMY", 1).unwrap();
+/// assert_eq!(outcome, Outcome::Added { file: path.clone() });
+/// let outcome = env_perm::update_in(&path, "DUMMY", 2).unwrap();
+/// assert_eq!(outcome, Outcome::Updated { file: path.clone(), old: "1".to_string() });
+/// assert_eq!(outcome.previous(), Some("1"));
+/// let outcome = env_perm::set_in(&path, "DUMMY", 3).unwrap();
+/// assert_eq!(outcome.previous(), Some("2"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Outcome {
+    /// It was already set so nothing was written.
+    AlreadySet,
+    /// On windows the entry being added was already in the
+    /// list in the registry, going by what windows would
+    /// find, so nothing was written.
+    AlreadyPresent {
+        /// The key whose value has it, the user's or
+        /// the machine's, e.g. `HKCU\Environment`.
+        key: String,
+    },
+    /// A line was added to a file.
+    Added {
+        /// The file that was written to.
+        file: PathBuf,
+    },
+    /// The file already gave it a value, which was replaced.
+    /// [`update`](crate::update) rewrites the assignment
+    /// where it is, [`set`](crate::set) adds one after it.
+    Updated {
+        /// The file that was edited.
+        file: PathBuf,
+        /// The value it had before.
+        old: String,
+    },
+    /// A value was written to the windows registry.
+    RegistryWritten {
+        /// The key it's under, e.g. `HKCU\Environment`.
+        key: String,
+        /// The value it had before, if any.
+        old: Option<String>,
+    },
+    /// A backend from [`Config::custom_backend`](crate::Config::custom_backend)
+    /// persisted it.
+    Custom {
+        /// The value it had before, if any.
+        old: Option<String>,
+    },
+    /// It was written to more than one place,
+    /// e.g. with [`Target::Both`](crate::Target::Both).
+    Several(Vec<Outcome>),
+    /// With [`Config::dry_run`](crate::Config::dry_run)
+    /// nothing was written, this is what would have been.
+    Planned(Plan),
+}
+
+impl Outcome {
+    /// The files that were written to, if any,
+    /// or would have been in a dry run.
+    pub fn files(&self) -> Vec<PathBuf> {
+        match self {
+            Outcome::Added { file } | Outcome::Updated { file, .. } => vec![file.clone()],
+            Outcome::Several(outcomes) => outcomes.iter().flat_map(Outcome::files).collect(),
+            Outcome::Planned(plan) => plan
+                .edits()
+                .iter()
+                .filter_map(|edit| match &edit.place {
+                    Place::File(path) => Some(path.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The value that was persisted before, like
+    /// [`HashMap::insert`](std::collections::HashMap::insert)
+    /// gives, e.g. to restore it on uninstall.
+    pub fn previous(&self) -> Option<&str> {
+        match self {
+            Outcome::Updated { old, .. } => Some(old),
+            Outcome::RegistryWritten { old, .. } | Outcome::Custom { old } => old.as_deref(),
+            Outcome::Several(outcomes) => outcomes.iter().find_map(Outcome::previous),
+            _ => None,
+        }
+    }
+
+    /// Whether anything was written,
+    /// or would have been in a dry run.
+    pub fn changed(&self) -> bool {
+        !matches!(self, Outcome::AlreadySet | Outcome::AlreadyPresent { .. })
+    }
+
+    /// One for each of `files`.
+    pub(crate) fn added(files: &[PathBuf]) -> Outcome {
+        match files {
+            [file] => Outcome::Added { file: file.clone() },
+            _ => Outcome::Several(files.iter().map(|file| Outcome::Added { file: file.clone() }).collect()),
+        }
+    }
+
+    /// The same but with `old` as the value that was
+    /// replaced, so files with one count as updated.
+    pub(crate) fn replacing(self, old: Option<String>) -> Outcome {
+        match (self, old) {
+            (Outcome::Added { file }, Some(old)) => Outcome::Updated { file, old },
+            (Outcome::RegistryWritten { key, .. }, old) => Outcome::RegistryWritten { key, old },
+            (Outcome::Custom { .. }, old) => Outcome::Custom { old },
+            (Outcome::Several(outcomes), old) => Outcome::Several(
+                outcomes.into_iter().map(|outcome| outcome.replacing(old.clone())).collect(),
+            ),
+            (outcome, _) => outcome,
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! Editing `PATHEXT`, the extensions windows
+//! runs files with without typing them.
+
+use std::io;
+
+/// What windows has when `PATHEXT` isn't set anywhere.
+pub(crate) const DEFAULT: &str = ".COM;.EXE;.BAT;.CMD;.VBS;.VBE;.JS;.JSE;.WSF;.WSH;.MSC";
+
+/// `current` with `extension`, like `.lua`, at the end in
+/// upper case. None if it's already there in any case.
+pub(crate) fn with_extension(current: &str, extension: &str) -> io::Result<Option<String>> {
+    let extension = normalize(extension)?;
+    let mut extensions = split(current);
+    if extensions.iter().any(|part| part.eq_ignore_ascii_case(&extension)) {
+        return Ok(None);
+    }
+    extensions.push(&extension);
+    Ok(Some(extensions.join(";")))
+}
+
+/// `current` without `extension` in any case,
+/// or None if it doesn't have it.
+pub(crate) fn without(current: &str, extension: &str) -> io::Result<Option<String>> {
+    let extension = normalize(extension)?;
+    let extensions = split(current);
+    if !extensions.iter().any(|part| part.eq_ignore_ascii_case(&extension)) {
+        return Ok(None);
+    }
+    Ok(Some(extensions.into_iter().filter(|part| !part.eq_ignore_ascii_case(&extension)).collect::<Vec<_>>().join(";")))
+}
+
+// The entries, leaving out empty ones from stray `;`s
+fn split(current: &str) -> Vec<&str> {
+    current.split(';').map(str::trim).filter(|part| !part.is_empty()).collect()
+}
+
+// `extension` the way windows lists them, `.LUA`,
+// failing if it isn't one
+fn normalize(extension: &str) -> io::Result<String> {
+    let trimmed = extension.trim();
+    let name = trimmed.strip_prefix('.').unwrap_or(trimmed);
+    if name.is_empty() || name.contains(|c: char| matches!(c, '.' | ';' | '%' | '"' | '\\' | '/' | '*' | '?') || c.is_whitespace()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} isn't a file extension like .LUA", extension)));
+    }
+    Ok(format!(".{}", name.to_uppercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn added(current: &str, extension: &str) -> Option<String> {
+        with_extension(current, extension).unwrap()
+    }
+
+    fn removed(current: &str, extension: &str) -> Option<String> {
+        without(current, extension).unwrap()
+    }
+
+    #[test]
+    fn adds_once_in_upper_case() {
+        assert_eq!(added(".COM;.EXE", ".lua").as_deref(), Some(".COM;.EXE;.LUA"));
+        assert_eq!(added(".COM;.EXE", "lua").as_deref(), Some(".COM;.EXE;.LUA"));
+        assert_eq!(added(".COM;.LUA", ".lua"), None);
+        assert_eq!(added(".COM;.lua", ".LUA"), None);
+        assert_eq!(added("", " .py ").as_deref(), Some(".PY"));
+        let default = added(DEFAULT, ".lua").unwrap();
+        assert!(default.starts_with(DEFAULT) && default.ends_with(";.LUA"));
+    }
+
+    #[test]
+    fn stray_separators_are_dropped() {
+        assert_eq!(added(";.COM;; .EXE ;", ".lua").as_deref(), Some(".COM;.EXE;.LUA"));
+        assert_eq!(removed(";.COM;;.LUA;", ".lua").as_deref(), Some(".COM"));
+    }
+
+    #[test]
+    fn without_ignores_case() {
+        assert_eq!(removed(".COM;.Lua;.EXE", "LUA").as_deref(), Some(".COM;.EXE"));
+        assert_eq!(removed(".COM;.EXE", ".lua"), None);
+        assert_eq!(removed(".LUAC", ".lua"), None);
+    }
+
+    #[test]
+    fn bad_extensions() {
+        for extension in ["", ".", "..lua", "lua;py", ".l ua", "%X%", ".*", "a/b", "a\\b", "\"x\"", "a?"] {
+            let e = with_extension(".COM", extension).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(e.to_string(), format!("{:?} isn't a file extension like .LUA", extension));
+            assert!(without(".COM", extension).is_err());
+        }
+    }
+}
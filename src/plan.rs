@@ -0,0 +1,412 @@
+//! Working out what a set of changes would write
+//! before writing any of it.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::journal;
+use crate::lock;
+use crate::profile::{self, Stamp, Writer};
+use crate::windows::{self, Scope};
+use crate::{Conflict, Place};
+
+thread_local! {
+    // What's been planned so far while `capture` runs
+    static PLANNING: RefCell<Option<Vec<Edit>>> = const { RefCell::new(None) };
+}
+
+/// The changes some writes would make, worked out without
+/// making them, to show the user and then [`apply`](Plan::apply).
+/// ```
+/// let path = std::env::temp_dir().join("env_perm_plan.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// let plan = env_perm::plan(|config| {
+///     config.set_in(&path, "MYAPP_HOME", "/opt/myapp")?;
+///     config.append_in(&path, "PATH", "/opt/myapp/bin")?;
+///     Ok(())
+/// })
+/// .unwrap();
+/// assert!(!path.exists());
+/// assert_eq!(plan.edits()[0].added(), ["export MYAPP_HOME=/opt/myapp", "export PATH=\"$PATH:/opt/myapp/bin\""]);
+/// plan.apply().unwrap();
+/// assert!(path.exists());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Plan {
+    edits: Vec<Edit>,
+}
+
+/// One file or registry value a [`Plan`] changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Edit {
+    /// What it changes.
+    pub place: Place,
+    /// The whole file or the value as it is now,
+    /// None if there isn't one.
+    pub before: Option<String>,
+    /// What it'll be, None if it's deleted.
+    pub after: Option<String>,
+    // How the file looked when planned, to tell if it changed
+    stamp: Option<Stamp>,
+    app: Option<String>,
+    backups: usize,
+}
+
+impl Edit {
+    /// The lines it adds, in order.
+    pub fn added(&self) -> Vec<&str> {
+        difference(self.after.as_deref(), self.before.as_deref())
+    }
+
+    /// The lines it takes out, in order.
+    pub fn removed(&self) -> Vec<&str> {
+        difference(self.before.as_deref(), self.after.as_deref())
+    }
+}
+
+// The lines of `from` that `to` doesn't have
+fn difference<'a>(from: Option<&'a str>, to: Option<&str>) -> Vec<&'a str> {
+    let to: Vec<&str> = to.map_or_else(Vec::new, |to| to.lines().collect());
+    from.map_or_else(Vec::new, |from| from.lines().filter(|line| !line.is_empty() && !to.contains(line)).collect())
+}
+
+impl Plan {
+    /// Everything it changes, in the order it was first planned.
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
+    /// Whether it doesn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Makes the changes, backing up and journaling them
+    /// like any other write. If any file or value changed
+    /// since the plan was made nothing is written and it
+    /// fails with a [`Conflict`], so the plan can be made
+    /// again from what's there now.
+    pub fn apply(&self) -> io::Result<()> {
+        self.apply_with(false)
+    }
+
+    /// Like [`apply`](Plan::apply), but if it changes the
+    /// machine's registry environment and the process isn't
+    /// elevated, the user is asked once with UAC and an
+    /// elevated `reg.exe` makes those changes.
+    /// Fails with [`io::ErrorKind::PermissionDenied`] if
+    /// the user says no, after the files are written.
+    /// ```no_run
+    /// use env_perm::{Config, Scope};
+    ///
+    /// let machine = Config::new().scope(Scope::Machine);
+    /// let plan = machine.plan(|config| config.append("PATH", r"C:\Program Files\MyApp\bin").map(|_| ())).unwrap();
+    /// plan.apply_elevated().unwrap();
+    /// ```
+    pub fn apply_elevated(&self) -> io::Result<()> {
+        self.apply_with(!windows::is_elevated())
+    }
+
+    // Registry values are broadcast once at the end
+    fn apply_with(&self, elevate: bool) -> io::Result<()> {
+        lock::locked(|| windows::batched(|| {
+            for edit in &self.edits {
+                let current = match (&edit.place, &edit.stamp) {
+                    (Place::File(path), Some(stamp)) => profile::check_stamp(path, stamp).is_ok(),
+                    (Place::Registry { key, var }, _) => windows::reg_get(Scope::of(key), var)? == edit.before,
+                    _ => false,
+                };
+                if !current {
+                    return Err(Conflict { place: edit.place.clone() }.into());
+                }
+            }
+            let mut elevated = Vec::new();
+            for edit in &self.edits {
+                let writer = Writer { app: edit.app.as_deref(), backups: edit.backups };
+                match &edit.place {
+                    Place::File(path) if edit.after.is_none() => profile::remove_file(path, writer)?,
+                    Place::File(path) => {
+                        profile::write_lines(path, &profile::lines_of(edit.after.clone()), writer)?
+                    }
+                    Place::Registry { key, .. } if elevate && Scope::of(key) == Scope::Machine => elevated.push(edit),
+                    Place::Registry { key, var } => windows::put(Scope::of(key), var, writer.app, edit.after.as_deref())?,
+                }
+            }
+            if elevated.is_empty() {
+                return Ok(());
+            }
+            windows::import_elevated(&reg_file(&reg_entries(&elevated)?))?;
+            for edit in elevated {
+                if let Place::Registry { key, var } = &edit.place {
+                    journal::record_registry(key, var, edit.app.as_deref(), edit.before.clone(), edit.after.clone())?;
+                }
+            }
+            Ok(())
+        }))
+    }
+
+    /// A POSIX shell script that puts every file the plan
+    /// changes back the way it is now, deleting the ones
+    /// that don't exist yet, for an uninstaller to run
+    /// after the plan is applied.
+    /// ```
+    /// let path = std::env::temp_dir().join("env_perm_undo_script.sh");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let plan = env_perm::plan(|config| config.set_in(&path, "DUMMY", 1).map(|_| ())).unwrap();
+    /// assert!(plan.undo_script().contains(&format!("rm -f '{}'", path.display())));
+    /// ```
+    pub fn undo_script(&self) -> String {
+        let mut script = String::from("#!/bin/sh\n# Undoes what env_perm wrote\n");
+        for edit in &self.edits {
+            if let Place::File(path) = &edit.place {
+                let path = sh_quote(&path.to_string_lossy());
+                match &edit.before {
+                    Some(before) => script.push_str(&format!("printf '%s' {} > {}\n", sh_quote(before), path)),
+                    None => script.push_str(&format!("rm -f {}\n", path)),
+                }
+            }
+        }
+        script
+    }
+
+    /// A `.reg` file that makes the plan's changes to the
+    /// user's and machine's registry environment, for admins
+    /// to review or hand out with their own tooling instead
+    /// of applying it. Values keep their type like they
+    /// would if it was applied. Changes to files aren't in it.
+    /// ```no_run
+    /// use env_perm::{Config, Outcome};
+    ///
+    /// let config = Config::new().dry_run(true);
+    /// if let Outcome::Planned(plan) = config.set("MYAPP_HOME", r"C:\Program Files\MyApp").unwrap() {
+    ///     plan.write_reg("myapp-env.reg").unwrap();
+    /// }
+    /// ```
+    pub fn reg(&self) -> io::Result<String> {
+        let edits: Vec<&Edit> = self.edits.iter().collect();
+        Ok(reg_file(&reg_entries(&edits)?))
+    }
+
+    /// Writes the [`reg`](Plan::reg) file to `path` in
+    /// UTF-16 as `regedit` expects.
+    pub fn write_reg<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, utf16_file(&self.reg()?))
+    }
+
+    /// A `.reg` file that puts every registry value the
+    /// plan changes back the way it is now, deleting the
+    /// ones that aren't set yet, for an uninstaller to
+    /// import with `reg import` after the plan is applied.
+    pub fn undo_reg(&self) -> String {
+        let mut entries = Vec::new();
+        for edit in &self.edits {
+            if let Place::Registry { key, var } = &edit.place {
+                let data = edit.before.as_deref().map_or_else(|| "-".to_string(), reg_quote);
+                entries.push((key.as_str(), format!("{}={}", reg_quote(var), data)));
+            }
+        }
+        reg_file(&entries)
+    }
+
+    /// Writes what undoes the plan to `path`: the
+    /// [`undo_reg`](Plan::undo_reg) file in UTF-16 as
+    /// `regedit` expects on windows, and the
+    /// [`undo_script`](Plan::undo_script) everywhere else.
+    /// Writing it before applying gives an uninstaller
+    /// something to ship, and writing it instead, e.g. from
+    /// a [`dry_run`](crate::Config::dry_run), gives one
+    /// without changing anything.
+    /// ```no_run
+    /// let plan = env_perm::plan(|config| config.append("PATH", "/opt/myapp/bin").map(|_| ())).unwrap();
+    /// plan.write_undo("uninstall-env.sh").unwrap();
+    /// plan.apply().unwrap();
+    /// ```
+    pub fn write_undo<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        if cfg!(windows) {
+            fs::write(path, utf16_file(&self.undo_reg()))
+        } else {
+            fs::write(path, self.undo_script())
+        }
+    }
+}
+
+// `text` in single quotes for sh
+fn sh_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+// `text` in double quotes for a .reg file
+fn reg_quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// `text` as REG_EXPAND_SZ data for a .reg file, which
+// is its UTF-16 with the null at the end in hex
+fn reg_expand(text: &str) -> String {
+    let bytes: Vec<String> = windows::reg_bytes(text.encode_utf16()).iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("hex(2):{}", bytes.join(","))
+}
+
+// `text` as UTF-16 with a byte order mark
+fn utf16_file(text: &str) -> Vec<u8> {
+    std::iter::once(0xfeff).chain(text.encode_utf16()).flat_map(|unit: u16| unit.to_le_bytes()).collect()
+}
+
+// The key and `name=data` line making each registry edit,
+// as REG_EXPAND_SZ if the value is one or it's new and
+// refers to other variables
+fn reg_entries<'a>(edits: &[&'a Edit]) -> io::Result<Vec<(&'a str, String)>> {
+    let mut entries = Vec::with_capacity(edits.len());
+    for edit in edits {
+        if let Place::Registry { key, var } = &edit.place {
+            let data = match &edit.after {
+                Some(after) if edit.before.is_none() && windows::has_reference(after) => reg_expand(after),
+                Some(after) if edit.before.is_some() && windows::is_expand(Scope::of(key), var)? => reg_expand(after),
+                Some(after) => reg_quote(after),
+                None => "-".to_string(),
+            };
+            entries.push((key.as_str(), format!("{}={}", reg_quote(var), data)));
+        }
+    }
+    Ok(entries)
+}
+
+// A .reg file with each `name=data` line
+// under the key it goes with
+fn reg_file(entries: &[(&str, String)]) -> String {
+    let mut reg = String::from("Windows Registry Editor Version 5.00\r\n");
+    let mut current = None;
+    for (key, line) in entries {
+        if current != Some(key) {
+            reg.push_str(&format!("\r\n[{}]\r\n", long_key(key)));
+            current = Some(key);
+        }
+        reg.push_str(&format!("{}\r\n", line));
+    }
+    reg
+}
+
+// .reg files need the root key spelled out
+fn long_key(key: &str) -> String {
+    match key.split_once('\\') {
+        Some(("HKCU", rest)) => format!("HKEY_CURRENT_USER\\{}", rest),
+        Some(("HKLM", rest)) => format!("HKEY_LOCAL_MACHINE\\{}", rest),
+        _ => key.to_string(),
+    }
+}
+
+impl fmt::Display for Plan {
+    /// Each place followed by the lines it adds
+    /// with `+` and takes out with `-`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for edit in &self.edits {
+            match &edit.place {
+                Place::File(path) => writeln!(f, "{}", path.display())?,
+                Place::Registry { key, var } => writeln!(f, "{}\\{}", key, var)?,
+            }
+            for line in edit.removed() {
+                writeln!(f, "- {}", line)?;
+            }
+            for line in edit.added() {
+                writeln!(f, "+ {}", line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f` with everything it would write to files and
+/// the registry kept in a [`Plan`] instead, reads seeing
+/// what it has written so far.
+pub(crate) fn capture<T, F: FnOnce() -> io::Result<T>>(f: F) -> io::Result<(T, Plan)> {
+    if planning() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "A plan can't be made while making one"));
+    }
+    PLANNING.with(|planning| *planning.borrow_mut() = Some(Vec::new()));
+    let capturing = Capturing;
+    let result = f();
+    let edits = capturing.finish();
+    Ok((result?, Plan { edits: edits.into_iter().filter(|edit| edit.before != edit.after).collect() }))
+}
+
+// Stops planning when dropped so if `f`
+// panics later writes aren't planned too
+struct Capturing;
+
+impl Capturing {
+    fn finish(self) -> Vec<Edit> {
+        PLANNING.with(|planning| planning.borrow_mut().take()).unwrap_or_default()
+    }
+}
+
+impl Drop for Capturing {
+    fn drop(&mut self) {
+        PLANNING.with(|planning| planning.borrow_mut().take());
+    }
+}
+
+/// Whether writes are being planned rather than made.
+pub(crate) fn planning() -> bool {
+    PLANNING.with(|planning| planning.borrow().is_some())
+}
+
+/// What's planned for `place` so far: None if nothing is,
+/// otherwise what it'll have, which can be nothing.
+pub(crate) fn planned(place: &Place) -> Option<Option<String>> {
+    PLANNING.with(|planning| {
+        let planning = planning.borrow();
+        let edit = planning.as_ref()?.iter().find(|edit| edit.place == *place)?;
+        Some(edit.after.clone())
+    })
+}
+
+/// Plans for the file at `path` to end up with `contents`.
+pub(crate) fn write_file(path: &Path, contents: String, writer: Writer) -> io::Result<()> {
+    plan_file(path, Some(contents), writer)
+}
+
+/// Plans for the file at `path` to be deleted.
+pub(crate) fn remove_file(path: &Path, writer: Writer) -> io::Result<()> {
+    plan_file(path, None, writer)
+}
+
+fn plan_file(path: &Path, after: Option<String>, writer: Writer) -> io::Result<()> {
+    let place = Place::File(path.to_path_buf());
+    if planned(&place).is_none() {
+        let (before, stamp) = profile::read_stamped(path)?;
+        add(Edit { place: place.clone(), before, after: None, stamp: Some(stamp), app: None, backups: 0 });
+    }
+    update(&place, after, writer);
+    Ok(())
+}
+
+/// Plans for `var` under `key` in the registry to be
+/// `value`, or deleted if it's None, `before` being
+/// what it has now.
+pub(crate) fn write_var(key: &str, var: &str, value: Option<String>, before: Option<String>, app: Option<&str>) {
+    let place = Place::Registry { key: key.to_string(), var: var.to_string() };
+    if planned(&place).is_none() {
+        add(Edit { place: place.clone(), before, after: None, stamp: None, app: None, backups: 0 });
+    }
+    update(&place, value, Writer { app, backups: 0 });
+}
+
+fn add(edit: Edit) {
+    PLANNING.with(|planning| planning.borrow_mut().get_or_insert_with(Vec::new).push(edit));
+}
+
+fn update(place: &Place, after: Option<String>, writer: Writer) {
+    PLANNING.with(|planning| {
+        let mut planning = planning.borrow_mut();
+        if let Some(edit) = planning.iter_mut().flatten().find(|edit| edit.place == *place) {
+            edit.after = after;
+            edit.app = writer.app.map(String::from);
+            edit.backups = writer.backups;
+        }
+    })
+}
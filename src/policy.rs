@@ -0,0 +1,178 @@
+//! Finding variables Group Policy sets, which windows
+//! puts back at each refresh whatever was written.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::windows::Scope;
+
+/// A Group Policy preference for a variable, from
+/// [`policy_managed`](crate::policy_managed). Windows applies
+/// it again at every policy refresh, every 90 minutes or so
+/// and at logon, so a change to the variable may not stick.
+/// ```
+/// let dir = std::env::temp_dir().join("env_perm_policy");
+/// let history = dir.join(r"Microsoft/Group Policy/History/{31B2F340-016D-11D2-945F-00C04FB984F9}/Machine/Preferences/EnvironmentVariables");
+/// std::fs::create_dir_all(&history).unwrap();
+/// std::fs::write(
+///     history.join("EnvironmentVariables.xml"),
+///     r#"<EnvironmentVariables><EnvironmentVariable name="JAVA_HOME"><Properties action="U" name="JAVA_HOME" value="C:\jdk8" user="0" partial="0"/></EnvironmentVariable></EnvironmentVariables>"#,
+/// )
+/// .unwrap();
+/// std::env::set_var("ProgramData", &dir);
+///
+/// let settings = env_perm::policy_managed("JAVA_HOME").unwrap();
+/// assert_eq!(settings[0].value.as_deref(), Some(r"C:\jdk8"));
+/// assert_eq!(settings[0].scope, env_perm::Scope::Machine);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PolicySetting {
+    /// The variable, as the policy names it.
+    pub var: String,
+    /// Whose variable it sets.
+    pub scope: Scope,
+    /// What the policy does to it.
+    pub action: PolicyAction,
+    /// The value it gives, None when it deletes it.
+    pub value: Option<String>,
+    /// Whether it only adds its value to a list like
+    /// `PATH` rather than replacing the whole thing.
+    pub partial: bool,
+    /// The policy file it's in.
+    pub file: PathBuf,
+}
+
+/// What a [`PolicySetting`] does at each refresh.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PolicyAction {
+    /// Sets it if it isn't set.
+    Create,
+    /// Deletes it and sets it again.
+    Replace,
+    /// Sets it, keeping it if it's already there.
+    Update,
+    /// Deletes it.
+    Delete,
+}
+
+impl fmt::Display for PolicySetting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let whose = match self.scope {
+            Scope::User => "user",
+            Scope::Machine => "machine",
+        };
+        match (&self.value, self.action) {
+            (_, PolicyAction::Delete) | (None, _) => write!(f, "Group Policy deletes the {} variable {}", whose, self.var)?,
+            (Some(value), _) if self.partial => write!(f, "Group Policy adds {:?} to the {} variable {}", value, whose, self.var)?,
+            (Some(value), _) => write!(f, "Group Policy sets the {} variable {} to {:?}", whose, self.var, value)?,
+        }
+        write!(f, " at each refresh, from {}", self.file.display())
+    }
+}
+
+/// Every setting for `var` in the Group Policy
+/// preferences applied to this machine and user.
+pub(crate) fn find(var: &str) -> io::Result<Vec<PolicySetting>> {
+    let mut files = Vec::new();
+    // The local policy, and what's cached of each domain
+    // policy applied to the machine and to the user
+    if let Some(root) = env::var_os("SystemRoot") {
+        for side in &["Machine", "User"] {
+            let path = Path::new(&root)
+                .join("System32")
+                .join("GroupPolicy")
+                .join(side)
+                .join("Preferences")
+                .join("EnvironmentVariables")
+                .join("EnvironmentVariables.xml");
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    for dir in &["ProgramData", "LOCALAPPDATA"] {
+        if let Some(root) = env::var_os(dir) {
+            collect(&Path::new(&root).join("Microsoft").join("Group Policy").join("History"), 4, &mut files)?;
+        }
+    }
+    let mut settings = Vec::new();
+    for file in files {
+        let text = fs::read_to_string(&file)?;
+        settings.extend(parse(&text, &file).into_iter().filter(|setting| setting.var.eq_ignore_ascii_case(var)));
+    }
+    Ok(settings)
+}
+
+// Every EnvironmentVariables.xml at most `depth` directories
+// below `dir`, e.g. `{GUID}\Machine\Preferences\EnvironmentVariables`
+fn collect(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() && depth > 0 {
+            collect(&path, depth - 1, files)?;
+        } else if path.file_name().is_some_and(|name| name == "EnvironmentVariables.xml") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// The settings in a preferences file, leaving out ones
+// that are turned off
+fn parse(text: &str, file: &Path) -> Vec<PolicySetting> {
+    let mut settings = Vec::new();
+    for item in text.split("<EnvironmentVariable ").skip(1) {
+        let item = item.split("</EnvironmentVariable>").next().unwrap_or(item);
+        if attribute(item, "disabled").as_deref() == Some("1") {
+            continue;
+        }
+        let properties = match item.split_once("<Properties ") {
+            Some((_, properties)) => properties.split('>').next().unwrap_or(properties),
+            None => continue,
+        };
+        let var = match attribute(properties, "name") {
+            Some(var) => var,
+            None => continue,
+        };
+        let action = match attribute(properties, "action").as_deref() {
+            Some("C") => PolicyAction::Create,
+            Some("R") => PolicyAction::Replace,
+            Some("D") => PolicyAction::Delete,
+            _ => PolicyAction::Update,
+        };
+        settings.push(PolicySetting {
+            var,
+            scope: if attribute(properties, "user").as_deref() == Some("1") { Scope::User } else { Scope::Machine },
+            action,
+            value: attribute(properties, "value").filter(|_| action != PolicyAction::Delete),
+            partial: attribute(properties, "partial").as_deref() == Some("1"),
+            file: file.to_path_buf(),
+        });
+    }
+    settings
+}
+
+// The value of the attribute `name` in the tag `tag` starts with
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let tag = tag.split('>').next().unwrap_or(tag);
+    let start = tag.match_indices(&format!("{}=\"", name)).find(|(i, _)| *i == 0 || tag[..*i].ends_with(char::is_whitespace))?.0 + name.len() + 2;
+    let value = &tag[start..start + tag[start..].find('"')?];
+    Some(
+        value
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
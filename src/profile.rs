@@ -0,0 +1,1036 @@
+//! Reading and editing the startup files
+//! of the shells.
+
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::shell::{Shell, PATH_SEPARATOR};
+use crate::app_conflict;
+use crate::confirm::{self, Decision};
+use crate::journal;
+use crate::plan;
+use crate::provenance;
+use crate::tamper;
+use crate::{Conflict, Place};
+use crate::lock;
+use crate::{EnvBackend, Quoting};
+
+/// A profile backend: lines in `shell`'s syntax
+/// appended to every file in `paths`.
+#[derive(Debug, Clone)]
+pub(crate) struct ProfileFiles {
+    pub(crate) shell: Shell,
+    pub(crate) paths: Vec<PathBuf>,
+    pub(crate) strict_posix: bool,
+    pub(crate) quoting: Quoting,
+    /// Lines go in this app's block when there is one.
+    pub(crate) app: Option<String>,
+    /// How many backups of each file to keep.
+    pub(crate) backups: usize,
+    /// Whether lines get a comment saying who wrote them.
+    pub(crate) annotate: bool,
+    /// Whether the app can set a variable another
+    /// app's block sets to something else.
+    pub(crate) app_conflicts: bool,
+}
+
+/// Who a write is for, which is recorded in the journal
+/// and names the block lines go in, and how many
+/// backups of the file to keep.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Writer<'a> {
+    pub(crate) app: Option<&'a str>,
+    pub(crate) backups: usize,
+}
+
+impl Default for Writer<'_> {
+    fn default() -> Self {
+        Writer { app: None, backups: BACKUPS }
+    }
+}
+
+/// How many backups of a file are kept unless
+/// [`Config::backups`](crate::Config::backups) says otherwise.
+pub(crate) const BACKUPS: usize = 5;
+
+impl EnvBackend for ProfileFiles {
+    fn set(&self, var: &str, value: &str) -> io::Result<()> {
+        one_line(self.shell, self.quoting, var, value)?;
+        self.write_sets(&[self.finish(self.shell.set_line(var, value, self.quoting))])
+    }
+
+    fn append(&self, var: &str, value: &str) -> io::Result<()> {
+        one_line(self.shell, self.quoting, var, value)?;
+        self.write_all(self.shell.append_line(var, value, self.quoting))
+    }
+
+    /// All the lines go in one block with a single write.
+    fn set_many(&self, vars: &[(String, String)]) -> io::Result<()> {
+        for (var, value) in vars {
+            one_line(self.shell, self.quoting, var, value)?;
+        }
+        let lines: Vec<String> = vars
+            .iter()
+            .map(|(var, value)| self.finish(self.shell.set_line(var, value, self.quoting)))
+            .collect();
+        self.write_sets(&lines)
+    }
+
+    fn prepend(&self, var: &str, value: &str) -> io::Result<()> {
+        one_line(self.shell, self.quoting, var, value)?;
+        self.write_all(self.shell.prepend_line(var, value, self.quoting))
+    }
+
+    /// Values that aren't UTF-8 are written with the
+    /// shell's escapes for raw bytes where it has them.
+    fn set_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        match value.to_str() {
+            Some(value) => self.set(var, value),
+            None => self.write_sets(&[self.finish(self.shell.set_line(var, self.escape_bytes(var, value)?, Quoting::Double))]),
+        }
+    }
+
+    fn append_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        match value.to_str() {
+            Some(value) => self.append(var, value),
+            None => self.write_all(self.shell.append_line(var, self.escape_bytes(var, value)?, Quoting::Double)),
+        }
+    }
+
+    fn prepend_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        match value.to_str() {
+            Some(value) => self.prepend(var, value),
+            None => self.write_all(self.shell.prepend_line(var, self.escape_bytes(var, value)?, Quoting::Double)),
+        }
+    }
+
+    fn append_with(&self, var: &str, value: &str, separator: char) -> io::Result<()> {
+        one_line(self.shell, self.quoting, var, value)?;
+        self.write_all(self.shell.append_line_with(var, value, separator, self.quoting))
+    }
+
+    fn append_with_os(&self, var: &str, value: &OsStr, separator: char) -> io::Result<()> {
+        match value.to_str() {
+            Some(value) => self.append_with(var, value, separator),
+            None => self.write_all(self.shell.append_line_with(var, self.escape_bytes(var, value)?, separator, Quoting::Double)),
+        }
+    }
+
+    /// Replaces the last plain assignment to `var` in each
+    /// file that has one, appends and prepends are left alone.
+    /// If no file has one it's the same as `set`.
+    /// With an app it's always `set`, which
+    /// replaces the one in the app's block.
+    fn update(&self, var: &str, value: &str) -> io::Result<()> {
+        lock::locked(|| {
+            if self.app.is_some() {
+                return self.set(var, value);
+            }
+            one_line(self.shell, self.quoting, var, value)?;
+            let line = self.finish(self.shell.set_line(var, value, self.quoting));
+            let mut found = false;
+            for path in &self.paths {
+                edit(path, self.writer(), |mut lines| {
+                    if let Some(i) = lines.iter().rposition(|line| self.is_set(line, var)) {
+                        lines[i] = line.clone();
+                        found = true;
+                    }
+                    lines
+                })?;
+            }
+            if found {
+                Ok(())
+            } else {
+                self.set(var, value)
+            }
+        })
+    }
+
+    fn remove(&self, var: &str) -> io::Result<()> {
+        let shell = self.shell;
+        for path in &self.paths {
+            edit(path, self.writer(), |lines| {
+                drop_lines(lines, |line| shell.parse_line(line).is_some_and(|(name, _)| name == var))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Drops the lines that appended or prepended `entry`
+    /// and takes it out of any plain assignments, splitting
+    /// them on the separator the list uses.
+    fn remove_entry(&self, var: &str, entry: &str) -> io::Result<()> {
+        for path in &self.paths {
+            edit(path, self.writer(), |lines| {
+                let separator = self.separator(&lines, var);
+                let lines = drop_lines(lines, |line| self.adds(line, var) == Some(entry.to_string()));
+                lines
+                    .into_iter()
+                    .map(|mut line| {
+                        if self.is_set(&line, var) {
+                            while let Some(edited) = self.without_entry(&line, var, entry, separator) {
+                                line = edited;
+                            }
+                        }
+                        line
+                    })
+                    .collect()
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The value the assignments to `var` in the first
+    /// file that has any add up to, e.g. `/a` then
+    /// appending `/b` gives `/a:/b`.
+    /// If nothing sets it first the inherited value
+    /// is left as `$VAR`.
+    fn get(&self, var: &str) -> io::Result<Option<String>> {
+        for path in &self.paths {
+            let mut current: Option<String> = None;
+            for (name, value) in read_lines(path)?.iter().filter_map(|line| self.shell.parse_line(line)) {
+                if name != var {
+                    continue;
+                }
+                let base = || current.clone().unwrap_or_else(|| format!("${}", var));
+                current = Some(if let Some((added, separator)) = self.shell.appended_with(var, &value) {
+                    format!("{}{}{}", base(), separator, added)
+                } else if let Some((added, separator)) = self.shell.prepended_with(var, &value) {
+                    format!("{}{}{}", added, separator, base())
+                } else {
+                    value
+                });
+            }
+            if current.is_some() {
+                return Ok(current);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Works out the list each file builds up and drops
+    /// whatever puts a repeat in it, so the first place each
+    /// entry shows up in the final value is the one kept.
+    fn dedupe(&self, var: &str) -> io::Result<()> {
+        for path in &self.paths {
+            edit(path, self.writer(), |lines| self.dedupe_lines(lines, var))?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites every line that assigns to `from` where
+    /// it is, so comments around it stay put.
+    /// Appends and prepends stay that way.
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        for path in &self.paths {
+            edit(path, self.writer(), |lines| {
+                lines
+                    .into_iter()
+                    .map(|line| match self.shell.parse_line(&line) {
+                        Some((name, value)) if name == from => {
+                            let renamed = if let Some(added) = self.shell.appended(from, &value) {
+                                self.shell.append_line(to, added, self.quoting)
+                            } else if let Some(added) = self.shell.prepended(from, &value) {
+                                self.shell.prepend_line(to, added, self.quoting)
+                            } else {
+                                self.shell.set_line(to, value, self.quoting)
+                            };
+                            self.finish(renamed)
+                        }
+                        _ => line,
+                    })
+                    .collect()
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Every variable assigned in any of the files,
+    /// in the order they first appear, with the
+    /// value [`get`](EnvBackend::get) gives.
+    fn list(&self) -> io::Result<Vec<(String, String)>> {
+        let mut names: Vec<String> = Vec::new();
+        for path in &self.paths {
+            for (name, _) in read_lines(path)?.iter().filter_map(|line| self.shell.parse_line(line)) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        let mut vars = Vec::with_capacity(names.len());
+        for name in names {
+            if let Some(value) = self.get(&name)? {
+                vars.push((name, value));
+            }
+        }
+        Ok(vars)
+    }
+
+    /// Looks at every assignment rather than just the
+    /// last one since appends build the value up
+    /// over several lines.
+    fn contains(&self, var: &str, entry: &str) -> io::Result<bool> {
+        for path in &self.paths {
+            let lines = read_lines(path)?;
+            let separator = self.separator(&lines, var);
+            for line in lines {
+                let (name, value) = match self.shell.parse_line(&line) {
+                    Some(parsed) => parsed,
+                    None => continue,
+                };
+                if name != var {
+                    continue;
+                }
+                let found = match self.adds(&line, var) {
+                    Some(added) => added == entry,
+                    None => value.split(separator).any(|part| part == entry),
+                };
+                if found {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl ProfileFiles {
+    fn write_all(&self, line: String) -> io::Result<()> {
+        let lines = [self.finish(line)];
+        for path in &self.paths {
+            add_lines(path, &lines, self.writer())?;
+        }
+        Ok(())
+    }
+
+    // Writes lines that set variables. In an app's block
+    // each replaces the one setting the same variable
+    // so running an installer again changes nothing.
+    fn write_sets(&self, lines: &[String]) -> io::Result<()> {
+        if self.app.is_none() {
+            for path in &self.paths {
+                write_block(path, lines, self.writer())?;
+            }
+            return Ok(());
+        }
+        let replaces = |existing: &str, line: &str| {
+            self.shell
+                .parse_line(line)
+                .is_some_and(|(var, _)| self.is_set(existing, &var))
+        };
+        let app = self.app.as_deref().unwrap_or_default();
+        for path in &self.paths {
+            lock::locked(|| {
+                if !self.app_conflicts {
+                    app_conflict::check(self.shell, path, app, lines)?;
+                }
+                write_in_block(path, lines, self.writer(), replaces)
+            })?;
+        }
+        Ok(())
+    }
+
+    // `value` ready to go in double quotes, or an
+    // error if the shell can't write it
+    fn escape_bytes(&self, var: &str, value: &OsStr) -> io::Result<String> {
+        self.shell.escape_bytes(value, self.quoting).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The value for {} isn't UTF-8 and {:?} profiles can't hold that", var, self.shell),
+            )
+        })
+    }
+
+    fn writer(&self) -> Writer<'_> {
+        Writer { app: self.app.as_deref(), backups: self.backups }
+    }
+
+    fn finish(&self, line: String) -> String {
+        let line = if self.strict_posix { self.shell.strict_posix(line) } else { line };
+        if self.annotate {
+            format!("{} {}", line, provenance::comment(self.app.as_deref()))
+        } else {
+            line
+        }
+    }
+
+    // Whether `line` sets `var` outright
+    // rather than adding to it
+    fn is_set(&self, line: &str, var: &str) -> bool {
+        match self.shell.parse_line(line) {
+            Some((name, value)) => {
+                name == var
+                    && self.shell.appended(var, &value).is_none()
+                    && self.shell.prepended(var, &value).is_none()
+            }
+            None => false,
+        }
+    }
+
+    fn dedupe_lines(&self, lines: Vec<String>, var: &str) -> Vec<String> {
+        let separator = self.separator(&lines, var);
+        // The entries of the final value and the line each came from
+        let mut list: Vec<(String, usize)> = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            let value = match self.shell.parse_line(line) {
+                Some((name, value)) if name == var => value,
+                _ => continue,
+            };
+            if let Some(added) = self.shell.appended(var, &value) {
+                list.push((added, i));
+            } else if let Some(added) = self.shell.prepended(var, &value) {
+                list.insert(0, (added, i));
+            } else {
+                list = value.split(separator).map(|part| (part.to_string(), i)).collect();
+            }
+        }
+        let mut seen: Vec<&str> = Vec::new();
+        let mut repeats: Vec<(usize, &str)> = Vec::new();
+        for (entry, i) in &list {
+            if seen.contains(&entry.as_str()) {
+                repeats.push((*i, entry));
+            } else {
+                seen.push(entry);
+            }
+        }
+        if repeats.is_empty() {
+            return lines;
+        }
+        let mut kept: Vec<String> = Vec::with_capacity(lines.len());
+        for (i, line) in lines.iter().enumerate() {
+            let drop: Vec<&str> = repeats.iter().filter(|(j, _)| *j == i).map(|(_, entry)| *entry).collect();
+            if drop.is_empty() {
+                kept.push(line.clone());
+            } else if self.is_set(line, var) {
+                kept.push(self.rewrite_set(line, var, &drop, separator));
+            } else {
+                // Drop the blank line we put before it
+                if kept.last().is_some_and(|last| last.is_empty()) {
+                    kept.pop();
+                }
+            }
+        }
+        kept
+    }
+
+    // A plain assignment to `var` with one copy of
+    // each entry in `drop` taken out for every time it's
+    // listed, starting from the end
+    fn rewrite_set(&self, line: &str, var: &str, drop: &[&str], separator: char) -> String {
+        let mut line = line.to_string();
+        for entry in drop {
+            if let Some(edited) = self.without_entry(&line, var, entry, separator) {
+                line = edited;
+            }
+        }
+        line
+    }
+
+    // The plain assignment `line` with the last copy of
+    // `entry` taken out of the list it assigns to `var`,
+    // or None if it isn't in it. It's cut out of the line
+    // where it is with a separator next to it, so the
+    // rest keeps its quoting and references as they
+    // were written, unless it's escaped in a way that
+    // means it has to be written again.
+    fn without_entry(&self, line: &str, var: &str, entry: &str, separator: char) -> Option<String> {
+        let (_, value) = self.shell.parse_line(line)?;
+        let mut parts: Vec<&str> = value.split(separator).collect();
+        let i = parts.iter().rposition(|part| *part == entry)?;
+        parts.remove(i);
+        let wanted = parts.join(separator.encode_utf8(&mut [0; 4]));
+        let width = separator.len_utf8();
+        for (at, _) in line.rmatch_indices(entry) {
+            let end = at + entry.len();
+            let (start, end) = if line[end..].starts_with(separator) {
+                (at, end + width)
+            } else if line[..at].ends_with(separator) {
+                (at - width, end)
+            } else {
+                (at, end)
+            };
+            let edited = format!("{}{}", &line[..start], &line[end..]);
+            if self.shell.parse_line(&edited) == Some((var.to_string(), wanted.clone())) {
+                return Some(edited);
+            }
+        }
+        Some(self.finish(self.shell.set_line(var, wanted, self.quoting)))
+    }
+
+    // What the list `var` is separated with in `lines`:
+    // whatever its appends and prepends use, or failing
+    // that the first separator a plain assignment has
+    fn separator(&self, lines: &[String], var: &str) -> char {
+        let mut found = None;
+        for (name, value) in lines.iter().filter_map(|line| self.shell.parse_line(line)) {
+            if name != var {
+                continue;
+            }
+            let adds = self.shell.appended_with(var, &value).or_else(|| self.shell.prepended_with(var, &value));
+            if let Some((_, separator)) = adds {
+                return separator;
+            }
+            found = found.or_else(|| [PATH_SEPARATOR, ';', ',', '|', ':'].iter().copied().find(|c| value.contains(*c)));
+        }
+        found.unwrap_or(PATH_SEPARATOR)
+    }
+
+    // What `line` appends or prepends to `var`, if anything
+    fn adds(&self, line: &str, var: &str) -> Option<String> {
+        let (name, value) = self.shell.parse_line(line)?;
+        if name != var {
+            return None;
+        }
+        self.shell
+            .appended(var, &value)
+            .or_else(|| self.shell.prepended(var, &value))
+    }
+}
+
+/// Fails if `value` has a newline that can't be
+/// escaped, since every line we write has to stay one line.
+pub(crate) fn one_line(shell: Shell, quoting: Quoting, var: &str, value: &str) -> io::Result<()> {
+    if !value.contains(['\n', '\r']) {
+        return Ok(());
+    }
+    let why = if quoting == Quoting::None {
+        "with Quoting::None".to_string()
+    } else if !shell.escapes_newlines() {
+        format!("in {:?} profiles", shell)
+    } else {
+        return Ok(());
+    };
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("The value for {} has a newline, which can't be written {}", var, why),
+    ))
+}
+
+// Drops the lines matching `f` along with
+// the blank line we put before each one
+fn drop_lines<F: Fn(&str) -> bool>(lines: Vec<String>, f: F) -> Vec<String> {
+    let mut kept: Vec<String> = Vec::with_capacity(lines.len());
+    for line in lines {
+        if f(&line) {
+            if kept.last().is_some_and(|last| last.is_empty()) {
+                kept.pop();
+            }
+        } else {
+            kept.push(line);
+        }
+    }
+    kept
+}
+
+/// Adds `line` to the file at `path`, in the app's block
+/// if there is one, unless a line there already matches
+/// it, ignoring indentation.
+/// Gives whether it was written.
+pub(crate) fn ensure_line(path: &Path, line: &str, writer: Writer) -> io::Result<bool> {
+    lock::locked(|| {
+        if line.contains(['\n', '\r']) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Only one line can be added at a time"));
+        }
+        if read_lines(path)?.iter().any(|existing| existing.trim() == line.trim()) {
+            return Ok(false);
+        }
+        add_lines(path, &[line.to_string()], writer)?;
+        Ok(true)
+    })
+}
+
+/// Appends `lines` to the file at `path`, or to the
+/// end of the app's block in it when there's an app.
+pub(crate) fn add_lines(path: &Path, lines: &[String], writer: Writer) -> io::Result<()> {
+    match writer.app {
+        Some(_) => write_in_block(path, lines, writer, |_, _| false),
+        None => write_block(path, lines, writer),
+    }
+}
+
+/// The comments that start and end `app`'s block.
+pub(crate) fn markers(app: &str) -> (String, String) {
+    (format!("# >>> env_perm ({}) >>>", app), format!("# <<< env_perm ({}) <<<", app))
+}
+
+/// Where `app`'s block is in `lines`, as the
+/// indexes of its start and end comments.
+pub(crate) fn find_block(lines: &[String], app: &str) -> Option<(usize, usize)> {
+    let (start, end) = markers(app);
+    let open = lines.iter().position(|line| line.trim() == start)?;
+    let close = open + lines[open..].iter().position(|line| line.trim() == end)?;
+    Some((open, close))
+}
+
+/// `lines` without `app`'s block or the
+/// blank line we put before it.
+pub(crate) fn remove_block(mut lines: Vec<String>, app: &str) -> Vec<String> {
+    if let Some((open, close)) = find_block(&lines, app) {
+        let start = if open > 0 && lines[open - 1].is_empty() { open - 1 } else { open };
+        lines.drain(start..=close);
+    }
+    lines
+}
+
+/// Puts `lines` in the app's block in the file at `path`,
+/// starting the block at the end of the file if it doesn't
+/// have one yet. Lines the block has already are left
+/// alone. Each of the others goes in place of the lines
+/// `replaces(existing, line)` says it replaces, or at
+/// the end of the block if there aren't any.
+pub(crate) fn write_in_block<F>(path: &Path, lines: &[String], writer: Writer, replaces: F) -> io::Result<()>
+where F: Fn(&str, &str) -> bool,
+{
+    block_write(path, lines, writer, &replaces)
+}
+
+fn block_write(path: &Path, lines: &[String], writer: Writer, replaces: &dyn Fn(&str, &str) -> bool) -> io::Result<()> {
+    lock::locked(|| {
+        let app = writer.app.unwrap_or_default();
+        if app.is_empty() || app.contains(['\n', '\r']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} can't be used to name a block", app),
+            ));
+        }
+        let (contents, stamp) = read_stamped(path)?;
+        let mut existing = lines_of(contents);
+        let before = existing.clone();
+        let (open, mut close) = match find_block(&existing, app) {
+            Some(block) => block,
+            None => {
+                let (start, end) = markers(app);
+                existing.extend([String::new(), start, end]);
+                (existing.len() - 2, existing.len() - 1)
+            }
+        };
+        for line in lines {
+            let block = open + 1..close;
+            if existing[block.clone()].iter().any(|old| old.trim() == line.trim()) {
+                continue;
+            }
+            match existing[block].iter().position(|old| replaces(old, line)) {
+                Some(i) => {
+                    existing[open + 1 + i] = line.clone();
+                    // Anything else it replaces is stale too
+                    let mut j = open + 2 + i;
+                    while j < close {
+                        if replaces(&existing[j], line) {
+                            existing.remove(j);
+                            close -= 1;
+                        } else {
+                            j += 1;
+                        }
+                    }
+                }
+                None => {
+                    existing.insert(close, line.clone());
+                    close += 1;
+                }
+            }
+        }
+        if existing == before {
+            return Ok(());
+        }
+        if !plan::planning() {
+            match confirm::ask(path, &before, &existing, writer.app) {
+                Decision::Write => {}
+                Decision::Skip => return Ok(()),
+                Decision::Redirect(to) => return confirm::with_hook(None, || block_write(&to, lines, writer, replaces)),
+            }
+        }
+        check_stamp(path, &stamp)?;
+        write_lines(path, &existing, writer)
+    })
+}
+
+/// Appends `lines` to the file at `path` together
+/// in one write, creating it if needed.
+/// The file is backed up first and the change
+/// is recorded in the journal.
+pub(crate) fn write_block(path: &Path, lines: &[String], writer: Writer) -> io::Result<()> {
+    lock::locked(|| {
+        if plan::planning() {
+            let mut planned = contents(path)?.unwrap_or_default();
+            planned.push_str(&format!("\n{}\n", lines.join("\n")));
+            return plan::write_file(path, planned, writer);
+        }
+        match confirm::ask(path, &[], lines, writer.app) {
+            Decision::Write => {}
+            Decision::Skip => return Ok(()),
+            Decision::Redirect(to) => return confirm::with_hook(None, || write_block(&to, lines, writer)),
+        }
+        // Some shells keep their config in directories
+        // that may not exist yet
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        back_up(path, writer.backups)?;
+        let previous = contents(path)?;
+        let mut profile = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?;
+        writeln!(profile, "\n{}", lines.join("\n"))?;
+        profile.flush()?;
+        // Appending leaves the blocks as they were
+        let blocks = tamper::checksums(&lines_of(previous.clone()));
+        journal::record_file(path, writer.app, previous, Some(&read_lines(path)?), blocks)
+    })
+}
+
+/// The lines of the file at `path`,
+/// or nothing if it doesn't exist.
+pub(crate) fn read_lines(path: &Path) -> io::Result<Vec<String>> {
+    Ok(lines_of(contents(path)?))
+}
+
+pub(crate) fn lines_of(contents: Option<String>) -> Vec<String> {
+    contents.map_or_else(Vec::new, |contents| contents.lines().map(String::from).collect())
+}
+
+/// What a file looked like when it was read: when it was
+/// last modified and a hash of what was in it, or None
+/// for both if it didn't exist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Stamp {
+    modified: Option<SystemTime>,
+    hash: Option<u64>,
+}
+
+/// What's in the file at `path`, like [`contents`],
+/// and its [`Stamp`] to check it against later.
+pub(crate) fn read_stamped(path: &Path) -> io::Result<(Option<String>, Stamp)> {
+    // The time is taken first so a change while
+    // reading shows up as a newer one
+    let modified = match fs::metadata(path) {
+        Ok(metadata) => Some(metadata.modified()?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let contents = contents(path)?;
+    let hash = contents.as_ref().map(|contents| {
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        hasher.finish()
+    });
+    Ok((contents, Stamp { modified: modified.filter(|_| hash.is_some()), hash }))
+}
+
+/// Fails with a [`Conflict`] if the file at `path`
+/// isn't the way it was when `stamp` was taken.
+pub(crate) fn check_stamp(path: &Path, stamp: &Stamp) -> io::Result<()> {
+    if read_stamped(path)?.1 != *stamp {
+        return Err(Conflict { place: Place::File(path.to_path_buf()) }.into());
+    }
+    Ok(())
+}
+
+/// Rewrites the file at `path` with the lines returned by `f`.
+/// The file is only written if something changed
+/// and is left alone if it doesn't exist.
+pub(crate) fn edit<F>(path: &Path, writer: Writer, f: F) -> io::Result<()>
+where F: FnOnce(Vec<String>) -> Vec<String>,
+{
+    lock::locked(|| {
+        let (contents, stamp) = match read_stamped(path)? {
+            (Some(contents), stamp) => (contents, stamp),
+            (None, _) => return Ok(()),
+        };
+        let before: Vec<String> = contents.lines().map(String::from).collect();
+        let lines = f(before.clone());
+        if render(&lines) != contents {
+            if !plan::planning() {
+                match confirm::ask(path, &before, &lines, writer.app) {
+                    Decision::Write => {}
+                    Decision::Skip => return Ok(()),
+                    Decision::Redirect(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Rewriting {} can't be redirected to another file", path.display()),
+                        ))
+                    }
+                }
+            }
+            check_stamp(path, &stamp)?;
+            write_lines(path, &lines, writer)?;
+        }
+        Ok(())
+    })
+}
+
+/// Replaces the contents of the file at `path` with `lines`,
+/// backing it up first, and records the change in the journal.
+/// While planning it's only planned.
+pub(crate) fn write_lines(path: &Path, lines: &[String], writer: Writer) -> io::Result<()> {
+    if plan::planning() {
+        return plan::write_file(path, render(lines), writer);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    back_up(path, writer.backups)?;
+    let previous = contents(path)?;
+    replace(path, render(lines).as_bytes())?;
+    journal::record_file(path, writer.app, previous, Some(lines), tamper::checksums(lines))
+}
+
+/// Puts `lines` in the file at `path`, or deletes it for
+/// None, backing it up first. Unlike [`write_lines`] it
+/// isn't journaled, for undoing what the journal has.
+pub(crate) fn put_back(path: &Path, lines: Option<&[String]>, writer: Writer) -> io::Result<()> {
+    back_up(path, writer.backups)?;
+    match lines {
+        Some(lines) => {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            replace(path, render(lines).as_bytes())
+        }
+        None => match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    }
+}
+
+/// Deletes the file at `path`, backing it up first, and
+/// records the change in the journal.
+/// While planning it's only planned.
+pub(crate) fn remove_file(path: &Path, writer: Writer) -> io::Result<()> {
+    if plan::planning() {
+        return plan::remove_file(path, writer);
+    }
+    let previous = match contents(path)? {
+        Some(previous) => previous,
+        None => return Ok(()),
+    };
+    back_up(path, writer.backups)?;
+    fs::remove_file(path)?;
+    journal::record_file(path, writer.app, Some(previous), None, Vec::new())
+}
+
+// Writes `contents` to a temporary file next to the one at
+// `path` and renames it over it, so a crash part way through
+// leaves either the old file or the new one and never half
+// of one. The old file's permissions and owner are kept and
+// a symlink is followed so the file it points to is replaced.
+pub(crate) fn replace(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = match fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+        Err(e) => return Err(e),
+    };
+    let mut name = OsStr::new(".").to_os_string();
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".env_perm.tmp.{}", std::process::id()));
+    let temp = path.with_file_name(name);
+    let result = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            fs::set_permissions(&temp, metadata.permissions())?;
+            keep_owner(&temp, &metadata)?;
+        }
+        fs::rename(&temp, &path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(unix)]
+fn keep_owner(temp: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let current = fs::metadata(temp)?;
+    if (current.uid(), current.gid()) == (metadata.uid(), metadata.gid()) {
+        return Ok(());
+    }
+    std::os::unix::fs::chown(temp, Some(metadata.uid()), Some(metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn keep_owner(_temp: &Path, _metadata: &fs::Metadata) -> io::Result<()> {
+    Ok(())
+}
+
+// Copies the file at `path` to `<path>.env_perm.bak.<milliseconds>`
+// and deletes all but the newest `keep` of those copies.
+fn back_up(path: &Path, keep: usize) -> io::Result<()> {
+    if keep == 0 || !path.is_file() {
+        return Ok(());
+    }
+    let mut stamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let mut backup = backup_path(path, stamp);
+    // Two writes in the same millisecond
+    while backup.exists() {
+        stamp += 1;
+        backup = backup_path(path, stamp);
+    }
+    fs::copy(path, &backup)?;
+    let backups = backups(path)?;
+    for old in &backups[..backups.len().saturating_sub(keep)] {
+        fs::remove_file(old)?;
+    }
+    Ok(())
+}
+
+fn backup_path(path: &Path, stamp: u128) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".env_perm.bak.{}", stamp));
+    path.with_file_name(name)
+}
+
+/// The backups of the file at `path`, oldest first.
+pub(crate) fn backups(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let prefix = match path.file_name().and_then(OsStr::to_str) {
+        Some(name) => format!("{}.env_perm.bak.", name),
+        None => return Ok(Vec::new()),
+    };
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut backups: Vec<(u128, PathBuf)> = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let stamp = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix(&prefix)?.parse().ok());
+        if let Some(stamp) = stamp {
+            backups.push((stamp, entry.path()));
+        }
+    }
+    backups.sort();
+    Ok(backups.into_iter().map(|(_, backup)| backup).collect())
+}
+
+/// Puts the newest backup of the file at `path` back in
+/// its place, so the one before it is next in line.
+/// The change is recorded in the journal.
+/// Gives whether there was one.
+pub(crate) fn restore_backup(path: &Path, writer: Writer) -> io::Result<bool> {
+    lock::locked(|| match backups(path)?.pop() {
+        Some(backup) if plan::planning() => {
+            let contents = fs::read_to_string(backup)?;
+            plan::write_file(path, contents, Writer { backups: 0, ..writer })?;
+            Ok(true)
+        }
+        Some(backup) => {
+            let previous = contents(path)?;
+            replace(path, &fs::read(&backup)?)?;
+            fs::remove_file(backup)?;
+            let lines = read_lines(path)?;
+            journal::record_file(path, writer.app, previous, Some(&lines), tamper::checksums(&lines))?;
+            Ok(true)
+        }
+        None => Ok(false),
+    })
+}
+
+/// What's in the file at `path`, or None if it doesn't exist.
+/// While planning it's what's been planned for it.
+pub(crate) fn contents(path: &Path) -> io::Result<Option<String>> {
+    if let Some(planned) = plan::planned(&Place::File(path.to_path_buf())) {
+        return Ok(planned);
+    }
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn render(lines: &[String]) -> String {
+    let mut contents = lines.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use crate::Config;
+
+    // The file `text` ends up as after `f` runs with a
+    // bash backend for it
+    fn edited<F: FnOnce(&ProfileFiles)>(text: &str, f: F) -> String {
+        let dir = TempDir::new("profile");
+        let path = dir.path().join("profile");
+        fs::write(&path, text).unwrap();
+        f(&Config::new().shell(Shell::Bash).file(&path));
+        fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn remove_entry_uses_the_lists_separator() {
+        let text = "export LIST='$HOME/a,b,$HOME/c'\n\nexport LIST=\"$LIST,d\"\n";
+        assert_eq!(edited(text, |files| files.remove_entry("LIST", "b").unwrap()), "export LIST='$HOME/a,$HOME/c'\n\nexport LIST=\"$LIST,d\"\n");
+        assert_eq!(edited(text, |files| files.remove_entry("LIST", "d").unwrap()), "export LIST='$HOME/a,b,$HOME/c'\n");
+        assert_eq!(edited(text, |files| files.remove_entry("LIST", "$HOME/c").unwrap()), "export LIST='$HOME/a,b'\n\nexport LIST=\"$LIST,d\"\n");
+        assert_eq!(edited("export LIST=\"a|b\"\n", |files| files.remove_entry("LIST", "a").unwrap()), "export LIST=\"b\"\n");
+    }
+
+    #[test]
+    fn remove_entry_keeps_the_line_as_written() {
+        let text = "export PATH=\"$HOME/bin:/opt/x:${HOME}/y\" # mine\n";
+        assert_eq!(edited(text, |files| files.remove_entry("PATH", "/opt/x").unwrap()), "export PATH=\"$HOME/bin:${HOME}/y\" # mine\n");
+        assert_eq!(edited("export PATH=/a:/b:/a\n", |files| files.remove_entry("PATH", "/a").unwrap()), "export PATH=/b\n");
+    }
+
+    #[test]
+    fn escaped_entries_are_written_again() {
+        let text = "export LIST=\"a\\\"b:c\"\n";
+        assert_eq!(edited(text, |files| files.remove_entry("LIST", "a\"b").unwrap()), "export LIST=c\n");
+    }
+
+    #[test]
+    fn dedupe_uses_the_lists_separator() {
+        assert_eq!(edited("export LIST=\"a,b,a\"\n", |files| files.dedupe("LIST").unwrap()), "export LIST=\"a,b\"\n");
+        let text = "export LIST='a,$HOME'\n\nexport LIST=\"$LIST,a\"\n";
+        assert_eq!(edited(text, |files| files.dedupe("LIST").unwrap()), "export LIST='a,$HOME'\n");
+        let text = "export LIST='b,a'\n\nexport LIST=\"a,$LIST\"\n";
+        assert_eq!(edited(text, |files| files.dedupe("LIST").unwrap()), "export LIST='b'\n\nexport LIST=\"a,$LIST\"\n");
+    }
+
+    #[test]
+    fn update_asks_before_rewriting() {
+        let text = "export A=1\n";
+        let hook = confirm::Hook(std::sync::Arc::new(|_: &confirm::PendingChange| Decision::Skip));
+        let after = edited(text, |files| confirm::with_hook(Some(&hook), || files.update("A", "2")).unwrap());
+        assert_eq!(after, text);
+        assert_eq!(edited(text, |files| files.update("A", "2").unwrap()), "export A=2\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn append_with_os_escapes_bytes() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let after = edited("", |files| files.append_with_os("LIST", OsStr::from_bytes(b"a\xff"), ',').unwrap());
+        assert_eq!(after, "\nexport LIST=\"$LIST,a\"$'\\xff'\"\"\n");
+    }
+
+    #[test]
+    fn contains_uses_the_lists_separator() {
+        edited("export LIST=\"a,b\"\n", |files| {
+            assert!(files.contains("LIST", "b").unwrap());
+            assert!(!files.contains("LIST", "a,b").unwrap());
+        });
+    }
+}
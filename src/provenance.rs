@@ -0,0 +1,91 @@
+//! Comments saying who wrote a line, so users can
+//! tell where exports they don't recognise came from.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::profile;
+use crate::Shell;
+
+/// Where a line in a profile came from, as the comment
+/// [`Config::annotate`](crate::Config::annotate) puts
+/// at the end of it says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Provenance {
+    /// The variable the line assigns to.
+    pub var: String,
+    /// The file it's in.
+    pub file: PathBuf,
+    /// The app that wrote it, if any.
+    pub app: Option<String>,
+    /// The day it was written, as `YYYY-MM-DD`.
+    pub date: String,
+}
+
+/// Every line in the file at `path` with a comment from
+/// [`comment`], reading assignments as `shell` writes them.
+pub(crate) fn read(shell: Shell, path: &Path) -> io::Result<Vec<Provenance>> {
+    let mut found = Vec::new();
+    for line in profile::read_lines(path)? {
+        if let (code, Some((app, date))) = split(line.trim()) {
+            if let Some((var, _)) = shell.parse_line(code) {
+                found.push(Provenance { var, file: path.to_path_buf(), app, date });
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// The comment to put at the end of a line written today,
+/// e.g. `# added by myapp 2024-05-01 via env_perm`.
+pub(crate) fn comment(app: Option<&str>) -> String {
+    let date = date(SystemTime::now());
+    match app {
+        Some(app) => format!("# added by {} {} via env_perm", app, date),
+        None => format!("# added {} via env_perm", date),
+    }
+}
+
+/// `line` without the comment from [`comment`] at the end,
+/// and the app and date it gives if it had one.
+pub(crate) fn split(line: &str) -> (&str, Option<(Option<String>, String)>) {
+    match parse(line) {
+        Some((code, app, date)) => (code, Some((app, date))),
+        None => (line, None),
+    }
+}
+
+fn parse(line: &str) -> Option<(&str, Option<String>, String)> {
+    let (code, comment) = line.rsplit_once(" # added ")?;
+    let rest = comment.trim_end().strip_suffix(" via env_perm")?;
+    let (app, date) = match rest.strip_prefix("by ") {
+        Some(rest) => {
+            let (app, date) = rest.rsplit_once(' ')?;
+            (Some(app.to_string()), date)
+        }
+        None => (None, rest),
+    };
+    let is_date = date.len() == 10 && date.chars().enumerate().all(|(i, c)| if i == 4 || i == 7 { c == '-' } else { c.is_ascii_digit() });
+    if !is_date {
+        return None;
+    }
+    Some((code.trim_end(), app, date.to_string()))
+}
+
+// The UTC day `time` is in, as `YYYY-MM-DD`
+fn date(time: SystemTime) -> String {
+    let days = (time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86400) as i64;
+    // Howard Hinnant's civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -0,0 +1,133 @@
+//! Gathering up everything a set of changes wrote
+//! for an installer to show once it's done.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::{Change, Place};
+
+thread_local! {
+    // What's been written so far while `collect` runs
+    static COLLECTING: RefCell<Option<Vec<Change>>> = const { RefCell::new(None) };
+}
+
+/// Everything the changes made with a [`Config`](crate::Config)
+/// given to [`Config::report`](crate::Config::report) wrote,
+/// for a GUI installer's summary page.
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use env_perm::{ChangeReport, Config};
+///
+/// let path = std::env::temp_dir().join("env_perm_report.sh");
+/// # let _ = std::fs::remove_file(&path);
+/// let report = Arc::new(Mutex::new(ChangeReport::default()));
+/// let config = Config::new().report(report.clone());
+/// config.set_in(&path, "MYAPP_HOME", "/opt/myapp").unwrap();
+/// config.append_in(&path, "PATH", "/opt/myapp/bin").unwrap();
+/// let report = report.lock().unwrap();
+/// assert_eq!(report.files.len(), 1);
+/// assert_eq!(report.lines_added.len(), 2);
+/// assert!(report.new_terminal);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChangeReport {
+    /// Every file written to, in the order
+    /// they were first written.
+    pub files: Vec<PathBuf>,
+    /// Each line added and the file it went in, in order.
+    pub lines_added: Vec<(PathBuf, String)>,
+    /// Every registry value written or deleted, as
+    /// the key it's under and the variable.
+    pub registry: Vec<(String, String)>,
+    /// Whether the user has to open a new terminal to
+    /// see the changes, which is whenever anything was
+    /// written since running shells don't read it again.
+    pub new_terminal: bool,
+}
+
+impl ChangeReport {
+    /// Whether nothing was written.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.registry.is_empty()
+    }
+
+    pub(crate) fn add(&mut self, change: &Change) {
+        match &change.place {
+            Place::File(path) => {
+                if !self.files.contains(path) {
+                    self.files.push(path.clone());
+                }
+                self.lines_added.extend(change.added.iter().map(|line| (path.clone(), line.clone())));
+            }
+            Place::Registry { key, var } => {
+                let value = (key.clone(), var.clone());
+                if !self.registry.contains(&value) {
+                    self.registry.push(value);
+                }
+            }
+        }
+        self.new_terminal = true;
+    }
+}
+
+impl fmt::Display for ChangeReport {
+    /// A line for each file and registry value
+    /// changed and each line added.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for path in &self.files {
+            writeln!(f, "Changed {}", path.display())?;
+            for (_, line) in self.lines_added.iter().filter(|(file, _)| file == path) {
+                writeln!(f, "  + {}", line)?;
+            }
+        }
+        for (key, var) in &self.registry {
+            writeln!(f, "Changed {}\\{}", key, var)?;
+        }
+        if self.new_terminal {
+            writeln!(f, "Open a new terminal to use the changes")?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs `f`, giving what it wrote to the journal
+/// as well as what it gave.
+pub(crate) fn collect<T, F: FnOnce() -> io::Result<T>>(f: F) -> (io::Result<T>, Vec<Change>) {
+    COLLECTING.with(|collecting| *collecting.borrow_mut() = Some(Vec::new()));
+    let collector = Collector;
+    let result = f();
+    (result, collector.finish())
+}
+
+// Stops collecting when dropped so
+// a panic in `f` doesn't leave it on
+struct Collector;
+
+impl Collector {
+    fn finish(self) -> Vec<Change> {
+        COLLECTING.with(|collecting| collecting.borrow_mut().take()).unwrap_or_default()
+    }
+}
+
+impl Drop for Collector {
+    fn drop(&mut self) {
+        COLLECTING.with(|collecting| collecting.borrow_mut().take());
+    }
+}
+
+/// Whether changes are being collected.
+pub(crate) fn collecting() -> bool {
+    COLLECTING.with(|collecting| collecting.borrow().is_some())
+}
+
+/// Adds `change` to what's being collected, if anything is.
+pub(crate) fn note(change: &Change) {
+    COLLECTING.with(|collecting| {
+        if let Some(changes) = collecting.borrow_mut().as_mut() {
+            changes.push(change.clone());
+        }
+    })
+}
@@ -0,0 +1,174 @@
+//! Persisting variables for one windows service
+//! rather than for users.
+
+use std::ffi::OsStr;
+use std::io;
+
+use crate::windows;
+use crate::{Backend, EnvBackend};
+
+/// A backend for the variables of the windows service called
+/// `name`, which the service control manager gives it when
+/// it starts. They're the `Environment` value under
+/// `HKLM\SYSTEM\CurrentControlSet\Services\<name>`, a
+/// `REG_MULTI_SZ` of `VAR=value` strings, so writing needs
+/// the process to be run as administrator and the service
+/// has to be restarted to see a change.
+///
+/// Use it with [`Config::custom_backend`](crate::Config::custom_backend).
+/// Like other custom backends it isn't journaled.
+/// ```no_run
+/// use env_perm::{Config, ServiceEnvironment};
+///
+/// let config = Config::new().custom_backend(ServiceEnvironment::new("MyAgent"));
+/// config.set("MYAGENT_LOG", r"C:\ProgramData\MyAgent\agent.log").unwrap();
+/// config.append("PATH", r"C:\Program Files\MyAgent\bin").unwrap();
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServiceEnvironment {
+    name: String,
+}
+
+impl ServiceEnvironment {
+    /// The variables of the service called `name`,
+    /// its short name rather than the display name.
+    pub fn new<T: Into<String>>(name: T) -> ServiceEnvironment {
+        ServiceEnvironment { name: name.into() }
+    }
+
+    /// The service's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The key the variables are under.
+    pub fn key(&self) -> String {
+        format!(r"HKLM\SYSTEM\CurrentControlSet\Services\{}", self.name)
+    }
+
+    // Changes the `VAR=value` strings with `f`
+    fn edit<F: FnOnce(&mut Vec<String>)>(&self, f: F) -> io::Result<()> {
+        let mut entries = self.entries()?;
+        let before = entries.clone();
+        f(&mut entries);
+        if entries == before {
+            return Ok(());
+        }
+        self.write(&entries)
+    }
+
+    // Gives `var` the value `f` makes of the one it has
+    fn update<F: FnOnce(Option<&str>) -> String>(&self, var: &str, f: F) -> io::Result<()> {
+        let mut entries = self.entries()?;
+        let i = entries.iter().position(|entry| is_var(entry, var));
+        let value = f(i.map(|i| &entries[i][var.len() + 1..]));
+        windows::fits(var, OsStr::new(&value), windows::REGISTRY_LIMIT, Backend::WindowsRegistry)?;
+        let entry = format!("{}={}", var, value);
+        match i {
+            Some(i) if entries[i] == entry => return Ok(()),
+            Some(i) => entries[i] = entry,
+            None => entries.push(entry),
+        }
+        self.write(&entries)
+    }
+}
+
+impl EnvBackend for ServiceEnvironment {
+    fn set(&self, var: &str, value: &str) -> io::Result<()> {
+        self.update(var, |_| value.to_string())
+    }
+
+    fn append(&self, var: &str, value: &str) -> io::Result<()> {
+        self.update(var, |current| join(current, Some(value)))
+    }
+
+    fn prepend(&self, var: &str, value: &str) -> io::Result<()> {
+        self.update(var, |current| join(Some(value), current))
+    }
+
+    fn remove(&self, var: &str) -> io::Result<()> {
+        self.edit(|entries| entries.retain(|entry| !is_var(entry, var)))
+    }
+
+    fn get(&self, var: &str) -> io::Result<Option<String>> {
+        Ok(self.entries()?.into_iter().find(|entry| is_var(entry, var)).map(|entry| entry[var.len() + 1..].to_string()))
+    }
+
+    fn list(&self) -> io::Result<Vec<(String, String)>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .filter_map(|entry| entry.split_once('=').map(|(var, value)| (var.to_string(), value.to_string())))
+            .collect())
+    }
+
+    // Windows lists are separated with `;`
+    fn contains(&self, var: &str, entry: &str) -> io::Result<bool> {
+        Ok(self.get(var)?.is_some_and(|value| value.split(';').any(|part| part == entry)))
+    }
+}
+
+// Whether `entry` is `var=...`, ignoring case like windows does
+fn is_var(entry: &str, var: &str) -> bool {
+    entry.len() > var.len() && entry.as_bytes()[var.len()] == b'=' && entry[..var.len()].eq_ignore_ascii_case(var)
+}
+
+fn join(first: Option<&str>, second: Option<&str>) -> String {
+    windows::join(first.map(OsStr::new), second.map(OsStr::new), ';').to_string_lossy().into_owned()
+}
+
+#[cfg(windows)]
+impl ServiceEnvironment {
+    fn entries(&self) -> io::Result<Vec<String>> {
+        let key = self.open(winreg::enums::KEY_READ)?;
+        match key.get_value::<Vec<String>, _>("Environment") {
+            Ok(entries) => Ok(entries),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, entries: &[String]) -> io::Result<()> {
+        use winreg::enums::{KEY_READ, KEY_WRITE};
+
+        let key = self.open(KEY_READ | KEY_WRITE)?;
+        let result = if entries.is_empty() {
+            key.delete_value("Environment")
+        } else {
+            key.set_value("Environment", &entries.to_vec())
+        };
+        result.map_err(|e| match e.kind() {
+            io::ErrorKind::PermissionDenied => io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Writing to {} needs the process to be run as administrator", self.key()),
+            ),
+            _ => e,
+        })
+    }
+
+    // The service's key, which has to be there already
+    fn open(&self, flags: u32) -> io::Result<winreg::RegKey> {
+        let path = format!(r"SYSTEM\CurrentControlSet\Services\{}", self.name);
+        winreg::HKLM.open_subkey_with_flags(path, flags).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(io::ErrorKind::NotFound, format!("There's no service called {}", self.name)),
+            _ => e,
+        })
+    }
+}
+
+#[cfg(not(windows))]
+impl ServiceEnvironment {
+    fn entries(&self) -> io::Result<Vec<String>> {
+        Err(unsupported())
+    }
+
+    fn write(&self, _entries: &[String]) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(not(windows))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "Windows services only exist on windows")
+}
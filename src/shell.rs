@@ -0,0 +1,1335 @@
+//! Working out which shell the user runs
+//! and which startup files it reads.
+
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::provenance;
+use crate::{Config, Outcome, Overrides, Quoting, Target, ToEnvValue};
+
+/// The shells we know how to write profiles for.
+///
+/// Use [`Shell::detect`] to find out which one
+/// the free functions like [`set`](crate::set) will target,
+/// or pick one yourself and call its methods directly.
+/// ```no_run
+/// use env_perm::Shell;
+///
+/// // export DUMMY=1 to the fish config
+/// // even if fish isn't the user's login shell
+/// Shell::Fish.set("DUMMY", 1).expect("Failed to set DUMMY");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Nu,
+    /// csh and tcsh
+    Csh,
+    /// Windows PowerShell and PowerShell 7
+    Pwsh,
+    Elvish,
+    Xonsh,
+    /// ksh, mksh and the other korn shells
+    Ksh,
+    /// Plain POSIX sh like dash or ash,
+    /// which only reads `.profile`
+    Sh,
+}
+
+pub(crate) const ALL: [Shell; 10] = [
+    Shell::Bash,
+    Shell::Zsh,
+    Shell::Fish,
+    Shell::Nu,
+    Shell::Csh,
+    Shell::Pwsh,
+    Shell::Elvish,
+    Shell::Xonsh,
+    Shell::Ksh,
+    Shell::Sh,
+];
+
+impl Shell {
+    /// Works out the user's shell from `$SHELL`,
+    /// falling back to the login shell in the
+    /// directory service on macOS or `/etc/passwd`.
+    /// If none of those give a shell we recognise
+    /// it falls back to the platform default, which is
+    /// PowerShell on windows, zsh on macOS and bash elsewhere.
+    ///
+    /// If the user has set `ENV_PERM_SHELL`
+    /// that is used instead, so someone on macOS who
+    /// really does use bash can opt out with `ENV_PERM_SHELL=bash`.
+    pub fn detect() -> Shell {
+        Overrides::from_env()
+            .shell
+            .or_else(|| env::var_os("SHELL")
+                .and_then(|shell| Shell::from_path(Path::new(&shell))))
+            .or_else(|| dscl_shell().and_then(|shell| Shell::from_path(&shell)))
+            .or_else(|| termux_shell().and_then(|shell| Shell::from_path(&shell)))
+            .or_else(|| login_shell().and_then(|shell| Shell::from_path(&shell)))
+            .unwrap_or(if cfg!(windows) {
+                Shell::Pwsh
+            } else if cfg!(target_os = "macos") {
+                // The default since Catalina
+                Shell::Zsh
+            } else {
+                Shell::Bash
+            })
+    }
+
+    pub(crate) fn from_path(path: &Path) -> Option<Shell> {
+        match path.file_name()?.to_str()? {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            "nu" => Some(Shell::Nu),
+            "csh" | "tcsh" => Some(Shell::Csh),
+            "pwsh" | "pwsh-preview" => Some(Shell::Pwsh),
+            "elvish" => Some(Shell::Elvish),
+            "xonsh" => Some(Shell::Xonsh),
+            "ksh" | "ksh93" | "mksh" | "oksh" | "pdksh" => Some(Shell::Ksh),
+            "sh" | "dash" | "ash" => Some(Shell::Sh),
+            _ => None,
+        }
+    }
+
+    /// Guesses which shell a startup file is for
+    /// from its name.
+    pub(crate) fn from_file(path: &Path) -> Option<Shell> {
+        let name = path.file_name()?.to_str()?;
+        match name {
+            ".bashrc" | ".bash_profile" | ".bash_login" | ".bash_logout" => return Some(Shell::Bash),
+            ".zshrc" | ".zshenv" | ".zprofile" | ".zlogin" => return Some(Shell::Zsh),
+            ".cshrc" | ".tcshrc" | ".login" => return Some(Shell::Csh),
+            ".kshrc" | ".mkshrc" => return Some(Shell::Ksh),
+            ".profile" => return Some(Shell::Sh),
+            ".xonshrc" => return Some(Shell::Xonsh),
+            _ => {}
+        }
+        match path.extension()?.to_str()? {
+            "bash" => Some(Shell::Bash),
+            "sh" => Some(Shell::Sh),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            "nu" => Some(Shell::Nu),
+            "csh" | "tcsh" => Some(Shell::Csh),
+            "ps1" => Some(Shell::Pwsh),
+            "elv" => Some(Shell::Elvish),
+            "xsh" => Some(Shell::Xonsh),
+            "ksh" => Some(Shell::Ksh),
+            _ => None,
+        }
+    }
+
+    /// Checks if a environment variable is set.
+    /// If it is then nothing will happen.
+    /// If it's not then it will be added
+    /// to this shell's profile.
+    pub fn check_or_set<T, U>(self, var: T, value: U) -> io::Result<Outcome>
+    where T: fmt::Display + AsRef<OsStr>,
+          U: ToEnvValue,
+    {
+        Config::new().shell(self).check_or_set(var, value)
+    }
+
+    /// Appends a value to the end of an environment
+    /// variable in this shell's profile.
+    pub fn append<T: fmt::Display, U: ToEnvValue>(self, var: T, value: U) -> io::Result<Outcome> {
+        Config::new().shell(self).append(var, value)
+    }
+
+    /// Prepends a value to the front of an environment
+    /// variable in this shell's profile.
+    pub fn prepend<T: fmt::Display, U: ToEnvValue>(self, var: T, value: U) -> io::Result<Outcome> {
+        Config::new().shell(self).prepend(var, value)
+    }
+
+    /// Sets an environment variable in this
+    /// shell's profile without checking if it exists.
+    pub fn set<T: fmt::Display, U: ToEnvValue>(self, var: T, value: U) -> io::Result<Outcome> {
+        Config::new().shell(self).set(var, value)
+    }
+
+    /// Adds `line` to this shell's profile unless
+    /// it's already there.
+    pub fn ensure_line<T: fmt::Display>(self, line: T) -> io::Result<Outcome> {
+        Config::new().shell(self).ensure_line(line)
+    }
+
+    /// Makes this shell's profile run the script at
+    /// `path`, unless it does already.
+    pub fn source_file<P: AsRef<Path>>(self, path: P) -> io::Result<Outcome> {
+        Config::new().shell(self).source_file(path)
+    }
+
+    /// Removes every assignment to an environment
+    /// variable from this shell's profile.
+    pub fn unset<T: fmt::Display>(self, var: T) -> io::Result<()> {
+        Config::new().shell(self).unset(var)
+    }
+
+    /// The files we write to for `target`.
+    pub(crate) fn target_paths(self, target: Target) -> io::Result<Vec<PathBuf>> {
+        let home = home_dir()?;
+        let login = pick(self.profiles(&home));
+        let rc = pick(self.rc_profiles(&home));
+        Ok(match target {
+            Target::LoginProfile => vec![login],
+            Target::InteractiveRc => vec![rc],
+            // Most shells other than the posix ones
+            // use the same file for both
+            Target::Both if login == rc => vec![login],
+            Target::Both => vec![login, rc],
+        })
+    }
+
+    /// The profile we'd write to if it looks like
+    /// this shell is set up, None otherwise.
+    pub(crate) fn existing_profile(self, home: &Path) -> Option<PathBuf> {
+        let profiles = self.profiles(home);
+        match self {
+            // We own the fish file so check for
+            // fish's config dir instead
+            Shell::Fish => profiles[0]
+                .ancestors()
+                .nth(2)
+                .filter(|dir| dir.is_dir())
+                .map(|_| profiles[0].clone()),
+            _ => profiles.into_iter().find(|profile| profile.is_file()),
+        }
+    }
+
+    /// The profiles this shell reads in the order
+    /// we try them.
+    /// If none of them exist the first one is created.
+    pub(crate) fn profiles(self, home: &Path) -> Vec<PathBuf> {
+        match self {
+            // Termux doesn't ship a .bash_profile and creating
+            // one would stop bash reading .profile, so that's
+            // what we create there
+            Shell::Bash if is_termux() => [".bash_profile", ".bash_login"]
+                .iter()
+                .map(|name| home.join(name))
+                .filter(|profile| profile.is_file())
+                .chain(Some(home.join(".profile")))
+                .collect(),
+            Shell::Bash => vec![
+                home.join(".bash_profile"),
+                home.join(".bash_login"),
+                home.join(".profile"),
+            ],
+            // On macOS /etc/zprofile runs path_helper which
+            // rebuilds PATH after .zshenv has been read,
+            // so only .zprofile changes stick
+            Shell::Zsh if cfg!(target_os = "macos") => {
+                let dir = zdotdir(home);
+                vec![dir.join(".zprofile"), dir.join(".zshenv")]
+            }
+            Shell::Zsh => {
+                let dir = zdotdir(home);
+                vec![dir.join(".zshenv"), dir.join(".zprofile")]
+            }
+            // fish sources everything in conf.d on startup
+            // so we keep our own file there rather than
+            // touching config.fish
+            Shell::Fish => vec![config_home(home)
+                .join("fish")
+                .join("conf.d")
+                .join("env_perm.fish")],
+            Shell::Nu => vec![nu_config_dir(home).join("env.nu")],
+            // tcsh reads .tcshrc in place of .cshrc if it exists
+            Shell::Csh => vec![
+                home.join(".login"),
+                home.join(".tcshrc"),
+                home.join(".cshrc"),
+            ],
+            // This is what $PROFILE points to for the current
+            // user in Windows PowerShell and PowerShell 7
+            Shell::Pwsh if cfg!(windows) => {
+                let documents = dirs::document_dir().unwrap_or_else(|| home.join("Documents"));
+                vec![
+                    documents
+                        .join("WindowsPowerShell")
+                        .join("Microsoft.PowerShell_profile.ps1"),
+                    documents
+                        .join("PowerShell")
+                        .join("Microsoft.PowerShell_profile.ps1"),
+                ]
+            }
+            // PowerShell 7 on unix never reads the posix profiles
+            Shell::Pwsh => vec![config_home(home)
+                .join("powershell")
+                .join("Microsoft.PowerShell_profile.ps1")],
+            // Older versions of elvish used ~/.elvish
+            Shell::Elvish => vec![
+                config_home(home).join("elvish").join("rc.elv"),
+                home.join(".elvish").join("rc.elv"),
+            ],
+            Shell::Xonsh => vec![
+                home.join(".xonshrc"),
+                config_home(home).join("xonsh").join("rc.xsh"),
+            ],
+            // Login korn shells read .profile, interactive ones
+            // read whatever $ENV names (usually .kshrc)
+            Shell::Ksh => {
+                let mut profiles = vec![home.join(".profile")];
+                profiles.extend(env_file());
+                profiles.push(home.join(".kshrc"));
+                profiles
+            }
+            Shell::Sh => vec![home.join(".profile")],
+        }
+    }
+
+    /// The files interactive non-login shells read
+    /// in the order we try them.
+    /// If none of them exist the first one is created.
+    pub(crate) fn rc_profiles(self, home: &Path) -> Vec<PathBuf> {
+        match self {
+            Shell::Bash => vec![home.join(".bashrc")],
+            Shell::Zsh => vec![zdotdir(home).join(".zshrc")],
+            Shell::Csh => vec![home.join(".tcshrc"), home.join(".cshrc")],
+            Shell::Ksh => {
+                let mut profiles: Vec<_> = env_file().into_iter().collect();
+                profiles.push(home.join(".kshrc"));
+                profiles
+            }
+            // Interactive sh only reads the file named by $ENV
+            Shell::Sh => env_file()
+                .into_iter()
+                .chain(Some(home.join(".shrc")))
+                .collect(),
+            // Everything else reads the same file
+            // whether it's a login shell or not
+            _ => self.profiles(home),
+        }
+    }
+
+    /// The line that runs the script at `path` in
+    /// this shell, e.g. `. "$HOME/.cargo/env"`.
+    /// References like `$HOME` in it still expand.
+    pub(crate) fn source_line(self, path: &str) -> String {
+        let path = format!("{}\"{}\"", self.string_prefix(path, Quoting::ShellEscape), self.escape(path, true));
+        match self {
+            Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh | Shell::Pwsh => format!(". {}", path),
+            Shell::Fish | Shell::Csh | Shell::Nu | Shell::Xonsh => format!("source {}", path),
+            // elvish has no source so the file's read in and run
+            Shell::Elvish => format!("eval (slurp < {})", path),
+        }
+    }
+
+    /// The line that sets `var` to `value`,
+    /// quoted the way `quoting` says.
+    pub(crate) fn set_line(self, var: impl fmt::Display, value: impl fmt::Display, quoting: Quoting) -> String {
+        let value = self.word(&value.to_string(), quoting);
+        match self {
+            Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh => format!("export {}={}", var, value),
+            Shell::Fish => format!("set -gx {} {}", var, value),
+            Shell::Nu => format!("$env.{} = {}", var, value),
+            Shell::Csh => format!("setenv {} {}", var, value),
+            Shell::Pwsh => powershell_assignment(var, value),
+            Shell::Elvish => format!("set-env {} {}", var, value),
+            Shell::Xonsh => format!("${} = {}", var, value),
+        }
+    }
+
+    /// The line that adds `value` to the
+    /// end of the list in `var`.
+    pub(crate) fn append_line(self, var: impl fmt::Display, value: impl fmt::Display, quoting: Quoting) -> String {
+        self.append_line_with(var, value, self.separator(), quoting)
+    }
+
+    /// Like `append_line` with `separator`
+    /// between the entries.
+    pub(crate) fn append_line_with(
+        self,
+        var: impl fmt::Display,
+        value: impl fmt::Display,
+        separator: char,
+        quoting: Quoting,
+    ) -> String {
+        let var = var.to_string();
+        let raw = value.to_string();
+        let value = self.inner(&raw, quoting);
+        // The shells that keep PATH as a list join
+        // it with : themselves
+        let list = separator == ':';
+        match self {
+            Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh => {
+                format!("export {}=\"${}{}{}\"", var, var, separator, value)
+            }
+            Shell::Fish if list && var.ends_with("PATH") => {
+                format!("set -gx {} ${} {}", var, var, self.word(&raw, quoting))
+            }
+            Shell::Fish => format!("set -gx {} \"${}{}{}\"", var, var, separator, value),
+            Shell::Nu if list && (var == "PATH" || var == "Path") => {
+                format!("$env.{} = ($env.{} | append {})", var, var, self.word(&raw, quoting))
+            }
+            Shell::Nu => format!("$env.{} = $\"($env.{}){}{}\"", var, var, separator, value),
+            Shell::Csh => format!("setenv {} \"${{{}}}{}{}\"", var, var, separator, value),
+            Shell::Pwsh => {
+                // Braces stop the separator being read
+                // as part of the variable name
+                let current = format!("${{env:{}}}", var);
+                powershell_assignment(var, format!("\"{}{}{}\"", current, separator, value))
+            }
+            Shell::Elvish if list && var == "PATH" => {
+                format!("set paths = [$@paths {}]", self.word(&raw, quoting))
+            }
+            Shell::Elvish => format!("set-env {} (get-env {})\"{}{}\"", var, var, separator, value),
+            Shell::Xonsh if list && (var.ends_with("PATH") || var.ends_with("DIRS")) => {
+                format!("${}.append({})", var, self.word(&raw, quoting))
+            }
+            Shell::Xonsh => format!("${} = ${} + {}\"{}{}\"", var, var, self.string_prefix(&raw, quoting), separator, value),
+        }
+    }
+
+    /// The line that adds `value` to the
+    /// front of the list in `var`.
+    pub(crate) fn prepend_line(self, var: impl fmt::Display, value: impl fmt::Display, quoting: Quoting) -> String {
+        self.prepend_line_with(var, value, self.separator(), quoting)
+    }
+
+    /// Like `prepend_line` with `separator`
+    /// between the entries.
+    pub(crate) fn prepend_line_with(
+        self,
+        var: impl fmt::Display,
+        value: impl fmt::Display,
+        separator: char,
+        quoting: Quoting,
+    ) -> String {
+        let var = var.to_string();
+        let raw = value.to_string();
+        let value = self.inner(&raw, quoting);
+        let list = separator == ':';
+        match self {
+            Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh => {
+                format!("export {}=\"{}{}${}\"", var, value, separator, var)
+            }
+            // fish keeps variables ending in PATH as lists
+            // and joins them with : when exporting
+            Shell::Fish if list && var.ends_with("PATH") => {
+                format!("set -gx {} {} ${}", var, self.word(&raw, quoting), var)
+            }
+            Shell::Fish => format!("set -gx {} \"{}{}${}\"", var, value, separator, var),
+            // nu converts PATH into a list on startup
+            // but leaves everything else as a string
+            Shell::Nu if list && (var == "PATH" || var == "Path") => {
+                format!("$env.{} = ($env.{} | prepend {})", var, var, self.word(&raw, quoting))
+            }
+            Shell::Nu => format!("$env.{} = $\"{}{}($env.{})\"", var, value, separator, var),
+            Shell::Csh => format!("setenv {} \"{}{}${{{}}}\"", var, value, separator, var),
+            Shell::Pwsh => {
+                let current = if is_identifier(&var) {
+                    format!("$env:{}", var)
+                } else {
+                    format!("${{env:{}}}", var)
+                };
+                powershell_assignment(var, format!("\"{}{}{}\"", value, separator, current))
+            }
+            // elvish keeps PATH in sync with the $paths list
+            Shell::Elvish if list && var == "PATH" => {
+                format!("set paths = [{} $@paths]", self.word(&raw, quoting))
+            }
+            Shell::Elvish => format!("set-env {} \"{}{}\"(get-env {})", var, value, separator, var),
+            // xonsh treats variables ending in PATH or DIRS as lists
+            Shell::Xonsh if list && (var.ends_with("PATH") || var.ends_with("DIRS")) => {
+                format!("${}.insert(0, {})", var, self.word(&raw, quoting))
+            }
+            Shell::Xonsh => format!("${} = {}\"{}{}\" + ${}", var, self.string_prefix(&raw, quoting), value, separator, var),
+        }
+    }
+
+    /// What this shell's lists are separated with.
+    /// PowerShell follows the platform, everything
+    /// else is a unix shell even on windows.
+    pub(crate) fn separator(self) -> char {
+        match self {
+            Shell::Pwsh => PATH_SEPARATOR,
+            _ => ':',
+        }
+    }
+
+    /// Escapes `value` so it can go between double quotes
+    /// in this shell and come out as written, e.g.
+    /// `hello "world"$` is `hello \"world\"\$` for bash.
+    /// With `references` variables like `$HOME` or `${HOME}`
+    /// are left alone so they still expand. xonsh only
+    /// expands them in f-strings, see `string_prefix`, so
+    /// there they're written `{$HOME}` and braces doubled.
+    pub(crate) fn escape(self, value: &str, references: bool) -> String {
+        let format = self == Shell::Xonsh && references && has_reference(value);
+        let mut escaped = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(c) = rest.chars().next() {
+            if let Some(len) = reference_len(rest).filter(|_| references) {
+                if format {
+                    let name = rest[1..len].trim_start_matches('{').trim_end_matches('}');
+                    escaped.push_str(&format!("{{${}}}", name));
+                } else {
+                    escaped.push_str(&rest[..len]);
+                }
+                rest = &rest[len..];
+                continue;
+            }
+            if let Some(control) = self.control(c) {
+                escaped.push_str(&control);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            match (self, c) {
+                (Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh, '\\' | '"' | '`' | '$') => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                (Shell::Fish, '\\' | '"' | '$') => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                // csh has no escapes inside double quotes so
+                // step outside them, except for ! which is
+                // the one thing \ works on in there
+                (Shell::Csh, '"' | '$' | '`') => {
+                    escaped.push_str("\"\\");
+                    escaped.push(c);
+                    escaped.push('"');
+                }
+                (Shell::Csh, '!') => escaped.push_str("\\!"),
+                (Shell::Pwsh, '`' | '"' | '$') => {
+                    escaped.push('`');
+                    escaped.push(c);
+                }
+                (Shell::Xonsh, '{' | '}') if format => {
+                    escaped.push(c);
+                    escaped.push(c);
+                }
+                // These don't expand anything inside quotes
+                (Shell::Nu | Shell::Elvish | Shell::Xonsh, '\\' | '"') => {
+                    escaped.push('\\');
+                    escaped.push(c);
+                }
+                _ => escaped.push(c),
+            }
+            rest = &rest[c.len_utf8()..];
+        }
+        escaped
+    }
+
+    /// Like [`escape`](Shell::escape) for a value that isn't
+    /// UTF-8. The bytes that aren't are written as escapes
+    /// outside the quotes, e.g. `"a"$'\xff'"b"` for bash.
+    /// None if this shell has no way to write them.
+    #[cfg(unix)]
+    pub(crate) fn escape_bytes(self, value: &OsStr, quoting: Quoting) -> Option<String> {
+        use std::os::unix::ffi::OsStrExt;
+
+        let byte: fn(u8) -> String = match self {
+            Shell::Bash | Shell::Zsh | Shell::Ksh => |b| format!("\"$'\\x{:02x}'\"", b),
+            Shell::Fish => |b| format!("\"\\X{:02X}\"", b),
+            _ => return None,
+        };
+        let mut escaped = String::new();
+        for chunk in value.as_bytes().utf8_chunks() {
+            escaped.push_str(&self.escape(chunk.valid(), quoting != Quoting::Single));
+            for &b in chunk.invalid() {
+                escaped.push_str(&byte(b));
+            }
+        }
+        Some(escaped)
+    }
+
+    /// Values that aren't UTF-8 can only
+    /// happen on unix.
+    #[cfg(not(unix))]
+    pub(crate) fn escape_bytes(self, _value: &OsStr, _quoting: Quoting) -> Option<String> {
+        None
+    }
+
+    // How to write a newline or carriage return inside
+    // double quotes so the line stays one line,
+    // None if `c` is neither or the shell can't
+    fn control(self, c: char) -> Option<String> {
+        let letter = match c {
+            '\n' => 'n',
+            '\r' => 'r',
+            _ => return None,
+        };
+        match self {
+            Shell::Bash | Shell::Zsh | Shell::Ksh => Some(format!("\"$'\\{}'\"", letter)),
+            Shell::Fish => Some(format!("\"\\{}\"", letter)),
+            Shell::Pwsh => Some(format!("`{}", letter)),
+            Shell::Nu | Shell::Elvish | Shell::Xonsh => Some(format!("\\{}", letter)),
+            // Neither has a way short of a literal newline
+            // inside the quotes
+            Shell::Sh | Shell::Csh => None,
+        }
+    }
+
+    /// Whether values with newlines can be written on
+    /// one line, which everything but sh and csh can.
+    pub(crate) fn escapes_newlines(self) -> bool {
+        !matches!(self, Shell::Sh | Shell::Csh)
+    }
+
+    // `value` with only newlines and
+    // carriage returns escaped
+    fn escape_controls(self, value: &str) -> String {
+        value
+            .chars()
+            .map(|c| self.control(c).unwrap_or_else(|| c.to_string()))
+            .collect()
+    }
+
+    /// Undoes [`escape`](Shell::escape) on the text of a value
+    /// with its outer quotes already gone.
+    fn unescape(self, value: &str) -> String {
+        match self {
+            Shell::Csh => value
+                .replace("\"\\\"\"", "\"")
+                .replace("\"\\$\"", "$")
+                .replace("\"\\`\"", "`")
+                .replace("\\!", "!"),
+            Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh => {
+                let value = value.replace("\"$'\\n'\"", "\n").replace("\"$'\\r'\"", "\r");
+                strip_escapes(&value, '\\', &['\\', '"', '`', '$'], false)
+            }
+            Shell::Fish => {
+                let value = value.replace("\"\\n\"", "\n").replace("\"\\r\"", "\r");
+                strip_escapes(&value, '\\', &['\\', '"', '$'], false)
+            }
+            Shell::Pwsh => strip_escapes(value, '`', &['`', '"', '$'], true),
+            Shell::Nu => strip_escapes(value, '\\', &['\\', '"', '('], true),
+            Shell::Elvish | Shell::Xonsh => strip_escapes(value, '\\', &['\\', '"'], true),
+        }
+    }
+
+    // What goes in front of the double quotes around
+    // `value`: `f` for xonsh if it has references,
+    // since only f-strings expand them
+    fn string_prefix(self, value: &str, quoting: Quoting) -> &'static str {
+        match (self, quoting) {
+            (Shell::Xonsh, Quoting::ShellEscape | Quoting::Prequoted) if has_reference(value) => "f",
+            _ => "",
+        }
+    }
+
+    // `value` as a word of its own
+    fn word(self, value: &str, quoting: Quoting) -> String {
+        // sh, fish and csh are happy with plain words,
+        // the rest want strings quoted
+        let bare = matches!(self, Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh | Shell::Fish | Shell::Csh);
+        match quoting {
+            Quoting::None => value.to_string(),
+            Quoting::Double => format!("\"{}\"", self.escape_controls(value)),
+            Quoting::Single => self.single_quote(value),
+            Quoting::Prequoted if is_quoted(value) => value.to_string(),
+            Quoting::ShellEscape | Quoting::Prequoted if bare && is_plain(value) => value.to_string(),
+            Quoting::ShellEscape | Quoting::Prequoted => {
+                format!("{}\"{}\"", self.string_prefix(value, quoting), self.escape(value, true))
+            }
+        }
+    }
+
+    // `value` as it goes inside the double quotes
+    // of an append or prepend line
+    fn inner(self, value: &str, quoting: Quoting) -> String {
+        let escaped = match quoting {
+            Quoting::None => return value.to_string(),
+            Quoting::Double => return self.escape_controls(value),
+            // Nothing expands if everything's escaped
+            Quoting::Single => self.escape(value, false),
+            Quoting::ShellEscape | Quoting::Prequoted => self.escape(value, true),
+        };
+        match self {
+            // nu's $"" strings run whatever's in ()
+            Shell::Nu => escaped.replace('(', "\\("),
+            _ => escaped,
+        }
+    }
+
+    // `value` in whatever quotes keep
+    // everything in them literal
+    fn single_quote(self, value: &str) -> String {
+        let controls = value.contains(['\n', '\r']);
+        match self {
+            // Step out of the quotes for newlines
+            Shell::Bash | Shell::Zsh | Shell::Ksh => format!(
+                "'{}'",
+                value.replace('\'', "'\\''").replace('\n', "'$'\\n''").replace('\r', "'$'\\r''")
+            ),
+            Shell::Fish => format!(
+                "'{}'",
+                value
+                    .replace('\\', "\\\\")
+                    .replace('\'', "\\'")
+                    .replace('\n', "'\\n'")
+                    .replace('\r', "'\\r'")
+            ),
+            Shell::Xonsh => format!(
+                "'{}'",
+                value
+                    .replace('\\', "\\\\")
+                    .replace('\'', "\\'")
+                    .replace('\n', "\\n")
+                    .replace('\r', "\\r")
+            ),
+            Shell::Sh => format!("'{}'", value.replace('\'', "'\\''")),
+            Shell::Csh => format!("'{}'", value.replace('\'', "'\\''").replace('!', "\\!")),
+            // Nothing can be escaped in their single quotes but
+            // everything can be in double quotes without expanding
+            Shell::Pwsh | Shell::Elvish if controls => format!("\"{}\"", self.escape(value, false)),
+            Shell::Pwsh | Shell::Elvish => format!("'{}'", value.replace('\'', "''")),
+            // nu's single quotes can't hold a ' at all but
+            // its double quotes don't expand anything either
+            Shell::Nu if value.contains('\'') || controls => format!("\"{}\"", self.escape(value, false)),
+            Shell::Nu => format!("'{}'", value),
+        }
+    }
+
+    // Undoes `single_quote` on what
+    // was inside the quotes
+    fn single_unquote(self, inner: &str) -> String {
+        match self {
+            Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh => inner
+                .replace("'$'\\n''", "\n")
+                .replace("'$'\\r''", "\r")
+                .replace("'\\''", "'"),
+            Shell::Csh => inner.replace("'\\''", "'").replace("\\!", "!"),
+            Shell::Fish => {
+                let inner = inner.replace("'\\n'", "\n").replace("'\\r'", "\r");
+                strip_escapes(&inner, '\\', &['\\', '\''], false)
+            }
+            Shell::Xonsh => strip_escapes(inner, '\\', &['\\', '\''], true),
+            Shell::Pwsh | Shell::Elvish => inner.replace("''", "'"),
+            Shell::Nu => inner.to_string(),
+        }
+    }
+
+    /// Rewrites a line from `set_line`, `append_line` or `prepend_line`
+    /// so that even the oldest Bourne shells can read it,
+    /// e.g. `export FOO=bar` becomes `FOO=bar; export FOO`.
+    /// Lines for other shells are left alone.
+    pub(crate) fn strict_posix(self, line: String) -> String {
+        match self {
+            Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh => {
+                match line.strip_prefix("export ").and_then(|rest| rest.split_once('=')) {
+                    Some((var, value)) => format!("{}={}; export {}", var, value, var),
+                    None => line,
+                }
+            }
+            _ => line,
+        }
+    }
+
+    /// Splits a line that assigns to a variable, in any of
+    /// the forms we write or a user might, into the name and
+    /// the text of the value with outer quotes removed.
+    /// Appends come back as the whole expression,
+    /// e.g. `"/bin:$PATH"` is `/bin:$PATH`.
+    pub(crate) fn parse_line(self, line: &str) -> Option<(String, String)> {
+        let line = provenance::split(line.trim()).0;
+        let (var, value) = match self {
+            Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh => match line.strip_prefix("export ") {
+                Some(rest) => rest.trim_start().split_once('=')?,
+                // FOO=bar; export FOO
+                None => {
+                    let (var, rest) = line.split_once('=')?;
+                    (var, rest.strip_suffix(&format!("; export {}", var))?)
+                }
+            },
+            Shell::Fish => {
+                let mut rest = line.strip_prefix("set ")?;
+                // Skip flags like -gx or --export
+                loop {
+                    rest = rest.trim_start();
+                    match split_word(rest) {
+                        (flag, after) if flag.starts_with('-') => rest = after,
+                        _ => break,
+                    }
+                }
+                split_word(rest)
+            }
+            Shell::Nu => {
+                let (var, value) = line.strip_prefix("$env.")?.split_once('=')?;
+                (var.trim_end(), value)
+            }
+            Shell::Csh => split_word(line.strip_prefix("setenv ")?.trim_start()),
+            Shell::Pwsh => {
+                if let Some(call) = line.strip_prefix("[Environment]::SetEnvironmentVariable(") {
+                    let call = call.trim_start().strip_prefix('\'')?;
+                    let (var, rest) = call.split_once('\'')?;
+                    let value = rest.trim_start().strip_prefix(',')?;
+                    let value = value.rsplit_once(',')?.0;
+                    return Some((var.replace("''", "'"), self.value_text(value)));
+                }
+                let (var, value) = line.split_once('=')?;
+                let var = var.trim_end();
+                let var = var
+                    .strip_prefix("$env:")
+                    .or_else(|| var.strip_prefix("${env:")?.strip_suffix('}'))?;
+                (var, value)
+            }
+            Shell::Elvish => {
+                if let Some(paths) = line.strip_prefix("set paths ") {
+                    ("PATH", paths.trim_start().strip_prefix('=')?)
+                } else {
+                    split_word(line.strip_prefix("set-env ")?.trim_start())
+                }
+            }
+            Shell::Xonsh => {
+                let name = line.strip_prefix('$')?;
+                let end = name
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(name.len());
+                let (var, rest) = name.split_at(end);
+                let value = match rest.trim_start().strip_prefix('=') {
+                    Some(value) => value,
+                    // $PATH.insert(0, ...) and friends
+                    None if rest.starts_with('.') => line,
+                    None => return None,
+                };
+                (var, value)
+            }
+        };
+        if !is_identifier(var) && self != Shell::Pwsh {
+            return None;
+        }
+        Some((var.to_string(), self.value_text(value)))
+    }
+
+    // The text of a value with outer quotes
+    // and any escaping taken off. The list and string
+    // building forms of fish, nu, elvish and xonsh are
+    // left as they are and their parts get unescaped
+    // once they're split out.
+    fn value_text(self, value: &str) -> String {
+        let value = value.trim();
+        if let Some(inner) = value.strip_prefix('\'').and_then(|inner| inner.strip_suffix('\'')) {
+            return self.single_unquote(inner);
+        }
+        match self {
+            Shell::Xonsh if value.starts_with("f\"") => match xonsh_string(value) {
+                Some(inner) => self.unescape(&inner),
+                None => value.to_string(),
+            },
+            Shell::Fish | Shell::Nu | Shell::Elvish | Shell::Xonsh if !is_quoted(value) => value.to_string(),
+            _ => self.unescape(unquote(value)),
+        }
+    }
+
+    /// If `value` from [`parse_line`](Shell::parse_line) adds to
+    /// the end of `var` this is what was added,
+    /// e.g. `$PATH:/bin` gives `/bin`.
+    pub(crate) fn appended(self, var: &str, value: &str) -> Option<String> {
+        self.appended_with(var, value).map(|(added, _)| added)
+    }
+
+    /// Like `appended` but also gives the separator.
+    /// Any of [`SEPARATORS`] is accepted and the shells'
+    /// own list forms count as `:`.
+    pub(crate) fn appended_with(self, var: &str, value: &str) -> Option<(String, char)> {
+        let list = |added: &str| Some((self.value_text(added), ':'));
+        let text = |(added, separator): (String, char)| (self.unescape(&added), separator);
+        match self {
+            Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh | Shell::Csh => {
+                let rest = value
+                    .strip_prefix(&format!("${{{}}}", var))
+                    .or_else(|| value.strip_prefix(&format!("${}", var)))?;
+                after_separator(rest)
+            }
+            // fish lists are separated with a space
+            Shell::Fish => after_separator(value.strip_prefix(&format!("${}", var))?)
+                .map(|(added, separator)| match separator {
+                    ' ' => (self.value_text(&added), ':'),
+                    _ => (added, separator),
+                }),
+            Shell::Nu => match value.strip_prefix(&format!("($env.{} | append ", var)) {
+                Some(rest) => list(rest.strip_suffix(')')?),
+                None => after_separator(value.strip_prefix(&format!("$\"($env.{})", var))?.strip_suffix('"')?).map(text),
+            },
+            Shell::Pwsh => {
+                let rest = value
+                    .strip_prefix(&format!("${{env:{}}}", var))
+                    .or_else(|| value.strip_prefix(&format!("$env:{}", var)))?;
+                after_separator(rest)
+            }
+            Shell::Elvish if value.starts_with("[$@paths") => {
+                list(value.strip_prefix("[$@paths")?.strip_suffix(']')?)
+            }
+            Shell::Elvish => {
+                after_separator(value.strip_prefix(&format!("(get-env {})\"", var))?.strip_suffix('"')?).map(text)
+            }
+            Shell::Xonsh => match value.strip_prefix(&format!("${}.append(", var)) {
+                Some(rest) => list(rest.strip_suffix(')')?),
+                None => after_separator(&xonsh_string(value.strip_prefix(&format!("${} + ", var))?)?).map(text),
+            },
+        }
+    }
+
+    /// If `value` from [`parse_line`](Shell::parse_line) adds to
+    /// the front of `var` this is what was added,
+    /// e.g. `/bin:$PATH` gives `/bin`.
+    pub(crate) fn prepended(self, var: &str, value: &str) -> Option<String> {
+        self.prepended_with(var, value).map(|(added, _)| added)
+    }
+
+    /// Like `prepended` but also gives the separator.
+    pub(crate) fn prepended_with(self, var: &str, value: &str) -> Option<(String, char)> {
+        let list = |added: &str| Some((self.value_text(added), ':'));
+        let text = |(added, separator): (String, char)| (self.unescape(&added), separator);
+        match self {
+            Shell::Bash | Shell::Zsh | Shell::Ksh | Shell::Sh | Shell::Csh => {
+                let rest = value
+                    .strip_suffix(&format!("${{{}}}", var))
+                    .or_else(|| value.strip_suffix(&format!("${}", var)))?;
+                before_separator(rest)
+            }
+            Shell::Fish => before_separator(value.strip_suffix(&format!("${}", var))?)
+                .map(|(added, separator)| match separator {
+                    ' ' => (self.value_text(&added), ':'),
+                    _ => (added, separator),
+                }),
+            Shell::Nu => match value.strip_prefix(&format!("($env.{} | prepend ", var)) {
+                Some(rest) => list(rest.strip_suffix(')')?),
+                None => before_separator(value.strip_prefix("$\"")?.strip_suffix(&format!("($env.{})\"", var))?).map(text),
+            },
+            Shell::Pwsh => {
+                let rest = value
+                    .strip_suffix(&format!("${{env:{}}}", var))
+                    .or_else(|| value.strip_suffix(&format!("$env:{}", var)))?;
+                before_separator(rest)
+            }
+            Shell::Elvish if value.ends_with("$@paths]") => {
+                list(value.strip_prefix('[')?.strip_suffix("$@paths]")?)
+            }
+            Shell::Elvish => {
+                before_separator(value.strip_prefix('"')?.strip_suffix(&format!("\"(get-env {})", var))?).map(text)
+            }
+            Shell::Xonsh => match value.strip_prefix(&format!("${}.insert(0,", var)) {
+                Some(rest) => list(rest.strip_suffix(')')?),
+                None => before_separator(&xonsh_string(value.strip_suffix(&format!(" + ${}", var))?)?).map(text),
+            },
+        }
+    }
+}
+
+// What's inside a xonsh string, `"..."` or `f"..."`,
+// still escaped but with the f-string's references
+// back as `$HOME`
+fn xonsh_string(value: &str) -> Option<String> {
+    match value.strip_prefix('f') {
+        Some(rest) => Some(unformat(rest.strip_prefix('"')?.strip_suffix('"')?)),
+        None => Some(value.strip_prefix('"')?.strip_suffix('"')?.to_string()),
+    }
+}
+
+// Undoes the f-string part of xonsh's escaping: `{{`
+// and `}}` are braces and `{$HOME}` is `$HOME`, or
+// `${HOME}` if what follows would run into the name
+fn unformat(inner: &str) -> String {
+    let mut text = String::with_capacity(inner.len());
+    let mut rest = inner;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("{{") {
+            text.push('{');
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("}}") {
+            text.push('}');
+            rest = after;
+            continue;
+        }
+        if let Some((name, after)) = rest.strip_prefix("{$").and_then(|reference| reference.split_once('}')) {
+            if is_identifier(name) {
+                if after.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+                    text.push_str(&format!("${{{}}}", name));
+                } else {
+                    text.push_str(&format!("${}", name));
+                }
+                rest = after;
+                continue;
+            }
+        }
+        text.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    text
+}
+
+// Whether any of `value` is a reference like `$HOME`
+fn has_reference(value: &str) -> bool {
+    value.char_indices().any(|(i, _)| reference_len(&value[i..]).is_some())
+}
+
+// What follows the separator at the start of
+// `rest` and the separator
+fn after_separator(rest: &str) -> Option<(String, char)> {
+    let separator = rest.chars().next().filter(|c| is_separator(*c))?;
+    Some((rest[separator.len_utf8()..].to_string(), separator))
+}
+
+// What comes before the separator at the
+// end of `rest` and the separator
+fn before_separator(rest: &str) -> Option<(String, char)> {
+    let separator = rest.chars().next_back().filter(|c| is_separator(*c))?;
+    Some((rest[..rest.len() - separator.len_utf8()].to_string(), separator))
+}
+
+// The separators we recognise when reading lines back.
+// `$HOME/bin` is a path, not a list.
+fn is_separator(c: char) -> bool {
+    SEPARATORS.contains(&c)
+}
+
+pub(crate) const SEPARATORS: [char; 5] = [':', ';', ',', ' ', '|'];
+
+/// What lists like PATH are separated with.
+#[cfg(windows)]
+pub(crate) const PATH_SEPARATOR: char = ';';
+/// What lists like PATH are separated with.
+#[cfg(not(windows))]
+pub(crate) const PATH_SEPARATOR: char = ':';
+
+// `$env:FOO = ...` only works for names that are plain identifiers,
+// anything else like `ProgramFiles(x86)` has to go through .NET
+fn powershell_assignment(var: impl fmt::Display, value: String) -> String {
+    let var = var.to_string();
+    if is_identifier(&var) {
+        format!("$env:{} = {}", var, value)
+    } else {
+        format!(
+            "[Environment]::SetEnvironmentVariable('{}', {}, 'Process')",
+            var.replace('\'', "''"),
+            value
+        )
+    }
+}
+
+// How long the reference to a variable at the start
+// of `rest` is, `$HOME` or `${HOME}`, if it is one
+fn reference_len(rest: &str) -> Option<usize> {
+    let name = |s: &str| {
+        s.char_indices()
+            .take_while(|&(i, c)| c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()))
+            .count()
+    };
+    let after = rest.strip_prefix('$')?;
+    if let Some(braced) = after.strip_prefix('{') {
+        let len = name(braced);
+        return Some(len + 3).filter(|_| len > 0 && braced[len..].starts_with('}'));
+    }
+    Some(name(after) + 1).filter(|&len| len > 1)
+}
+
+// `value` with `escape` taken off the `special`
+// characters after it. With `controls` it followed
+// by n or r is a newline or carriage return.
+fn strip_escapes(value: &str, escape: char, special: &[char], controls: bool) -> String {
+    let mut stripped = String::with_capacity(value.len());
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(next) if c == escape && special.contains(next) => {
+                stripped.push(*next);
+                chars.next();
+            }
+            Some('n') if c == escape && controls => {
+                stripped.push('\n');
+                chars.next();
+            }
+            Some('r') if c == escape && controls => {
+                stripped.push('\r');
+                chars.next();
+            }
+            _ => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+// Whether `value` is wrapped in quotes, which with
+// Quoting::Prequoted means the caller quoted it
+fn is_quoted(value: &str) -> bool {
+    value.len() >= 2
+        && ['"', '\''].iter().any(|&quote| value.starts_with(quote) && value.ends_with(quote))
+}
+
+// Whether `value` is safe to write without quotes
+// in sh, fish and csh, so `1` stays `export DUMMY=1`
+fn is_plain(value: &str) -> bool {
+    let mut rest = value;
+    while let Some(c) = rest.chars().next() {
+        let len = match reference_len(rest) {
+            Some(len) => len,
+            None if c.is_alphanumeric() || "_-./:,+=@%~".contains(c) => c.len_utf8(),
+            None => return false,
+        };
+        rest = &rest[len..];
+    }
+    !value.is_empty()
+}
+
+// Strips one pair of matching outer quotes
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    for quote in &['"', '\''] {
+        if let Some(inner) = value.strip_prefix(*quote).and_then(|v| v.strip_suffix(*quote)) {
+            return inner;
+        }
+    }
+    value
+}
+
+// Splits off the first word of `line`
+fn split_word(line: &str) -> (&str, &str) {
+    match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], &line[i..]),
+        None => (line, ""),
+    }
+}
+
+fn is_identifier(var: &str) -> bool {
+    !var.is_empty() && var.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// The first of `profiles` that exists
+// or the first one if none do.
+fn pick(mut profiles: Vec<PathBuf>) -> PathBuf {
+    match profiles.iter().position(|profile| profile.is_file()) {
+        Some(i) => profiles.swap_remove(i),
+        None => profiles.swap_remove(0),
+    }
+}
+
+// zsh looks for its startup files in $ZDOTDIR
+// if it's set, otherwise in $HOME
+fn zdotdir(home: &Path) -> PathBuf {
+    env::var_os("ZDOTDIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.to_path_buf())
+}
+
+// The rc file named by $ENV if it's usable
+fn env_file() -> Option<PathBuf> {
+    env::var_os("ENV")
+        .map(PathBuf::from)
+        .filter(|rc| rc.is_absolute())
+}
+
+pub(crate) fn home_dir() -> io::Result<PathBuf> {
+    #[cfg(windows)]
+    {
+        if let Some(home) = crate::windows::msys_home() {
+            return Ok(home);
+        }
+    }
+    dirs::home_dir().ok_or_else(|| io::Error::other("No home directory"))
+}
+
+// macOS keeps users in the directory service so
+// /etc/passwd only has the system accounts
+#[cfg(target_os = "macos")]
+fn dscl_shell() -> Option<PathBuf> {
+    let user = env::var("USER").or_else(|_| env::var("LOGNAME")).ok()?;
+    let output = std::process::Command::new("dscl")
+        .args([".", "-read", &format!("/Users/{}", user), "UserShell"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Looks like "UserShell: /bin/zsh"
+    String::from_utf8(output.stdout)
+        .ok()?
+        .trim()
+        .strip_prefix("UserShell:")
+        .map(|shell| PathBuf::from(shell.trim()))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn dscl_shell() -> Option<PathBuf> {
+    None
+}
+
+// Termux on android has no /etc/passwd and
+// chsh there points ~/.termux/shell at the shell
+fn termux_shell() -> Option<PathBuf> {
+    if !is_termux() {
+        return None;
+    }
+    fs::read_link(dirs::home_dir()?.join(".termux").join("shell")).ok()
+}
+
+fn is_termux() -> bool {
+    env::var_os("TERMUX_VERSION").is_some()
+        || env::var("PREFIX").is_ok_and(|prefix| prefix.starts_with("/data/data/com.termux"))
+}
+
+// The shell field of our entry in /etc/passwd.
+// We don't know our uid without libc so match on
+// the user name or failing that the home directory.
+fn login_shell() -> Option<PathBuf> {
+    let passwd = fs::read_to_string("/etc/passwd").ok()?;
+    let user = env::var("USER").or_else(|_| env::var("LOGNAME")).ok();
+    let home = dirs::home_dir();
+    passwd
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields.len() == 7)
+        .find(|fields| match &user {
+            Some(user) => fields[0] == user,
+            None => home.as_ref().is_some_and(|home| Path::new(fields[5]) == home),
+        })
+        .map(|fields| PathBuf::from(fields[6]))
+}
+
+fn config_home(home: &Path) -> PathBuf {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home.join(".config"))
+}
+
+// nu uses the platform config dir
+// unless XDG_CONFIG_HOME is set
+fn nu_config_dir(home: &Path) -> PathBuf {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(dirs::config_dir)
+        .unwrap_or_else(|| home.join(".config"))
+        .join("nushell")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What reading back the line `set_line` writes gives
+    fn round_trip(shell: Shell, value: &str, quoting: Quoting) -> String {
+        let line = shell.set_line("VAR", value, quoting);
+        let (var, read) = shell.parse_line(&line).unwrap_or_else(|| panic!("{:?} can't read {:?}", shell, line));
+        assert_eq!(var, "VAR");
+        read
+    }
+
+    const AWKWARD: [&str; 14] = [
+        "two words",
+        "  padded  ",
+        "cost $5",
+        "ends in $",
+        "$ alone",
+        "wow!",
+        "!!",
+        "it's",
+        "'a' and 'b'",
+        "\"a\" \"b\"",
+        "say \"hi\"",
+        "back\\slash\\",
+        "`tick`",
+        "tab\there",
+    ];
+
+    #[test]
+    fn shell_escape_round_trips() {
+        for shell in ALL {
+            for value in AWKWARD {
+                assert_eq!(round_trip(shell, value, Quoting::ShellEscape), value, "{:?}", shell);
+            }
+        }
+    }
+
+    #[test]
+    fn single_round_trips() {
+        for shell in ALL {
+            for value in AWKWARD.iter().copied().chain(["$HOME/bin", "${HOME}"]) {
+                assert_eq!(round_trip(shell, value, Quoting::Single), value, "{:?}", shell);
+            }
+        }
+    }
+
+    #[test]
+    fn control_characters_round_trip() {
+        for shell in ALL.iter().copied().filter(|shell| shell.escapes_newlines()) {
+            for value in ["two\nlines", "crlf\r\n", "\n"] {
+                for quoting in [Quoting::ShellEscape, Quoting::Single] {
+                    let line = shell.set_line("VAR", value, quoting);
+                    assert!(!line.contains(['\n', '\r']), "{:?} wrote {:?}", shell, line);
+                    assert_eq!(round_trip(shell, value, quoting), value, "{:?}", shell);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn references_still_expand() {
+        assert_eq!(Shell::Bash.set_line("VAR", "$HOME/bin", Quoting::ShellEscape), "export VAR=$HOME/bin");
+        assert_eq!(Shell::Bash.set_line("VAR", "${HOME} x", Quoting::ShellEscape), "export VAR=\"${HOME} x\"");
+        assert_eq!(Shell::Fish.set_line("VAR", "$HOME/my bin", Quoting::ShellEscape), "set -gx VAR \"$HOME/my bin\"");
+    }
+
+    #[test]
+    fn wrapping_quotes_are_escaped() {
+        assert_eq!(Shell::Bash.set_line("X", "'a' and 'b'", Quoting::ShellEscape), "export X=\"'a' and 'b'\"");
+        assert_eq!(Shell::Bash.set_line("X", "\"a\" \"b\"", Quoting::ShellEscape), "export X=\"\\\"a\\\" \\\"b\\\"\"");
+        assert_eq!(Shell::Pwsh.set_line("X", "\"a\"", Quoting::ShellEscape), "$env:X = \"`\"a`\"\"");
+    }
+
+    #[test]
+    fn prequoted_values_are_left_alone() {
+        assert_eq!(Shell::Bash.set_line("X", "\"/a b\"", Quoting::Prequoted), "export X=\"/a b\"");
+        assert_eq!(Shell::Bash.set_line("X", "'$5'", Quoting::Prequoted), "export X='$5'");
+        // Anything else is escaped like ShellEscape
+        assert_eq!(Shell::Bash.set_line("X", "say \"hi\"", Quoting::Prequoted), "export X=\"say \\\"hi\\\"\"");
+        assert_eq!(round_trip(Shell::Zsh, "'/a b'", Quoting::Prequoted), "/a b");
+    }
+
+    #[test]
+    fn xonsh_references_are_f_strings() {
+        let xonsh = Shell::Xonsh;
+        assert_eq!(xonsh.set_line("VAR", "$HOME/bin", Quoting::ShellEscape), "$VAR = f\"{$HOME}/bin\"");
+        assert_eq!(xonsh.set_line("VAR", "${HOME}/{x}", Quoting::ShellEscape), "$VAR = f\"{$HOME}/{{x}}\"");
+        assert_eq!(xonsh.append_line("PATH", "$HOME/bin", Quoting::ShellEscape), "$PATH.append(f\"{$HOME}/bin\")");
+        assert_eq!(xonsh.prepend_line("PATH", "$HOME/bin", Quoting::ShellEscape), "$PATH.insert(0, f\"{$HOME}/bin\")");
+        assert_eq!(xonsh.append_line_with("LIST", "$HOME/a", ',', Quoting::ShellEscape), "$LIST = $LIST + f\",{$HOME}/a\"");
+        assert_eq!(xonsh.prepend_line_with("LIST", "$HOME/a", ',', Quoting::ShellEscape), "$LIST = f\"{$HOME}/a,\" + $LIST");
+        assert_eq!(xonsh.source_line("$HOME/.cargo/env"), "source f\"{$HOME}/.cargo/env\"");
+    }
+
+    #[test]
+    fn xonsh_plain_strings_without_references() {
+        let xonsh = Shell::Xonsh;
+        assert_eq!(xonsh.set_line("VAR", "{a} cost $5", Quoting::ShellEscape), "$VAR = \"{a} cost $5\"");
+        assert_eq!(xonsh.set_line("VAR", "$HOME", Quoting::Single), "$VAR = '$HOME'");
+        assert_eq!(xonsh.append_line_with("LIST", "$HOME", ',', Quoting::Single), "$LIST = $LIST + \",$HOME\"");
+    }
+
+    #[test]
+    fn xonsh_f_strings_read_back() {
+        let xonsh = Shell::Xonsh;
+        for (value, read) in [
+            ("$HOME/bin", "$HOME/bin"),
+            ("${HOME}/bin", "$HOME/bin"),
+            ("${HOME}bin", "${HOME}bin"),
+            ("{braces} $HOME \"q\" \\", "{braces} $HOME \"q\" \\"),
+            ("${A}_${B}", "${A}_$B"),
+        ] {
+            assert_eq!(round_trip(xonsh, value, Quoting::ShellEscape), read);
+            let (_, line) = xonsh.parse_line(&xonsh.append_line_with("LIST", value, ',', Quoting::ShellEscape)).unwrap();
+            assert_eq!(xonsh.appended_with("LIST", &line), Some((read.to_string(), ',')));
+            let (_, line) = xonsh.parse_line(&xonsh.prepend_line_with("LIST", value, ',', Quoting::ShellEscape)).unwrap();
+            assert_eq!(xonsh.prepended_with("LIST", &line), Some((read.to_string(), ',')));
+            let (_, line) = xonsh.parse_line(&xonsh.append_line("PATH", value, Quoting::ShellEscape)).unwrap();
+            assert_eq!(xonsh.appended("PATH", &line).as_deref(), Some(read));
+        }
+    }
+}
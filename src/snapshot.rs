@@ -0,0 +1,139 @@
+//! Taking a copy of everything variables are persisted
+//! in so it can all be put back later.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::journal::{escape, optional, parse_optional, unescape};
+use crate::lock;
+use crate::profile::{self, Writer};
+use crate::windows::{self, Scope};
+use crate::Place;
+
+/// A copy of every profile and, on windows, every value in
+/// the registry environment, from [`snapshot`](crate::snapshot),
+/// to put back with [`restore`](crate::restore) if a
+/// setup script goes wrong.
+/// ```no_run
+/// let snapshot = env_perm::snapshot("env.snapshot").unwrap();
+/// if env_perm::append("PATH", "/opt/myapp/bin").is_err() {
+///     env_perm::restore(&snapshot).expect("Failed to restore");
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Snapshot {
+    /// When it was taken.
+    pub time: SystemTime,
+    files: Vec<(PathBuf, Option<String>)>,
+    registry: Option<Vec<(String, String)>>,
+}
+
+impl Snapshot {
+    /// Takes one of `files`, and of the registry
+    /// environment if `registry` is set.
+    pub(crate) fn take(files: Vec<PathBuf>, registry: bool) -> io::Result<Snapshot> {
+        let mut taken = Vec::with_capacity(files.len());
+        for path in files {
+            let contents = profile::contents(&path)?;
+            taken.push((path, contents));
+        }
+        Ok(Snapshot {
+            time: SystemTime::now(),
+            files: taken,
+            registry: if registry { Some(windows::reg_list(Scope::User)?) } else { None },
+        })
+    }
+
+    /// Reads a snapshot [`save`](Snapshot::save)d to `path`.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Snapshot> {
+        let contents = fs::read_to_string(path)?;
+        let mut lines = contents.lines().enumerate();
+        let broken = |i: usize| io::Error::new(io::ErrorKind::InvalidData, format!("Line {} of the snapshot is broken", i + 1));
+        let header: Vec<&str> = lines.next().map_or_else(Vec::new, |(_, line)| line.split('\t').collect());
+        let (millis, registry) = match header[..] {
+            ["env_perm snapshot", millis] => (millis, false),
+            ["env_perm snapshot", millis, "registry"] => (millis, true),
+            _ => return Err(broken(0)),
+        };
+        let mut snapshot = Snapshot {
+            time: UNIX_EPOCH + Duration::from_millis(millis.parse().map_err(|_| broken(0))?),
+            files: Vec::new(),
+            registry: if registry { Some(Vec::new()) } else { None },
+        };
+        for (i, line) in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match (&fields[..], snapshot.registry.as_mut()) {
+                (["file", path, contents], _) => {
+                    let contents = parse_optional(contents).ok_or_else(|| broken(i))?;
+                    snapshot.files.push((PathBuf::from(unescape(path)), contents));
+                }
+                (["registry", var, value], Some(registry)) => registry.push((unescape(var), unescape(value))),
+                _ => return Err(broken(i)),
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Writes it to `path`, one line for each file and
+    /// registry value, to [`read`](Snapshot::read) later.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let millis = self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let mut lines = vec![match self.registry {
+            Some(_) => format!("env_perm snapshot\t{}\tregistry", millis),
+            None => format!("env_perm snapshot\t{}", millis),
+        }];
+        for (path, contents) in &self.files {
+            lines.push(format!("file\t{}\t{}", escape(&path.to_string_lossy()), optional(contents.as_deref())));
+        }
+        for (var, value) in self.registry.iter().flatten() {
+            lines.push(format!("registry\t{}\t{}", escape(var), escape(value)));
+        }
+        fs::write(path, lines.join("\n") + "\n")
+    }
+
+    /// The files it has a copy of.
+    pub fn files(&self) -> Vec<&Path> {
+        self.files.iter().map(|(path, _)| path.as_path()).collect()
+    }
+
+    /// Puts every file back the way it was, deleting the ones
+    /// that didn't exist, and the registry environment too if
+    /// it was taken, deleting values that weren't there.
+    /// Gives the places that changed.
+    pub(crate) fn restore(&self, writer: Writer) -> io::Result<Vec<Place>> {
+        lock::locked(|| {
+            let mut changed = Vec::new();
+            for (path, contents) in &self.files {
+                if profile::contents(path)? == *contents {
+                    continue;
+                }
+                match contents {
+                    Some(contents) => profile::write_lines(path, &profile::lines_of(Some(contents.clone())), writer)?,
+                    None => profile::remove_file(path, writer)?,
+                }
+                changed.push(Place::File(path.clone()));
+            }
+            if let Some(registry) = &self.registry {
+                let mut vars: Vec<(&str, Option<&str>)> =
+                    registry.iter().map(|(var, value)| (var.as_str(), Some(value.as_str()))).collect();
+                let current = windows::reg_list(Scope::User)?;
+                for (var, _) in &current {
+                    if !registry.iter().any(|(name, _)| name.eq_ignore_ascii_case(var)) {
+                        vars.push((var, None));
+                    }
+                }
+                for (var, value) in vars {
+                    if windows::reg_get(Scope::User, var)?.as_deref() == value {
+                        continue;
+                    }
+                    windows::put(Scope::User, var, writer.app, value)?;
+                    changed.push(Place::Registry { key: windows::USER_ENVIRONMENT.to_string(), var: var.to_string() });
+                }
+            }
+            Ok(changed)
+        })
+    }
+}
@@ -0,0 +1,193 @@
+//! Describing the variables something needs
+//! and checking they're persisted.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::manifest;
+use crate::shell::PATH_SEPARATOR;
+use crate::{Config, Shell, Target, ToEnvValue};
+
+/// The variables and list entries something needs persisted,
+/// to check with [`verify`](crate::verify) or write with
+/// [`apply_spec`](crate::apply_spec).
+/// ```no_run
+/// use env_perm::Spec;
+///
+/// let spec = Spec::new()
+///     .var("MYAPP_HOME", "/opt/myapp")
+///     .append("PATH", "/opt/myapp/bin");
+/// let verification = env_perm::verify(&spec).unwrap();
+/// for (var, value) in &verification.missing {
+///     println!("{} is missing {}", var, value);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spec {
+    pub(crate) wanted: Vec<Wanted>,
+    pub(crate) shells: Vec<Shell>,
+    pub(crate) target: Option<Target>,
+    pub(crate) app: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) enum Wanted {
+    Set(String, String),
+    Append(String, String),
+    Prepend(String, String),
+}
+
+impl Spec {
+    /// Nothing wanted yet.
+    pub fn new() -> Spec {
+        Spec::default()
+    }
+
+    /// `var` should be `value`.
+    pub fn var<T: fmt::Display, U: ToEnvValue>(mut self, var: T, value: U) -> Spec {
+        self.wanted.push(Wanted::Set(var.to_string(), value.to_env_value()));
+        self
+    }
+
+    /// The list in `var` should have `entry`, at the end
+    /// when it's written.
+    pub fn append<T: fmt::Display, U: ToEnvValue>(mut self, var: T, entry: U) -> Spec {
+        self.wanted.push(Wanted::Append(var.to_string(), entry.to_env_value()));
+        self
+    }
+
+    /// The list in `var` should have `entry`, at the front
+    /// when it's written.
+    pub fn prepend<T: fmt::Display, U: ToEnvValue>(mut self, var: T, entry: U) -> Spec {
+        self.wanted.push(Wanted::Prepend(var.to_string(), entry.to_env_value()));
+        self
+    }
+
+    /// Write to the profiles of these shells, rather
+    /// than where the [`Config`] would.
+    pub fn shells(mut self, shells: &[Shell]) -> Spec {
+        self.shells = shells.to_vec();
+        self
+    }
+
+    /// Write to these startup files, see [`Config::target`].
+    pub fn target(mut self, target: Target) -> Spec {
+        self.target = Some(target);
+        self
+    }
+
+    /// Write in a block for `app`, see [`Config::app`].
+    pub fn app<T: fmt::Display>(mut self, app: T) -> Spec {
+        self.app = Some(app.to_string());
+        self
+    }
+
+    /// Reads a spec from a TOML manifest like this,
+    /// where everything is optional:
+    /// ```toml
+    /// app = "myapp"
+    /// shells = ["bash", "zsh", "fish"]
+    /// # login, interactive or both
+    /// target = "login"
+    ///
+    /// [set]
+    /// MYAPP_HOME = "/opt/myapp"
+    ///
+    /// [append]
+    /// PATH = ["/opt/myapp/bin", "/opt/myapp/tools"]
+    ///
+    /// [prepend]
+    /// PATH = "/opt/myapp/shims"
+    /// ```
+    /// It's an [`InvalidData`](std::io::ErrorKind::InvalidData)
+    /// error saying which line is wrong if it can't be read,
+    /// has a key or table twice or uses parts of TOML a
+    /// manifest doesn't need like inline tables.
+    pub fn from_toml(text: &str) -> io::Result<Spec> {
+        manifest::parse(text)
+    }
+
+    /// Reads the TOML manifest at `path`,
+    /// see [`from_toml`](Spec::from_toml).
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Spec> {
+        manifest::read(path.as_ref())
+    }
+
+    // A config for each set of profiles this writes to,
+    // `config` with what this picks changed
+    pub(crate) fn configs(&self, config: &Config) -> Vec<Config> {
+        let mut config = config.clone();
+        if let Some(target) = self.target {
+            config = config.target(target);
+        }
+        if let Some(app) = &self.app {
+            config = config.app(app);
+        }
+        if self.shells.is_empty() {
+            return vec![config];
+        }
+        self.shells.iter().map(|&shell| config.clone().shell(shell)).collect()
+    }
+
+    // The entries wanted in `var`'s list
+    pub(crate) fn entries(&self, var: &str) -> Vec<&str> {
+        self.wanted
+            .iter()
+            .filter_map(|wanted| match wanted {
+                Wanted::Append(name, entry) | Wanted::Prepend(name, entry) if name == var => Some(entry.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// How what's persisted differs from a [`Spec`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Verification {
+    /// Variables that aren't persisted at all and list
+    /// entries that aren't in their list, as the variable
+    /// and the value or entry wanted.
+    pub missing: Vec<(String, String)>,
+    /// Variables persisted with another value.
+    pub differing: Vec<Difference>,
+    /// Entries persisted in the lists the spec adds
+    /// to that it doesn't name, as the variable and
+    /// the entry.
+    pub extra: Vec<(String, String)>,
+}
+
+/// A variable that's persisted with a
+/// different value than a [`Spec`] wants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Difference {
+    /// The variable.
+    pub var: String,
+    /// The value the spec wants.
+    pub wanted: String,
+    /// The value that's persisted.
+    pub actual: String,
+}
+
+impl Verification {
+    /// Whether what's persisted matches the spec,
+    /// extra entries aside.
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.differing.is_empty()
+    }
+}
+
+/// The entries of the persisted list `value` in `var`,
+/// leaving out the reference to what it inherits
+/// like `$PATH` or `%PATH%`.
+pub(crate) fn list_entries<'a>(var: &str, value: &'a str) -> Vec<&'a str> {
+    let inherited = [format!("${}", var), format!("${{{}}}", var), format!("%{}%", var)];
+    value
+        .split(PATH_SEPARATOR)
+        .filter(|entry| !entry.is_empty() && !inherited.iter().any(|inherited| inherited == entry))
+        .collect()
+}
@@ -0,0 +1,219 @@
+//! Everything env_perm has persisted, gathered
+//! from the journal for other tools to read.
+
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::journal::{self, Change};
+use crate::login_conf::{self, LoginConf};
+use crate::spec;
+use crate::windows::{self, Scope};
+use crate::{Backend, Config, EnvBackend, Place, Shell, Spec};
+
+/// Every variable the journal says env_perm has persisted
+/// on this machine, from [`export_state`](crate::export_state).
+/// With the `serde` feature it can be written out as
+/// JSON for auditing tools and uninstallers.
+/// ```no_run
+/// for managed in env_perm::export_state().unwrap().vars {
+///     println!("{} = {:?} in {:?}", managed.var, managed.value, managed.place);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct State {
+    /// Each variable in each place it was written,
+    /// in the order they were first written.
+    pub vars: Vec<ManagedVar>,
+}
+
+/// A variable env_perm wrote to one place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ManagedVar {
+    /// The variable.
+    pub var: String,
+    /// What's persisted there now, None if
+    /// it's been taken out since.
+    pub value: Option<String>,
+    /// The file or registry value it's in.
+    pub place: Place,
+    /// The backend that reads it from there.
+    pub backend: Backend,
+    /// The app that last wrote it, if any.
+    pub app: Option<String>,
+    /// When it was last written.
+    pub written: SystemTime,
+}
+
+/// What the journal says was written, with
+/// files read the way `config` would read them.
+pub(crate) fn export(config: &Config) -> io::Result<State> {
+    let mut vars: Vec<ManagedVar> = Vec::new();
+    for change in journal::history()? {
+        for var in written(config, &change) {
+            match vars.iter_mut().find(|managed| managed.var == var && managed.place == change.place) {
+                Some(managed) => {
+                    managed.app = change.app.clone();
+                    managed.written = change.time;
+                }
+                None => vars.push(ManagedVar {
+                    var,
+                    value: None,
+                    backend: backend(config, &change.place),
+                    place: change.place.clone(),
+                    app: change.app.clone(),
+                    written: change.time,
+                }),
+            }
+        }
+    }
+    for managed in &mut vars {
+        managed.value = match &managed.place {
+            Place::File(path) if is_login_conf(path) => LoginConf.get(&managed.var)?,
+            Place::File(path) => config.file(path).get(&managed.var)?,
+            Place::Registry { key, var } => windows::reg_get(Scope::of(key), var)?,
+        };
+    }
+    Ok(State { vars })
+}
+
+/// What to write so this machine has what `state` has,
+/// a spec for each app. Lists, which are variables that
+/// refer to what they inherit or ones ending in `PATH`
+/// with more than one entry, are split into their entries
+/// so they go in with this machine's separator, the ones
+/// before the reference being prepended. References to
+/// other variables are rewritten between `$VAR` and
+/// `%VAR%` if `windows` differs from where they were
+/// written, `HOME` and `USERPROFILE` swapping.
+pub(crate) fn specs(state: &State, windows: bool) -> Vec<Spec> {
+    let mut specs: Vec<(Option<&str>, Spec)> = Vec::new();
+    for managed in &state.vars {
+        let value = match &managed.value {
+            Some(value) => value,
+            None => continue,
+        };
+        let from_windows = matches!(managed.backend, Backend::WindowsRegistry | Backend::WindowsSetx | Backend::WslInterop);
+        let value = if from_windows == windows { value.clone() } else { references(value, windows) };
+        let i = match specs.iter().position(|(app, _)| *app == managed.app.as_deref()) {
+            Some(i) => i,
+            None => {
+                let spec = managed.app.as_ref().map_or_else(Spec::new, |app| Spec::new().app(app));
+                specs.push((managed.app.as_deref(), spec));
+                specs.len() - 1
+            }
+        };
+        let spec = std::mem::take(&mut specs[i].1);
+        specs[i].1 = want(spec, &managed.var, &value, separator(managed.backend, &value));
+    }
+    specs.into_iter().map(|(_, spec)| spec).collect()
+}
+
+// `spec` also wanting `var` to be `value`, a
+// list separated with `separator` if it's one
+fn want(mut spec: Spec, var: &str, value: &str, separator: char) -> Spec {
+    let entries: Vec<&str> = value.split(separator).collect();
+    // Where it has what it inherits, like `$PATH`
+    let reference = entries.iter().position(|entry| !entry.is_empty() && spec::list_entries(var, entry).is_empty());
+    if reference.is_none() && !(var.ends_with("PATH") && value.contains(separator)) {
+        return spec.var(var, value);
+    }
+    let (before, after) = match reference {
+        Some(i) => (&entries[..i], &entries[i + 1..]),
+        None => (&entries[..0], &entries[..]),
+    };
+    // Prepending one at a time puts them in reverse
+    for entry in before.iter().rev().filter(|entry| !entry.is_empty()) {
+        spec = spec.prepend(var, *entry);
+    }
+    for entry in after.iter().filter(|entry| !entry.is_empty()) {
+        spec = spec.append(var, *entry);
+    }
+    spec
+}
+
+// What lists were separated with where `value` was written
+fn separator(backend: Backend, value: &str) -> char {
+    match backend {
+        Backend::WindowsRegistry | Backend::WindowsSetx | Backend::WslInterop => ';',
+        Backend::Profile(Shell::Pwsh) if value.contains(';') => ';',
+        _ => ':',
+    }
+}
+
+// `value` with `$VAR` and `${VAR}` written as `%VAR%`
+// for windows, or the other way round
+fn references(value: &str, windows: bool) -> String {
+    let mut rewritten = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(c) = rest.chars().next() {
+        let (name, len) = match c {
+            '$' if windows => match rest[1..].strip_prefix('{') {
+                Some(braced) => match braced.find('}') {
+                    Some(end) if is_name(&braced[..end]) => (&braced[..end], end + 3),
+                    _ => ("", 0),
+                },
+                None => {
+                    let end = rest[1..].find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len() - 1);
+                    (&rest[1..=end], end + 1)
+                }
+            },
+            '%' if !windows => match rest[1..].find('%') {
+                Some(end) => (&rest[1..=end], end + 2),
+                None => ("", 0),
+            },
+            _ => ("", 0),
+        };
+        if !is_name(name) {
+            rewritten.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        match (windows, name) {
+            (true, "HOME") => rewritten.push_str("%USERPROFILE%"),
+            (true, _) => rewritten.push_str(&format!("%{}%", name)),
+            (false, "USERPROFILE") => rewritten.push_str("$HOME"),
+            (false, _) => rewritten.push_str(&format!("${{{}}}", name)),
+        }
+        rest = &rest[len..];
+    }
+    rewritten
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// The variables `change` assigned to
+fn written(config: &Config, change: &Change) -> Vec<String> {
+    match &change.place {
+        Place::Registry { var, .. } if !change.added.is_empty() => vec![var.clone()],
+        Place::Registry { .. } => Vec::new(),
+        Place::File(path) if is_login_conf(path) => login_conf::written(&change.removed(), &change.added),
+        Place::File(path) => {
+            let shell = config.file_shell(path);
+            let mut names: Vec<String> = Vec::new();
+            for (name, _) in change.added.iter().filter_map(|line| shell.parse_line(line)) {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            names
+        }
+    }
+}
+
+fn backend(config: &Config, place: &Place) -> Backend {
+    match place {
+        Place::File(path) if is_login_conf(path) => Backend::LoginConf,
+        Place::File(path) => Backend::Profile(config.file_shell(path)),
+        Place::Registry { .. } => Backend::WindowsRegistry,
+    }
+}
+
+fn is_login_conf(path: &Path) -> bool {
+    login_conf::path().is_ok_and(|login_conf| login_conf == path)
+}
@@ -0,0 +1,220 @@
+//! Writing the same variables to several shells at once.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::profile::{self, Writer};
+use crate::shell::{self, Shell};
+use crate::{Config, Overrides, Quoting, Target, ToEnvValue};
+
+/// Writes equivalent assignments to the
+/// profiles of several shells in one go.
+/// ```no_run
+/// use env_perm::{Shell, Sync};
+///
+/// let written = Sync::new()
+///     .var("DUMMY", 1)
+///     .prepend("PATH", "$HOME/some/cool/bin")
+///     .shells(&[Shell::Bash, Shell::Zsh, Shell::Fish])
+///     .apply()
+///     .expect("Failed to write profiles");
+/// for w in written {
+///     println!("{}: {}", w.file.display(), w.line);
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Sync {
+    changes: Vec<Change>,
+    shells: Vec<Shell>,
+    target: Target,
+    quoting: Quoting,
+}
+
+#[derive(Debug, Clone)]
+enum Change {
+    Set(String, String),
+    // None is the shell's usual separator
+    Append(String, String, Option<char>),
+    Prepend(String, String, Option<char>),
+}
+
+/// A line [`Sync::apply`] added to a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Written {
+    /// The shell whose syntax the line is in.
+    pub shell: Shell,
+    /// The file that was written to.
+    pub file: PathBuf,
+    /// The line that was added.
+    pub line: String,
+}
+
+impl Sync {
+    /// Nothing to write yet and every shell
+    /// that looks set up on this machine.
+    pub fn new() -> Sync {
+        Sync::default()
+    }
+
+    /// Set `var` to `value`.
+    pub fn var<T: fmt::Display, U: ToEnvValue>(mut self, var: T, value: U) -> Sync {
+        self.changes.push(Change::Set(var.to_string(), value.to_env_value()));
+        self
+    }
+
+    /// Append `value` to `var`.
+    pub fn append<T: fmt::Display, U: ToEnvValue>(mut self, var: T, value: U) -> Sync {
+        self.changes.push(Change::Append(var.to_string(), value.to_env_value(), None));
+        self
+    }
+
+    /// Prepend `value` to `var`.
+    pub fn prepend<T: fmt::Display, U: ToEnvValue>(mut self, var: T, value: U) -> Sync {
+        self.changes.push(Change::Prepend(var.to_string(), value.to_env_value(), None));
+        self
+    }
+
+    /// Write to these shells only.
+    /// By default it's the detected shell plus
+    /// every shell whose profile already exists.
+    pub fn shells(mut self, shells: &[Shell]) -> Sync {
+        self.shells = shells.to_vec();
+        self
+    }
+
+    /// Choose which startup files to write to.
+    pub fn target(mut self, target: Target) -> Sync {
+        self.target = target;
+        self
+    }
+
+    /// Choose how values are quoted.
+    pub fn quoting(mut self, quoting: Quoting) -> Sync {
+        self.quoting = quoting;
+        self
+    }
+
+    /// Writes every change to every shell's files.
+    /// Each file is only written once, in one go,
+    /// even if several shells read it.
+    ///
+    /// If `ENV_PERM_PROFILE` is set only that file is written.
+    pub fn apply(&self) -> io::Result<Vec<Written>> {
+        self.write(self.files()?)
+    }
+
+    fn write(&self, targets: Vec<(Shell, PathBuf)>) -> io::Result<Vec<Written>> {
+        // Check everything first so nothing is half written
+        for (shell, _) in &targets {
+            for change in &self.changes {
+                let (Change::Set(var, value) | Change::Append(var, value, _) | Change::Prepend(var, value, _)) = change;
+                profile::one_line(*shell, self.quoting, var, value)?;
+            }
+        }
+        let mut written = Vec::new();
+        let mut files: Vec<PathBuf> = Vec::new();
+        for (shell, file) in targets {
+            // bash and ksh can both end up on .profile
+            if files.contains(&file) {
+                continue;
+            }
+            let lines: Vec<String> = self
+                .changes
+                .iter()
+                .map(|change| match change {
+                    Change::Set(var, value) => shell.set_line(var, value, self.quoting),
+                    Change::Append(var, value, separator) => {
+                        let separator = separator.unwrap_or_else(|| shell.separator());
+                        shell.append_line_with(var, value, separator, self.quoting)
+                    }
+                    Change::Prepend(var, value, separator) => {
+                        let separator = separator.unwrap_or_else(|| shell.separator());
+                        shell.prepend_line_with(var, value, separator, self.quoting)
+                    }
+                })
+                .collect();
+            if !lines.is_empty() {
+                profile::write_block(&file, &lines, Writer::default())?;
+            }
+            written.extend(lines.into_iter().map(|line| Written { shell, file: file.clone(), line }));
+            files.push(file);
+        }
+        Ok(written)
+    }
+
+    fn files(&self) -> io::Result<Vec<(Shell, PathBuf)>> {
+        // The user asked for everything to go in one file
+        if let Some(profile) = Overrides::from_env().profile {
+            return Ok(vec![(Config::new().file_shell(&profile), profile)]);
+        }
+        let mut files = Vec::new();
+        if self.shells.is_empty() {
+            let home = shell::home_dir()?;
+            let detected = Shell::detect();
+            for file in detected.target_paths(self.target)? {
+                files.push((detected, file));
+            }
+            files.extend(shell::ALL
+                .iter()
+                .filter_map(|&shell| shell.existing_profile(&home).map(|profile| (shell, profile))));
+        } else {
+            for &shell in &self.shells {
+                for file in shell.target_paths(self.target)? {
+                    files.push((shell, file));
+                }
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Copies the assignments to `vars` from the profiles of
+/// `from` into the login profile of `to`, rewritten in its syntax.
+/// Appends and prepends stay that way so several `PATH` entries carry over
+/// in the same order. An empty `vars` copies everything.
+/// Returns the lines that were written.
+///
+/// Values are copied as they were written so any references
+/// to other variables must make sense in both shells.
+/// ```no_run
+/// use env_perm::Shell;
+///
+/// env_perm::migrate(Shell::Bash, Shell::Fish, &["PATH", "GOPATH"])
+///     .expect("Failed to migrate");
+/// ```
+pub fn migrate(from: Shell, to: Shell, vars: &[&str]) -> io::Result<Vec<Written>> {
+    let home = shell::home_dir()?;
+    let mut sources: Vec<PathBuf> = Vec::new();
+    for file in from.profiles(&home).into_iter().chain(from.rc_profiles(&home)) {
+        if file.is_file() && !sources.contains(&file) {
+            sources.push(file);
+        }
+    }
+    let mut sync = Sync::new().shells(&[to]);
+    for file in &sources {
+        for line in profile::read_lines(file)? {
+            let (var, value) = match from.parse_line(&line) {
+                Some(parsed) => parsed,
+                None => continue,
+            };
+            if !vars.is_empty() && !vars.contains(&var.as_str()) {
+                continue;
+            }
+            // Lists keep their separator unless it's
+            // just the one `from` always uses
+            let custom = |separator: char| Some(separator).filter(|&s| s != from.separator());
+            let change = if let Some((added, separator)) = from.appended_with(&var, &value) {
+                Change::Append(var, added, custom(separator))
+            } else if let Some((added, separator)) = from.prepended_with(&var, &value) {
+                Change::Prepend(var, added, custom(separator))
+            } else {
+                Change::Set(var, value)
+            };
+            sync.changes.push(change);
+        }
+    }
+    let targets = to.target_paths(Target::LoginProfile)?;
+    sync.write(targets.into_iter().map(|file| (to, file)).collect())
+}
@@ -0,0 +1,88 @@
+//! Telling when someone edited inside one of
+//! our blocks since we last wrote it.
+
+use std::io;
+use std::path::Path;
+
+use crate::journal;
+use crate::profile;
+use crate::Place;
+
+/// Whether an app's block in a file is the way env_perm
+/// last wrote it, from [`block_status`](crate::block_status).
+/// A block that's been edited by hand is
+/// [`Tampered`](BlockStatus::Tampered), so tools can ask
+/// before writing over the user's changes.
+/// ```no_run
+/// use env_perm::BlockStatus;
+///
+/// for (path, status) in env_perm::block_status("myapp").unwrap() {
+///     if status == BlockStatus::Tampered {
+///         println!("{} was edited by hand, overwrite it?", path.display());
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockStatus {
+    /// The file doesn't have a block for the app.
+    Missing,
+    /// It's the way env_perm last wrote it.
+    Intact,
+    /// Something inside the markers changed since
+    /// env_perm last wrote the file.
+    Tampered,
+    /// The journal doesn't say what it should be,
+    /// e.g. because the markers were written by hand.
+    Unrecorded,
+}
+
+/// The checksum of each app's block in `lines`, by app.
+pub(crate) fn checksums(lines: &[String]) -> Vec<(String, u64)> {
+    lines
+        .iter()
+        .filter_map(|line| line.trim().strip_prefix("# >>> env_perm (")?.strip_suffix(") >>>"))
+        .filter_map(|app| Some((app.to_string(), checksum(lines, app)?)))
+        .collect()
+}
+
+// A hash of what's between `app`'s markers
+fn checksum(lines: &[String], app: &str) -> Option<u64> {
+    let (open, close) = profile::find_block(lines, app)?;
+    Some(hash(&lines[open + 1..close]))
+}
+
+/// A hash of `lines` that stays the same between runs
+/// and versions of Rust, unlike `DefaultHasher`, since
+/// it's kept in the journal. This is 64 bit FNV-1a.
+pub(crate) fn hash(lines: &[String]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for line in lines {
+        for byte in line.bytes().chain(Some(b'\n')) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// How `app`'s block in the file at `path` compares with
+/// the checksum the journal last recorded for it.
+pub(crate) fn status(path: &Path, app: &str) -> io::Result<BlockStatus> {
+    let lines = profile::read_lines(path)?;
+    let current = match checksum(&lines, app) {
+        Some(current) => current,
+        None => return Ok(BlockStatus::Missing),
+    };
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let recorded = journal::history()?
+        .into_iter()
+        .rev()
+        .find(|change| change.place == Place::File(path.clone()))
+        .and_then(|change| change.blocks.into_iter().find(|(name, _)| name == app));
+    Ok(match recorded {
+        Some((_, recorded)) if recorded == current => BlockStatus::Intact,
+        Some(_) => BlockStatus::Tampered,
+        None => BlockStatus::Unrecorded,
+    })
+}
@@ -0,0 +1,46 @@
+//! Helpers for the unit tests.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+/// An empty directory only one test uses,
+/// deleted again when it's dropped.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// A new directory with `name` in its name, unique to
+    /// this process and call so tests running at the same
+    /// time or again never share files.
+    pub(crate) fn new(name: &str) -> TempDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        state_dir();
+        let n = COUNT.fetch_add(1, Ordering::Relaxed);
+        let path = env::temp_dir().join(format!("env_perm-test-{}-{}-{}", process::id(), n, name));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Points the journal and the lock at a directory of this
+// process's own, once, rather than the user's state
+// directory. It's set before any test writes anything
+// so they all agree on it.
+fn state_dir() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| env::set_var("ENV_PERM_STATE_DIR", env::temp_dir().join(format!("env_perm-test-{}-state", process::id()))));
+}
@@ -0,0 +1,158 @@
+//! Writing the variables of a user who isn't logged
+//! on, by loading their registry hive.
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::windows;
+#[cfg(windows)]
+use crate::windows::RegistryType;
+#[cfg(windows)]
+use crate::Backend;
+use crate::ToEnvValue;
+
+/// Another user's registry hive, loaded from their
+/// `NTUSER.DAT` to provision their environment, e.g. from
+/// a machine setup script. It's loaded privately to this
+/// process and unloaded when dropped.
+///
+/// Loading it needs the process to be run as administrator
+/// and fails with [`io::ErrorKind::PermissionDenied`]
+/// otherwise. It also fails while the user is logged on
+/// since windows has the file open then. Nothing written
+/// here goes in the journal and nothing is broadcast, the
+/// user gets it when they next log on.
+/// ```no_run
+/// use env_perm::UserHive;
+///
+/// let hive = UserHive::load(r"C:\Users\alice\NTUSER.DAT").expect("Needs to run as administrator");
+/// hive.set("MYAPP_HOME", r"C:\Program Files\MyApp").unwrap();
+/// hive.append("PATH", r"C:\Program Files\MyApp\bin").unwrap();
+/// ```
+pub struct UserHive {
+    path: PathBuf,
+    #[cfg(windows)]
+    root: winreg::RegKey,
+    // It can't be loaded anywhere else
+    #[cfg(not(windows))]
+    never: std::convert::Infallible,
+}
+
+impl fmt::Debug for UserHive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserHive").field("path", &self.path).finish()
+    }
+}
+
+impl UserHive {
+    /// Loads the hive in the file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<UserHive> {
+        let path = path.as_ref().to_path_buf();
+        if !windows::is_elevated() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Loading {} needs the process to be run as administrator", path.display()),
+            ));
+        }
+        open(path)
+    }
+
+    /// The file it was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sets `var` in the user's environment. Values keep their
+    /// type like with [`RegistryType::Keep`](crate::RegistryType::Keep).
+    pub fn set<T: fmt::Display, U: ToEnvValue>(&self, var: T, value: U) -> io::Result<()> {
+        self.write(&var.to_string(), &value.to_env_os_value())
+    }
+
+    /// Adds `value` to the end of the `;` separated
+    /// list in `var`, if it isn't there already.
+    pub fn append<T: fmt::Display, U: ToEnvValue>(&self, var: T, value: U) -> io::Result<()> {
+        let (var, value) = (var.to_string(), value.to_env_value());
+        let current = self.get(&var)?;
+        if current.as_deref().is_some_and(|current| current.split(';').any(|part| part == value)) {
+            return Ok(());
+        }
+        self.write(&var, &windows::join(current.as_deref().map(OsStr::new), Some(OsStr::new(&value)), ';'))
+    }
+
+    /// The user's value of `var`, with its
+    /// `%VAR%` references left alone.
+    pub fn get<T: fmt::Display>(&self, var: T) -> io::Result<Option<String>> {
+        self.read(&var.to_string())
+    }
+
+    /// Deletes `var` from the user's environment.
+    /// It's not an error if it isn't there.
+    pub fn unset<T: fmt::Display>(&self, var: T) -> io::Result<()> {
+        self.delete(&var.to_string())
+    }
+}
+
+#[cfg(windows)]
+fn open(path: PathBuf) -> io::Result<UserHive> {
+    use winreg::enums::{KEY_READ, KEY_WRITE, REG_PROCESS_APPKEY};
+
+    let root = winreg::RegKey::load_app_key_with_flags(&path, KEY_READ | KEY_WRITE, REG_PROCESS_APPKEY).map_err(|e| {
+        io::Error::new(e.kind(), format!("Couldn't load {}, is the user logged on? {}", path.display(), e))
+    })?;
+    Ok(UserHive { path, root })
+}
+
+#[cfg(not(windows))]
+fn open(_path: PathBuf) -> io::Result<UserHive> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Registry hives can only be loaded on windows"))
+}
+
+#[cfg(windows)]
+impl UserHive {
+    fn write(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        use winreg::enums::{KEY_READ, KEY_WRITE};
+
+        windows::fits(var, value, windows::REGISTRY_LIMIT, Backend::WindowsRegistry)?;
+        let (key, _) = self.root.create_subkey_with_flags("Environment", KEY_READ | KEY_WRITE)?;
+        windows::write_value(&key, var, value, RegistryType::Keep)
+    }
+
+    fn read(&self, var: &str) -> io::Result<Option<String>> {
+        match self.root.open_subkey("Environment") {
+            Ok(key) => windows::read_value(&key, var, &format!(r"{}\Environment", self.path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn delete(&self, var: &str) -> io::Result<()> {
+        use winreg::enums::{KEY_READ, KEY_WRITE};
+
+        let key = match self.root.open_subkey_with_flags("Environment", KEY_READ | KEY_WRITE) {
+            Ok(key) => key,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        match key.delete_value(var) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(not(windows))]
+impl UserHive {
+    fn write(&self, _var: &str, _value: &OsStr) -> io::Result<()> {
+        match self.never {}
+    }
+
+    fn read(&self, _var: &str) -> io::Result<Option<String>> {
+        match self.never {}
+    }
+
+    fn delete(&self, _var: &str) -> io::Result<()> {
+        match self.never {}
+    }
+}
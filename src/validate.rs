@@ -0,0 +1,188 @@
+//! Checks the application registers that every
+//! write has to pass before anything is written.
+
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crate::{Backend, EnvBackend};
+
+/// The error a write fails with when a validator from
+/// [`Config::validate`](crate::Config::validate) turned it down.
+/// Nothing is written.
+/// ```no_run
+/// use env_perm::{Config, Rejected};
+///
+/// let config = Config::new().validate(|var, _, _| match var {
+///     "LD_PRELOAD" => Err("LD_PRELOAD is forbidden by policy".to_string()),
+///     _ => Ok(()),
+/// });
+/// if let Err(e) = config.set("LD_PRELOAD", "/tmp/hook.so") {
+///     match Rejected::from_error(&e) {
+///         Some(rejected) => println!("{}", rejected.reason),
+///         None => panic!("{}", e),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rejected {
+    /// The variable that was going to be written.
+    pub var: String,
+    /// The value it was going to get. For an append or
+    /// prepend it's the entry being added.
+    pub value: String,
+    /// The backend it was going to be written with,
+    /// `None` for one from
+    /// [`Config::custom_backend`](crate::Config::custom_backend).
+    pub backend: Option<Backend>,
+    /// Why, as the validator said.
+    pub reason: String,
+}
+
+impl Rejected {
+    /// The rejection `e` is about, if it's one.
+    pub fn from_error(e: &io::Error) -> Option<&Rejected> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Writing {} was rejected: {}", self.var, self.reason)
+    }
+}
+
+impl Error for Rejected {}
+
+impl From<Rejected> for io::Error {
+    fn from(rejected: Rejected) -> io::Error {
+        io::Error::other(rejected)
+    }
+}
+
+// What a validator is given and gives back
+type Check = dyn Fn(&str, &str, Option<Backend>) -> Result<(), String> + Send + Sync;
+
+/// A check from [`Config::validate`](crate::Config::validate).
+#[derive(Clone)]
+pub(crate) struct Validator(pub(crate) Arc<Check>);
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Validator")
+    }
+}
+
+/// A backend that runs every validator against each
+/// write, for each of `backends`, before handing
+/// it to `inner`. Reads go straight through.
+pub(crate) struct Checked<B: ?Sized> {
+    pub(crate) backends: Vec<Option<Backend>>,
+    pub(crate) validators: Vec<Validator>,
+    pub(crate) inner: Box<B>,
+}
+
+impl<B: EnvBackend + ?Sized> Checked<B> {
+    fn check(&self, var: &str, value: &str) -> io::Result<()> {
+        for backend in &self.backends {
+            for validator in &self.validators {
+                if let Err(reason) = (validator.0)(var, value, *backend) {
+                    return Err(Rejected { var: var.to_string(), value: value.to_string(), backend: *backend, reason }.into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        self.check(var, &value.to_string_lossy())
+    }
+}
+
+impl<B: EnvBackend + ?Sized> EnvBackend for Checked<B> {
+    fn set(&self, var: &str, value: &str) -> io::Result<()> {
+        self.check(var, value)?;
+        self.inner.set(var, value)
+    }
+
+    // Every one is checked before any is written
+    fn set_many(&self, vars: &[(String, String)]) -> io::Result<()> {
+        vars.iter().try_for_each(|(var, value)| self.check(var, value))?;
+        self.inner.set_many(vars)
+    }
+
+    fn append(&self, var: &str, value: &str) -> io::Result<()> {
+        self.check(var, value)?;
+        self.inner.append(var, value)
+    }
+
+    fn prepend(&self, var: &str, value: &str) -> io::Result<()> {
+        self.check(var, value)?;
+        self.inner.prepend(var, value)
+    }
+
+    fn set_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        self.check_os(var, value)?;
+        self.inner.set_os(var, value)
+    }
+
+    fn append_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        self.check_os(var, value)?;
+        self.inner.append_os(var, value)
+    }
+
+    fn prepend_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        self.check_os(var, value)?;
+        self.inner.prepend_os(var, value)
+    }
+
+    fn append_with(&self, var: &str, value: &str, separator: char) -> io::Result<()> {
+        self.check(var, value)?;
+        self.inner.append_with(var, value, separator)
+    }
+
+    fn append_with_os(&self, var: &str, value: &OsStr, separator: char) -> io::Result<()> {
+        self.check_os(var, value)?;
+        self.inner.append_with_os(var, value, separator)
+    }
+
+    fn update(&self, var: &str, value: &str) -> io::Result<()> {
+        self.check(var, value)?;
+        self.inner.update(var, value)
+    }
+
+    fn remove(&self, var: &str) -> io::Result<()> {
+        self.inner.remove(var)
+    }
+
+    fn remove_entry(&self, var: &str, entry: &str) -> io::Result<()> {
+        self.inner.remove_entry(var, entry)
+    }
+
+    fn dedupe(&self, var: &str) -> io::Result<()> {
+        self.inner.dedupe(var)
+    }
+
+    // The value moving to the new name has to pass
+    fn rename(&self, from: &str, to: &str) -> io::Result<()> {
+        if let Some(value) = self.inner.get(from)? {
+            self.check(to, &value)?;
+        }
+        self.inner.rename(from, to)
+    }
+
+    fn get(&self, var: &str) -> io::Result<Option<String>> {
+        self.inner.get(var)
+    }
+
+    fn list(&self) -> io::Result<Vec<(String, String)>> {
+        self.inner.list()
+    }
+
+    fn contains(&self, var: &str, entry: &str) -> io::Result<bool> {
+        self.inner.contains(var, entry)
+    }
+}
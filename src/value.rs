@@ -0,0 +1,158 @@
+//! Turning typed values into the text of a variable.
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+use crate::shell::PATH_SEPARATOR;
+
+/// A value that can be stored in an environment variable.
+///
+/// Strings are used as they are, numbers and `bool`s the
+/// way `Display` writes them and paths as the platform writes
+/// them. Lists like `&[PathBuf]` are joined with the platform's
+/// separator, `:` or `;` on windows, so they fit in `PATH`.
+/// Paths and `OsStr`s don't have to be UTF-8, see
+/// [`to_env_os_value`](ToEnvValue::to_env_os_value).
+/// ```
+/// use std::path::PathBuf;
+/// use env_perm::ToEnvValue;
+///
+/// let dirs = vec![PathBuf::from("/opt/a/bin"), PathBuf::from("/opt/b/bin")];
+/// # #[cfg(not(windows))]
+/// assert_eq!(dirs.to_env_value(), "/opt/a/bin:/opt/b/bin");
+/// assert_eq!(true.to_env_value(), "true");
+/// ```
+pub trait ToEnvValue {
+    /// The text to store.
+    fn to_env_value(&self) -> String;
+
+    /// The value to store, which can hold bytes that
+    /// aren't UTF-8 where `to_env_value` couldn't.
+    /// By default this is `to_env_value`.
+    fn to_env_os_value(&self) -> OsString {
+        self.to_env_value().into()
+    }
+}
+
+impl ToEnvValue for str {
+    fn to_env_value(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl ToEnvValue for String {
+    fn to_env_value(&self) -> String {
+        self.clone()
+    }
+}
+
+impl ToEnvValue for Cow<'_, str> {
+    fn to_env_value(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl ToEnvValue for OsStr {
+    fn to_env_value(&self) -> String {
+        self.to_string_lossy().into_owned()
+    }
+
+    fn to_env_os_value(&self) -> OsString {
+        self.to_os_string()
+    }
+}
+
+impl ToEnvValue for OsString {
+    fn to_env_value(&self) -> String {
+        self.as_os_str().to_env_value()
+    }
+
+    fn to_env_os_value(&self) -> OsString {
+        self.clone()
+    }
+}
+
+impl ToEnvValue for Path {
+    fn to_env_value(&self) -> String {
+        self.as_os_str().to_env_value()
+    }
+
+    fn to_env_os_value(&self) -> OsString {
+        self.as_os_str().to_os_string()
+    }
+}
+
+impl ToEnvValue for PathBuf {
+    fn to_env_value(&self) -> String {
+        self.as_path().to_env_value()
+    }
+
+    fn to_env_os_value(&self) -> OsString {
+        self.as_path().to_env_os_value()
+    }
+}
+
+impl<T: ToEnvValue + ?Sized> ToEnvValue for &T {
+    fn to_env_value(&self) -> String {
+        (**self).to_env_value()
+    }
+
+    fn to_env_os_value(&self) -> OsString {
+        (**self).to_env_os_value()
+    }
+}
+
+impl<T: ToEnvValue> ToEnvValue for [T] {
+    fn to_env_value(&self) -> String {
+        self.iter()
+            .map(ToEnvValue::to_env_value)
+            .collect::<Vec<_>>()
+            .join(&PATH_SEPARATOR.to_string())
+    }
+
+    fn to_env_os_value(&self) -> OsString {
+        let mut joined = OsString::new();
+        for (i, value) in self.iter().enumerate() {
+            if i > 0 {
+                joined.push(PATH_SEPARATOR.to_string());
+            }
+            joined.push(value.to_env_os_value());
+        }
+        joined
+    }
+}
+
+impl<T: ToEnvValue> ToEnvValue for Vec<T> {
+    fn to_env_value(&self) -> String {
+        self.as_slice().to_env_value()
+    }
+
+    fn to_env_os_value(&self) -> OsString {
+        self.as_slice().to_env_os_value()
+    }
+}
+
+impl<T: ToEnvValue, const N: usize> ToEnvValue for [T; N] {
+    fn to_env_value(&self) -> String {
+        self.as_slice().to_env_value()
+    }
+
+    fn to_env_os_value(&self) -> OsString {
+        self.as_slice().to_env_os_value()
+    }
+}
+
+macro_rules! display_value {
+    ($($t:ty)*) => {
+        $(
+            impl ToEnvValue for $t {
+                fn to_env_value(&self) -> String {
+                    self.to_string()
+                }
+            }
+        )*
+    };
+}
+
+display_value!(bool char i8 i16 i32 i64 i128 isize u8 u16 u32 u64 u128 usize f32 f64);
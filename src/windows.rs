@@ -0,0 +1,1196 @@
+//! Persisting variables in the windows registry.
+
+use std::cell::Cell;
+use std::env;
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io;
+#[cfg(windows)]
+use std::path::PathBuf;
+#[cfg(windows)]
+use std::process::Command;
+
+use crate::journal;
+use crate::lock;
+use crate::plan;
+use crate::Place;
+use crate::{Backend, EnvBackend, Quoting};
+
+thread_local! {
+    // While `batched` runs, whether a write
+    // has held back its broadcast
+    static BATCH: Cell<Option<bool>> = const { Cell::new(None) };
+}
+
+/// Where the current user's variables live.
+pub(crate) const USER_ENVIRONMENT: &str = r"HKCU\Environment";
+
+/// Where variables for every user live.
+pub(crate) const MACHINE_ENVIRONMENT: &str = r"HKLM\SYSTEM\CurrentControlSet\Control\Session Manager\Environment";
+
+/// Whose variables the windows backends write, see
+/// [`Config::scope`](crate::Config::scope).
+/// ```no_run
+/// use env_perm::{Config, Scope};
+///
+/// Config::new()
+///     .scope(Scope::Machine)
+///     .set("MYAPP_HOME", r"C:\Program Files\MyApp")
+///     .expect("Needs to run as administrator");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Scope {
+    /// The current user's, in `HKCU\Environment`.
+    /// This is the default.
+    #[default]
+    User,
+    /// Every user's, in
+    /// `HKLM\SYSTEM\CurrentControlSet\Control\Session Manager\Environment`.
+    /// Writing fails with [`io::ErrorKind::PermissionDenied`]
+    /// unless the process is elevated.
+    Machine,
+}
+
+impl Scope {
+    /// The registry key its variables are under.
+    pub fn key(self) -> &'static str {
+        match self {
+            Scope::User => USER_ENVIRONMENT,
+            Scope::Machine => MACHINE_ENVIRONMENT,
+        }
+    }
+
+    /// The scope whose variables are under `key`,
+    /// as kept in the journal.
+    pub(crate) fn of(key: &str) -> Scope {
+        if key.eq_ignore_ascii_case(MACHINE_ENVIRONMENT) {
+            Scope::Machine
+        } else {
+            Scope::User
+        }
+    }
+}
+
+/// The type a value is written to the registry as, see
+/// [`Config::registry_type`](crate::Config::registry_type).
+/// Only the registry backend can pick, setx
+/// always writes `REG_SZ`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RegistryType {
+    /// Whatever type the value has now, so a `PATH` that's
+    /// `REG_EXPAND_SZ` stays that way when it's appended to.
+    /// New values are `REG_SZ`, or `REG_EXPAND_SZ` if they
+    /// refer to another variable like `%USERPROFILE%` so it's
+    /// looked up each time rather than when it's written.
+    /// This is the default.
+    /// ```no_run
+    /// use env_perm::{Backend, Config};
+    ///
+    /// // Still right if the user's profile moves
+    /// Config::new()
+    ///     .backend(Backend::WindowsRegistry)
+    ///     .set("MYTOOL_HOME", r"%USERPROFILE%\.mytool")
+    ///     .expect("Failed to set MYTOOL_HOME");
+    /// ```
+    #[default]
+    Keep,
+    /// `REG_SZ`, taken as it is.
+    String,
+    /// `REG_EXPAND_SZ`, which has references like
+    /// `%SystemRoot%` expanded when it's read into
+    /// a new process's environment.
+    Expandable,
+}
+
+/// The error a windows write fails with when the value is
+/// longer than the backend can store. Nothing is written,
+/// where setx would cut it off at 1024 characters.
+/// ```no_run
+/// use env_perm::{Backend, Config, TooLong};
+///
+/// let path = vec![r"C:\tools\bin"; 200].join(";");
+/// if let Err(e) = Config::new().backend(Backend::WindowsSetx).set("PATH", path) {
+///     match TooLong::from_error(&e) {
+///         Some(too_long) => println!("{} is over by {}", too_long.var, too_long.length - too_long.limit),
+///         None => panic!("{}", e),
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TooLong {
+    /// The variable that was going to be written.
+    pub var: String,
+    /// How many UTF-16 characters the value has.
+    pub length: usize,
+    /// The most it can have: 1024 for setx and 32,767
+    /// for a variable in the registry.
+    pub limit: usize,
+    /// The backend it was going to be written with.
+    pub backend: Backend,
+}
+
+impl TooLong {
+    /// The value `e` is about, if it's one that's too long.
+    pub fn from_error(e: &io::Error) -> Option<&TooLong> {
+        e.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for TooLong {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} would be {} characters long but {:?} can only write {}", self.var, self.length, self.backend, self.limit)?;
+        if self.backend == Backend::WindowsSetx && self.length <= REGISTRY_LIMIT {
+            write!(f, ", Backend::WindowsRegistry can write it")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for TooLong {}
+
+impl From<TooLong> for io::Error {
+    fn from(too_long: TooLong) -> io::Error {
+        io::Error::other(too_long)
+    }
+}
+
+/// A variable in the registry and the type of value it
+/// is, from [`Config::registry_values`](crate::Config::registry_values).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RegistryValue {
+    /// The variable.
+    pub var: String,
+    /// Its value as it's stored,
+    /// with `%VAR%` references left alone.
+    pub value: String,
+    /// [`RegistryType::String`] for a `REG_SZ` or
+    /// [`RegistryType::Expandable`] for a `REG_EXPAND_SZ`.
+    pub kind: RegistryType,
+    /// Whose variable it is.
+    pub scope: Scope,
+}
+
+// The most characters setx.exe writes before truncating
+const SETX_LIMIT: usize = 1024;
+
+// The most characters a variable can have
+pub(crate) const REGISTRY_LIMIT: usize = 32_767;
+
+// Fails if `value` is longer than `limit`
+pub(crate) fn fits(var: &str, value: &OsStr, limit: usize, backend: Backend) -> io::Result<()> {
+    let length = value.to_string_lossy().encode_utf16().count();
+    if length > limit {
+        return Err(TooLong { var: var.to_string(), length, limit, backend }.into());
+    }
+    Ok(())
+}
+
+/// The scope's registry environment written with setx.
+#[derive(Debug, Clone)]
+pub(crate) struct Setx {
+    pub(crate) scope: Scope,
+    pub(crate) quoting: Quoting,
+    /// Who the changes are recorded for in the journal.
+    pub(crate) app: Option<String>,
+}
+
+/// The user's registry environment written directly
+/// with RegSetValueEx.
+#[derive(Debug, Clone)]
+pub(crate) struct Registry {
+    pub(crate) scope: Scope,
+    pub(crate) kind: RegistryType,
+    pub(crate) quoting: Quoting,
+    pub(crate) app: Option<String>,
+}
+
+impl EnvBackend for Setx {
+    fn set(&self, var: &str, value: &str) -> io::Result<()> {
+        self.set_os(var, OsStr::new(value))
+    }
+
+    fn append(&self, var: &str, value: &str) -> io::Result<()> {
+        self.append_os(var, OsStr::new(value))
+    }
+
+    fn prepend(&self, var: &str, value: &str) -> io::Result<()> {
+        self.prepend_os(var, OsStr::new(value))
+    }
+
+    fn set_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        lock::locked(|| {
+            let previous = reg_get(self.scope, var)?;
+            self.write(var, literal(self.quoting, value))?;
+            record(self.scope, var, self.app.as_deref(), previous, Some(literal(self.quoting, value)))
+        })
+    }
+
+    fn append_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        lock::locked(|| {
+            let previous = reg_get(self.scope, var)?;
+            let joined = appended(previous.as_deref(), literal(self.quoting, value), ';');
+            self.write(var, &joined)?;
+            record(self.scope, var, self.app.as_deref(), previous, Some(&joined))
+        })
+    }
+
+    fn prepend_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        lock::locked(|| {
+            let previous = reg_get(self.scope, var)?;
+            let joined = prepended(previous.as_deref(), literal(self.quoting, value));
+            self.write(var, &joined)?;
+            record(self.scope, var, self.app.as_deref(), previous, Some(&joined))
+        })
+    }
+
+    fn append_with(&self, var: &str, value: &str, separator: char) -> io::Result<()> {
+        self.append_with_os(var, OsStr::new(value), separator)
+    }
+
+    fn append_with_os(&self, var: &str, value: &OsStr, separator: char) -> io::Result<()> {
+        lock::locked(|| {
+            let previous = reg_get(self.scope, var)?;
+            let joined = appended(previous.as_deref(), literal(self.quoting, value), separator);
+            self.write(var, &joined)?;
+            record(self.scope, var, self.app.as_deref(), previous, Some(&joined))
+        })
+    }
+
+    // Running setx for each would take a process and a
+    // broadcast every time, so they're all written to the
+    // registry like setx would and broadcast once
+    fn set_many(&self, vars: &[(String, String)]) -> io::Result<()> {
+        batched(|| {
+            lock::locked(|| {
+                for (var, value) in vars {
+                    let value = literal(self.quoting, OsStr::new(value));
+                    let previous = reg_get(self.scope, var)?;
+                    reg_set(self.scope, var, value, RegistryType::Keep)?;
+                    record(self.scope, var, self.app.as_deref(), previous, Some(value))?;
+                }
+                Ok(())
+            })
+        })
+    }
+
+    // setx can't delete so this goes
+    // through the registry too
+    fn remove(&self, var: &str) -> io::Result<()> {
+        remove(self.scope, var, self.app.as_deref())
+    }
+
+    fn get(&self, var: &str) -> io::Result<Option<String>> {
+        reg_get(self.scope, var)
+    }
+
+    fn list(&self) -> io::Result<Vec<(String, String)>> {
+        reg_list(self.scope)
+    }
+}
+
+impl Setx {
+    // Appends and prepends join onto the user's own value as
+    // it's stored, never the expanded one this process has
+    // with the machine's entries in it too. setx would make
+    // a REG_EXPAND_SZ into a REG_SZ so its %VAR% references
+    // stop working, so those are written directly instead,
+    // like new values with references in them and ones
+    // setx's command line would mangle.
+    fn write(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        let exists = reg_get(self.scope, var)?.is_some();
+        if past_setx(value, exists, is_expand(self.scope, var)?) {
+            reg_set(self.scope, var, value, RegistryType::Keep)
+        } else {
+            setx(self.scope, var, value)
+        }
+    }
+}
+
+// Whether the setx backend writes `value` straight to the
+// registry instead, given whether the variable `exists`
+// already and whether it's an `expand`able one
+fn past_setx(value: &OsStr, exists: bool, expand: bool) -> bool {
+    expand || !setx_safe(value) || (!exists && value.to_str().is_some_and(has_reference))
+}
+
+impl EnvBackend for Registry {
+    fn set(&self, var: &str, value: &str) -> io::Result<()> {
+        self.set_os(var, OsStr::new(value))
+    }
+
+    fn append(&self, var: &str, value: &str) -> io::Result<()> {
+        self.append_os(var, OsStr::new(value))
+    }
+
+    fn prepend(&self, var: &str, value: &str) -> io::Result<()> {
+        self.prepend_os(var, OsStr::new(value))
+    }
+
+    fn set_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        lock::locked(|| {
+            let previous = reg_get(self.scope, var)?;
+            reg_set(self.scope, var, literal(self.quoting, value), self.kind)?;
+            record(self.scope, var, self.app.as_deref(), previous, Some(literal(self.quoting, value)))
+        })
+    }
+
+    fn append_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        lock::locked(|| {
+            let previous = reg_get(self.scope, var)?;
+            let joined = appended(previous.as_deref(), literal(self.quoting, value), ';');
+            reg_set(self.scope, var, &joined, self.kind)?;
+            record(self.scope, var, self.app.as_deref(), previous, Some(&joined))
+        })
+    }
+
+    fn prepend_os(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        lock::locked(|| {
+            let previous = reg_get(self.scope, var)?;
+            let joined = prepended(previous.as_deref(), literal(self.quoting, value));
+            reg_set(self.scope, var, &joined, self.kind)?;
+            record(self.scope, var, self.app.as_deref(), previous, Some(&joined))
+        })
+    }
+
+    fn append_with(&self, var: &str, value: &str, separator: char) -> io::Result<()> {
+        self.append_with_os(var, OsStr::new(value), separator)
+    }
+
+    fn append_with_os(&self, var: &str, value: &OsStr, separator: char) -> io::Result<()> {
+        lock::locked(|| {
+            let previous = reg_get(self.scope, var)?;
+            let joined = appended(previous.as_deref(), literal(self.quoting, value), separator);
+            reg_set(self.scope, var, &joined, self.kind)?;
+            record(self.scope, var, self.app.as_deref(), previous, Some(&joined))
+        })
+    }
+
+    // One broadcast for all of them rather than one each
+    fn set_many(&self, vars: &[(String, String)]) -> io::Result<()> {
+        batched(|| vars.iter().try_for_each(|(var, value)| self.set(var, value)))
+    }
+
+    fn remove(&self, var: &str) -> io::Result<()> {
+        remove(self.scope, var, self.app.as_deref())
+    }
+
+    fn get(&self, var: &str) -> io::Result<Option<String>> {
+        reg_get(self.scope, var)
+    }
+
+    fn list(&self) -> io::Result<Vec<(String, String)>> {
+        reg_list(self.scope)
+    }
+}
+
+// Records a write to `var` in the journal
+fn record(scope: Scope, var: &str, app: Option<&str>, previous: Option<String>, value: Option<&OsStr>) -> io::Result<()> {
+    let value = value.map(|value| value.to_string_lossy().into_owned());
+    journal::record_registry(scope.key(), var, app, previous, value)
+}
+
+// Deletes `var` and records it if there was anything
+fn remove(scope: Scope, var: &str, app: Option<&str>) -> io::Result<()> {
+    lock::locked(|| {
+        let previous = reg_get(scope, var)?;
+        if previous.is_none() {
+            return Ok(());
+        }
+        reg_delete(scope, var)?;
+        record(scope, var, app, previous, None)
+    })
+}
+
+/// Puts `var` back to `previous`, deleting it if that's
+/// None, without recording it in the journal.
+pub(crate) fn restore(scope: Scope, var: &str, previous: Option<&str>) -> io::Result<()> {
+    match previous {
+        Some(previous) => reg_set(scope, var, OsStr::new(previous), RegistryType::Keep),
+        None if reg_get(scope, var)?.is_some() => reg_delete(scope, var),
+        None => Ok(()),
+    }
+}
+
+/// Takes `added` out of `var`'s entries for `app`. If that
+/// leaves nothing `var` goes back to `first`, what it was
+/// before `app` wrote to it, or is deleted if it wasn't set.
+/// Gives whether anything changed.
+pub(crate) fn take_out(scope: Scope, var: &str, app: &str, first: Option<&str>, added: &[String]) -> io::Result<bool> {
+    lock::locked(|| {
+        let current = match reg_get(scope, var)? {
+            Some(current) => current,
+            None => return Ok(false),
+        };
+        let kept: Vec<&str> = current
+            .split(';')
+            .filter(|entry| !entry.is_empty() && !added.iter().any(|added| added == entry))
+            .collect();
+        let kept = kept.join(";");
+        if kept == current {
+            return Ok(false);
+        }
+        let value = if kept.is_empty() { first.map(String::from) } else { Some(kept) };
+        match &value {
+            Some(value) => reg_set(scope, var, OsStr::new(value), RegistryType::Keep)?,
+            None => reg_delete(scope, var)?,
+        }
+        journal::record_registry(scope.key(), var, Some(app), Some(current), value)?;
+        Ok(true)
+    })
+}
+
+/// The scope whose value of `var` already has `entry` in
+/// it, the user's first. Entries are the same if they only
+/// differ in case or in backslashes at the end, since
+/// windows finds the same directory with either.
+pub(crate) fn has_entry(var: &str, entry: &str) -> io::Result<Option<Scope>> {
+    for scope in [Scope::User, Scope::Machine] {
+        let value = reg_get(scope, var)?.unwrap_or_default();
+        if value.split(';').any(|part| same_entry(part, entry)) {
+            return Ok(Some(scope));
+        }
+    }
+    Ok(None)
+}
+
+fn same_entry(a: &str, b: &str) -> bool {
+    let trim = |entry: &str| entry.trim().trim_end_matches(['\\', '/']).to_string();
+    let (a, b) = (trim(a), trim(b));
+    !a.is_empty() && a.eq_ignore_ascii_case(&b)
+}
+
+/// Whether `text` refers to another variable like
+/// `%USERPROFILE%`, which only a `REG_EXPAND_SZ` expands.
+pub(crate) fn has_reference(text: &str) -> bool {
+    let parts: Vec<&str> = text.split('%').collect();
+    (1..parts.len().saturating_sub(1)).step_by(2).any(|i| {
+        let name = parts[i];
+        !name.is_empty() && !name.contains(|c: char| c.is_whitespace() || c == ';' || c == '\\')
+    })
+}
+
+/// Whether we're running under Git Bash, MSYS2
+/// or Cygwin on windows.
+/// Their shells read a unix style `~/.bash_profile`
+/// as well as the registry environment.
+pub fn is_msys() -> bool {
+    cfg!(windows)
+        && (env::var_os("MSYSTEM").is_some()
+            || env::var_os("CYGWIN").is_some()
+            || env::var("SHELL").is_ok_and(|shell| shell.starts_with('/')))
+}
+
+/// Whether the process can write the machine's registry
+/// environment, which it can when it's run as administrator
+/// and elevated. It's always false off windows.
+pub fn is_elevated() -> bool {
+    can_write_machine()
+}
+
+/// Asks the user with UAC to let an elevated `reg.exe`
+/// import `reg`, the text of a `.reg` file, and waits for it.
+pub(crate) fn import_elevated(reg: &str) -> io::Result<()> {
+    let path = env::temp_dir().join(format!("env_perm-{}.reg", std::process::id()));
+    let bytes: Vec<u8> = std::iter::once(0xfeff)
+        .chain(reg.encode_utf16())
+        .flat_map(|unit: u16| unit.to_le_bytes())
+        .collect();
+    std::fs::write(&path, bytes)?;
+    let result = run_elevated(&path);
+    let _ = std::fs::remove_file(&path);
+    result?;
+    broadcast();
+    Ok(())
+}
+
+/// The windows path to the posix layer's `$HOME`.
+/// For MSYS2 and Cygwin that's under their
+/// install directory, not the windows profile.
+#[cfg(windows)]
+pub(crate) fn msys_home() -> Option<PathBuf> {
+    if !is_msys() {
+        return None;
+    }
+    let home = env::var("HOME").ok()?;
+    if !home.starts_with('/') {
+        return Some(PathBuf::from(home));
+    }
+    let output = Command::new("cygpath").args(["-w", &home]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(PathBuf::from(String::from_utf8(output.stdout).ok()?.trim()))
+}
+
+// The registry functions below see and make
+// planned writes instead while planning
+
+// The machine's variables are written with setx /M,
+// which needs the process to be elevated. setx only
+// says it was denied so that's checked first.
+fn setx(scope: Scope, var: &str, value: &OsStr) -> io::Result<()> {
+    fits(var, value, SETX_LIMIT, Backend::WindowsSetx)?;
+    if plan::planning() {
+        return Ok(());
+    }
+    if scope == Scope::Machine && !is_elevated() {
+        return Err(not_elevated(scope));
+    }
+    match run_setx(scope, var, value) {
+        // Nano Server and other cut down
+        // images don't have setx.exe
+        Err(e) if e.kind() == io::ErrorKind::NotFound => reg_set(scope, var, value, RegistryType::String),
+        result => result,
+    }
+}
+
+/// Whether `value` gets to setx as it is. setx gets its
+/// arguments in the console's code page, which can't hold
+/// most text that isn't ASCII. `%`, `^`, `&`, `|`, `<` and
+/// `>` mean something to cmd, which setx is often run
+/// through, and setx takes a leading `/` as a switch. A `"`
+/// or a trailing `\` ends up escaping the quotes around the
+/// argument rather than being part of it.
+pub(crate) fn setx_safe(value: &OsStr) -> bool {
+    value.to_str().is_some_and(|value| {
+        value.is_ascii()
+            && !value.contains(['%', '^', '&', '|', '<', '>', '"'])
+            && !value.starts_with('/')
+            && !value.ends_with('\\')
+    })
+}
+
+// Writing the registry directly doesn't tell anything
+// so these broadcast the change like setx does
+fn reg_set(scope: Scope, var: &str, value: &OsStr, kind: RegistryType) -> io::Result<()> {
+    fits(var, value, REGISTRY_LIMIT, Backend::WindowsRegistry)?;
+    if plan::planning() {
+        return Ok(());
+    }
+    set_value(scope, var, value, kind)?;
+    broadcast();
+    Ok(())
+}
+
+fn reg_delete(scope: Scope, var: &str) -> io::Result<()> {
+    if plan::planning() {
+        return Ok(());
+    }
+    delete_value(scope, var)?;
+    broadcast();
+    Ok(())
+}
+
+/// The value of `var` in the scope's registry environment.
+pub(crate) fn reg_get(scope: Scope, var: &str) -> io::Result<Option<String>> {
+    let place = Place::Registry { key: scope.key().to_string(), var: var.to_string() };
+    match plan::planned(&place) {
+        Some(planned) => Ok(planned),
+        None => get_value(scope, var),
+    }
+}
+
+/// Sets `var` to `value`, or deletes it if that's
+/// None, recording it in the journal for `app`.
+pub(crate) fn put(scope: Scope, var: &str, app: Option<&str>, value: Option<&str>) -> io::Result<()> {
+    let previous = reg_get(scope, var)?;
+    restore(scope, var, value)?;
+    record(scope, var, app, previous, value.map(OsStr::new))
+}
+
+// Runs `reg import` on the file at `path` as administrator
+// through PowerShell's Start-Process, which is
+// ShellExecute with the runas verb
+#[cfg(windows)]
+fn run_elevated(path: &std::path::Path) -> io::Result<()> {
+    let path = path.to_string_lossy().replace('\'', "''");
+    let script = format!(
+        "$p = Start-Process -FilePath reg.exe -ArgumentList 'import','\"{}\"' -Verb RunAs -WindowStyle Hidden -Wait -PassThru; exit $p.ExitCode",
+        path
+    );
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", &script])
+        .output()?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!(
+            "Writing to {} as administrator failed or was declined: {}",
+            MACHINE_ENVIRONMENT,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    ))
+}
+
+/// Runs `f` with the broadcast after each registry write
+/// held back, sending one at the end if anything was
+/// written. Every window is asked to reread the whole
+/// environment so one is as good as many.
+pub(crate) fn batched<T, F: FnOnce() -> T>(f: F) -> T {
+    if BATCH.with(Cell::get).is_some() {
+        return f();
+    }
+    BATCH.with(|batch| batch.set(Some(false)));
+    let batch = Batch;
+    let result = f();
+    drop(batch);
+    result
+}
+
+// Sends the held back broadcast when dropped, so
+// one still goes out if `f` fails or panics after
+// writing something
+struct Batch;
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        if BATCH.with(|batch| batch.replace(None)) == Some(true) {
+            notify();
+        }
+    }
+}
+
+fn broadcast() {
+    match BATCH.with(Cell::get) {
+        Some(_) => BATCH.with(|batch| batch.set(Some(true))),
+        None => notify(),
+    }
+}
+
+// Sends WM_SETTINGCHANGE for "Environment" to every top
+// level window so Explorer, and so programs started from it,
+// read the registry environment again without logging off.
+// Windows that don't answer within a second are skipped
+// rather than holding up the write.
+#[cfg(windows)]
+fn notify() {
+    use windows_sys::Win32::UI::WindowsAndMessaging::{
+        SendMessageTimeoutW, HWND_BROADCAST, SMTO_ABORTIFHUNG, WM_SETTINGCHANGE,
+    };
+
+    let area: Vec<u16> = "Environment".encode_utf16().chain(Some(0)).collect();
+    let mut result = 0;
+    // Safety: `area` is a null terminated string that
+    // outlives the call and `result` is a valid pointer
+    unsafe {
+        SendMessageTimeoutW(
+            HWND_BROADCAST,
+            WM_SETTINGCHANGE,
+            0,
+            area.as_ptr() as isize,
+            SMTO_ABORTIFHUNG,
+            1000,
+            &mut result,
+        );
+    }
+}
+
+// Whether the machine's environment key opens for writing
+#[cfg(windows)]
+fn can_write_machine() -> bool {
+    use winreg::enums::KEY_WRITE;
+
+    let (hive, path) = hive(Scope::Machine);
+    hive.open_subkey_with_flags(path, KEY_WRITE).is_ok()
+}
+
+/// Whether `var` in the scope's registry environment
+/// is a `REG_EXPAND_SZ`.
+#[cfg(windows)]
+pub(crate) fn is_expand(scope: Scope, var: &str) -> io::Result<bool> {
+    let key = match environment(scope)? {
+        Some(key) => key,
+        None => return Ok(false),
+    };
+    match key.get_raw_value(var) {
+        Ok(value) => Ok(value.vtype == winreg::enums::REG_EXPAND_SZ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Sets `var` to `value` using setx.
+#[cfg(windows)]
+fn run_setx(scope: Scope, var: &str, value: &OsStr) -> io::Result<()> {
+    let mut command = Command::new("setx");
+    command.arg(var).arg(value);
+    if scope == Scope::Machine {
+        command.arg("/M");
+    }
+    run(&mut command)
+}
+
+// Sets `var` to `value` as `kind`, which keeps the existing
+// value's type, e.g. REG_EXPAND_SZ for a PATH with
+// %USERPROFILE% in it. RegSetValueEx has no length
+// limit unlike setx.
+#[cfg(windows)]
+fn set_value(scope: Scope, var: &str, value: &OsStr, kind: RegistryType) -> io::Result<()> {
+    write_value(&writable(scope)?, var, value, kind).map_err(|e| denied(scope, e))
+}
+
+/// Sets `var` under `key` to `value` as `kind`.
+#[cfg(windows)]
+pub(crate) fn write_value(key: &winreg::RegKey, var: &str, value: &OsStr, kind: RegistryType) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+    use winreg::enums::{REG_EXPAND_SZ, REG_SZ};
+    use winreg::RegValue;
+
+    let vtype = match kind {
+        RegistryType::String => REG_SZ,
+        RegistryType::Expandable => REG_EXPAND_SZ,
+        RegistryType::Keep => match key.get_raw_value(var) {
+            Ok(existing) if existing.vtype == REG_EXPAND_SZ => REG_EXPAND_SZ,
+            Ok(_) => REG_SZ,
+            Err(_) if value.to_str().is_some_and(has_reference) => REG_EXPAND_SZ,
+            Err(_) => REG_SZ,
+        },
+    };
+    key.set_raw_value(var, &RegValue { bytes: reg_bytes(value.encode_wide()).into(), vtype })
+}
+
+#[cfg(windows)]
+fn delete_value(scope: Scope, var: &str) -> io::Result<()> {
+    if get_value(scope, var)?.is_none() {
+        return Ok(());
+    }
+    match writable(scope)?.delete_value(var) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(denied(scope, e)),
+        _ => Ok(()),
+    }
+}
+
+// The value of `var` in the scope's registry environment.
+// This is what's persisted rather than what this
+// process sees, so `%VAR%` references are left alone
+// and the user's and machine's entries aren't mixed.
+#[cfg(windows)]
+fn get_value(scope: Scope, var: &str) -> io::Result<Option<String>> {
+    match environment(scope)? {
+        Some(key) => read_value(&key, var, scope.key()),
+        None => Ok(None),
+    }
+}
+
+/// The text of `var` under `key`, which is called
+/// `name` if it isn't a string.
+#[cfg(windows)]
+pub(crate) fn read_value(key: &winreg::RegKey, var: &str, name: &str) -> io::Result<Option<String>> {
+    use winreg::enums::{REG_EXPAND_SZ, REG_SZ};
+
+    let value = match key.get_raw_value(var) {
+        Ok(value) => value,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    match value.vtype {
+        REG_SZ | REG_EXPAND_SZ => Ok(Some(String::from_utf16_lossy(&reg_units(&value.bytes)))),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} in {} isn't a string but a {:?}", var, name, value.vtype),
+        )),
+    }
+}
+
+// Every value in the scope's registry environment that's text
+#[cfg(windows)]
+pub(crate) fn reg_list(scope: Scope) -> io::Result<Vec<(String, String)>> {
+    use winreg::types::FromRegValue;
+
+    let key = match environment(scope)? {
+        Some(key) => key,
+        None => return Ok(Vec::new()),
+    };
+    let mut vars = Vec::new();
+    for value in key.enum_values() {
+        let (name, value) = value?;
+        if let Ok(value) = String::from_reg_value(&value) {
+            vars.push((name, value));
+        }
+    }
+    Ok(vars)
+}
+
+/// Every string variable in the scope's environment key
+/// with its type. Others, like a `REG_DWORD`, are left out.
+#[cfg(windows)]
+pub(crate) fn reg_values(scope: Scope) -> io::Result<Vec<RegistryValue>> {
+    use winreg::enums::{REG_EXPAND_SZ, REG_SZ};
+
+    let key = match environment(scope)? {
+        Some(key) => key,
+        None => return Ok(Vec::new()),
+    };
+    let mut values = Vec::new();
+    for value in key.enum_values() {
+        let (var, value) = value?;
+        let kind = match value.vtype {
+            REG_SZ => RegistryType::String,
+            REG_EXPAND_SZ => RegistryType::Expandable,
+            _ => continue,
+        };
+        let value = String::from_utf16_lossy(&reg_units(&value.bytes));
+        values.push(RegistryValue { var, value, kind, scope });
+    }
+    Ok(values)
+}
+
+// The hive and path of the scope's environment key
+#[cfg(windows)]
+fn hive(scope: Scope) -> (&'static winreg::RegKey, &'static str) {
+    match scope {
+        Scope::User => (winreg::HKCU, "Environment"),
+        Scope::Machine => (winreg::HKLM, r"SYSTEM\CurrentControlSet\Control\Session Manager\Environment"),
+    }
+}
+
+// The scope's environment key opened for reading,
+// if there is one
+#[cfg(windows)]
+fn environment(scope: Scope) -> io::Result<Option<winreg::RegKey>> {
+    let (hive, path) = hive(scope);
+    match hive.open_subkey(path) {
+        Ok(key) => Ok(Some(key)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// The scope's environment key opened for writing,
+// made if the user doesn't have one yet
+#[cfg(windows)]
+fn writable(scope: Scope) -> io::Result<winreg::RegKey> {
+    use winreg::enums::{KEY_READ, KEY_WRITE};
+
+    let (hive, path) = hive(scope);
+    match hive.create_subkey_with_flags(path, KEY_READ | KEY_WRITE) {
+        Ok((key, _)) => Ok(key),
+        Err(e) => Err(denied(scope, e)),
+    }
+}
+
+// `e`, or if it's because the process isn't elevated
+// an error that says so
+#[cfg(windows)]
+fn denied(scope: Scope, e: io::Error) -> io::Error {
+    if e.kind() != io::ErrorKind::PermissionDenied {
+        return e;
+    }
+    not_elevated(scope)
+}
+
+fn not_elevated(scope: Scope) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("Writing to {} needs the process to be run as administrator", scope.key()),
+    )
+}
+
+#[cfg(windows)]
+fn run(command: &mut Command) -> io::Result<()> {
+    let output = command.output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{:?} failed: {}",
+            command.get_program(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )))
+    }
+}
+
+#[cfg(not(windows))]
+fn run_setx(_scope: Scope, _var: &str, _value: &OsStr) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(windows))]
+fn set_value(_scope: Scope, _var: &str, _value: &OsStr, _kind: RegistryType) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(windows))]
+fn delete_value(_scope: Scope, _var: &str) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(windows))]
+fn get_value(_scope: Scope, _var: &str) -> io::Result<Option<String>> {
+    Err(unsupported())
+}
+
+#[cfg(not(windows))]
+pub(crate) fn reg_list(_scope: Scope) -> io::Result<Vec<(String, String)>> {
+    Err(unsupported())
+}
+
+#[cfg(not(windows))]
+pub(crate) fn reg_values(_scope: Scope) -> io::Result<Vec<RegistryValue>> {
+    Err(unsupported())
+}
+
+#[cfg(not(windows))]
+fn run_elevated(_path: &std::path::Path) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(not(windows))]
+fn notify() {}
+
+#[cfg(not(windows))]
+fn can_write_machine() -> bool {
+    false
+}
+
+#[cfg(not(windows))]
+pub(crate) fn is_expand(_scope: Scope, _var: &str) -> io::Result<bool> {
+    Err(unsupported())
+}
+
+#[cfg(not(windows))]
+fn unsupported() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "The windows registry is only available on windows")
+}
+
+/// The bytes of a `REG_SZ` holding `units`, the UTF-16
+/// windows keeps text in: little endian with a null on
+/// the end. Lone surrogates go in as they are.
+pub(crate) fn reg_bytes<I: IntoIterator<Item = u16>>(units: I) -> Vec<u8> {
+    units.into_iter().chain(Some(0)).flat_map(u16::to_le_bytes).collect()
+}
+
+/// The UTF-16 in the bytes of a `REG_SZ`, without
+/// the nulls on the end. A stray odd byte is dropped.
+#[cfg(any(windows, test))]
+pub(crate) fn reg_units(bytes: &[u8]) -> Vec<u16> {
+    let mut units: Vec<u16> = bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    while units.last() == Some(&0) {
+        units.pop();
+    }
+    units
+}
+
+// What to store for `value`, which is the value itself
+// unless it's Quoting::Prequoted. Quotes around the whole
+// value were how callers had to write it for the profiles
+// so a shell would take them off, here they'd end up in
+// the value.
+pub(crate) fn literal(quoting: Quoting, value: &OsStr) -> &OsStr {
+    match (quoting, value.to_str()) {
+        (Quoting::Prequoted, Some(text)) if text.len() >= 2 => ['"', '\'']
+            .iter()
+            .find_map(|&quote| text.strip_prefix(quote)?.strip_suffix(quote))
+            .map_or(value, OsStr::new),
+        _ => value,
+    }
+}
+
+// Two parts of a list with `separator` between, either of
+// which can be missing or empty. Separators and spaces
+// where they meet are dropped so `C:\a;` and `C:\b` give
+// `C:\a;C:\b`, without an empty entry or one that
+// starts with a space.
+pub(crate) fn join(first: Option<&OsStr>, second: Option<&OsStr>, separator: char) -> OsString {
+    let edge = [separator, ' '];
+    let first = first.map(|part| part.to_str().map_or(part, |text| OsStr::new(text.trim_end_matches(edge))));
+    let second = second.map(|part| part.to_str().map_or(part, |text| OsStr::new(text.trim_start_matches(edge))));
+    [first, second]
+        .iter()
+        .flatten()
+        .filter(|part| !part.is_empty())
+        .copied()
+        .collect::<Vec<_>>()
+        .join(OsStr::new(separator.encode_utf8(&mut [0; 4])))
+}
+
+// What appending `value` to the `current` value
+// stored in the registry gives, `value` going last
+fn appended(current: Option<&str>, value: &OsStr, separator: char) -> OsString {
+    join(current.map(OsStr::new), Some(value), separator)
+}
+
+// What prepending `value` to the `current`
+// value gives, `value` going first
+fn prepended(current: Option<&str>, value: &OsStr) -> OsString {
+    join(Some(value), current.map(OsStr::new), ';')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joined(first: Option<&str>, second: Option<&str>, separator: char) -> String {
+        join(first.map(OsStr::new), second.map(OsStr::new), separator).into_string().unwrap()
+    }
+
+    #[test]
+    fn join_without_current_value() {
+        assert_eq!(joined(None, Some(r"C:\b"), ';'), r"C:\b");
+        assert_eq!(joined(Some(""), Some(r"C:\b"), ';'), r"C:\b");
+        assert_eq!(joined(Some(r"C:\a"), None, ';'), r"C:\a");
+        assert_eq!(joined(Some(r"C:\a"), Some(""), ';'), r"C:\a");
+        assert_eq!(joined(None, None, ';'), "");
+    }
+
+    #[test]
+    fn join_trailing_separator() {
+        assert_eq!(joined(Some(r"C:\a;"), Some(r"C:\b"), ';'), r"C:\a;C:\b");
+        assert_eq!(joined(Some(r"C:\a;;"), Some(r"C:\b"), ';'), r"C:\a;C:\b");
+        assert_eq!(joined(Some(r"C:\a"), Some(r";C:\b"), ';'), r"C:\a;C:\b");
+        assert_eq!(joined(Some(";"), Some(r"C:\b"), ';'), r"C:\b");
+    }
+
+    #[test]
+    fn join_separator_and_space() {
+        assert_eq!(joined(Some(r"C:\a; "), Some(r"C:\b"), ';'), r"C:\a;C:\b");
+        assert_eq!(joined(Some(r"C:\a"), Some(r" ; C:\b"), ';'), r"C:\a;C:\b");
+        // Spaces inside an entry are part of it
+        assert_eq!(joined(Some(r"C:\Program Files\a"), Some(r"C:\b c"), ';'), r"C:\Program Files\a;C:\b c");
+    }
+
+    #[test]
+    fn join_custom_separator() {
+        assert_eq!(joined(Some("a,"), Some("b"), ','), "a,b");
+        assert_eq!(joined(Some("a"), Some("b"), ','), "a,b");
+        // Other separators are left alone
+        assert_eq!(joined(Some("a;"), Some("b"), ','), "a;,b");
+        assert_eq!(joined(Some(r"C:\a;C:\b"), Some("c"), ','), r"C:\a;C:\b,c");
+    }
+
+    #[test]
+    fn setx_safe_values() {
+        assert!(setx_safe(OsStr::new(r"C:\Program Files\MyApp")));
+        assert!(setx_safe(OsStr::new("plain-value_1.0")));
+        assert!(setx_safe(OsStr::new("")));
+        for value in ["50%", "a^b", "a&b", "a|b", "<in", "out>", r#"say "hi""#, "/M", r"C:\dir\"] {
+            assert!(!setx_safe(OsStr::new(value)), "{:?} should skip setx", value);
+        }
+        for value in ["Crème brûlée", "東京都", r"C:\Users\José\bin", "🦀"] {
+            assert!(!setx_safe(OsStr::new(value)), "{:?} should skip setx", value);
+        }
+        // Only a leading / is a switch and only
+        // a trailing \ escapes the quote
+        assert!(setx_safe(OsStr::new("a/b")));
+        assert!(setx_safe(OsStr::new(r"C:\dir\file")));
+    }
+
+    #[test]
+    fn setx_or_registry() {
+        let value = |text: &str| OsStr::new(text).to_os_string();
+        // Plain values go through setx
+        assert!(!past_setx(&value(r"C:\tools"), false, false));
+        assert!(!past_setx(&value(r"C:\tools"), true, false));
+        // setx would turn a REG_EXPAND_SZ into a REG_SZ
+        assert!(past_setx(&value(r"C:\tools"), true, true));
+        // References skip setx whether they're new
+        // or not, for their % if nothing else
+        assert!(past_setx(&value(r"%USERPROFILE%\bin"), false, false));
+        assert!(past_setx(&value(r"%USERPROFILE%\bin"), true, false));
+        // Anything setx's command line would change
+        for text in ["50%", "a^b&c", "a|b", "<x>", r#""quoted""#, "/M", r"C:\dir\"] {
+            assert!(past_setx(&value(text), true, false), "{:?} should skip setx", text);
+            assert!(past_setx(&value(text), false, false), "{:?} should skip setx", text);
+        }
+        // And anything the console's code page might not hold
+        for text in ["Crème brûlée", "東京都", "Ελληνικά"] {
+            assert!(past_setx(&value(text), true, false), "{:?} should skip setx", text);
+            assert!(past_setx(&value(text), false, false), "{:?} should skip setx", text);
+        }
+    }
+
+    #[test]
+    fn reg_text_round_trip() {
+        for text in ["Crème brûlée", "東京都", "Ελληνικά", "🦀 emoji", "", r"C:\ASCII;%PATH%"] {
+            let units: Vec<u16> = text.encode_utf16().collect();
+            let bytes = reg_bytes(units.iter().copied());
+            assert_eq!(bytes.len(), units.len() * 2 + 2);
+            assert_eq!(&bytes[bytes.len() - 2..], [0, 0]);
+            assert_eq!(String::from_utf16(&reg_units(&bytes)).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn reg_lone_surrogates() {
+        for units in [vec![0xd800], vec![0x61, 0xdc00, 0x62], vec![0xdfff, 0xd800]] {
+            assert_eq!(reg_units(&reg_bytes(units.iter().copied())), units);
+        }
+    }
+
+    #[test]
+    fn reg_units_stray_bytes() {
+        // Values written by other programs may have no null,
+        // several of them, or an odd length
+        assert_eq!(reg_units(&[0x61, 0]), [0x61]);
+        assert_eq!(reg_units(&[0x61, 0, 0, 0, 0, 0]), [0x61]);
+        assert_eq!(reg_units(&[0x61, 0, 0]), [0x61]);
+        assert_eq!(reg_units(&[]), [] as [u16; 0]);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn reg_os_string_round_trip() {
+        use std::os::windows::ffi::{OsStrExt, OsStringExt};
+
+        let values = [
+            OsString::from("Crème brûlée"),
+            OsString::from("東京都"),
+            OsString::from_wide(&[0x61, 0xd800, 0x62]),
+            OsString::from_wide(&[0xdc00]),
+        ];
+        for value in values {
+            assert_eq!(OsString::from_wide(&reg_units(&reg_bytes(value.encode_wide()))), value);
+        }
+    }
+
+    #[test]
+    fn literal_is_verbatim() {
+        for value in ["\"/something\"", "'quoted'", "\"a\" \"b\"", "plain", "\"", "''"] {
+            for quoting in [Quoting::ShellEscape, Quoting::None, Quoting::Double, Quoting::Single] {
+                assert_eq!(literal(quoting, OsStr::new(value)), value, "{:?}", quoting);
+            }
+        }
+    }
+
+    #[test]
+    fn literal_prequoted() {
+        let unquoted = |value: &str| literal(Quoting::Prequoted, OsStr::new(value)).to_str().unwrap().to_string();
+        assert_eq!(unquoted("\"/something\""), "/something");
+        assert_eq!(unquoted("'C:\\a b'"), "C:\\a b");
+        assert_eq!(unquoted("\"\""), "");
+        // Only a matching pair around the whole value
+        assert_eq!(unquoted("\"a' "), "\"a' ");
+        assert_eq!(unquoted("\"a\" b"), "\"a\" b");
+        assert_eq!(unquoted("\""), "\"");
+        assert_eq!(unquoted("plain"), "plain");
+    }
+
+    #[test]
+    fn join_order() {
+        assert_eq!(joined(Some("first"), Some("second"), ';'), "first;second");
+        assert_eq!(joined(Some("second"), Some("first"), ';'), "second;first");
+    }
+
+    #[test]
+    fn append_and_prepend_order() {
+        let mut value = appended(None, OsStr::new(r"C:\a"), ';');
+        for (entry, append) in [(r"C:\b", true), (r"C:\c", false), (r"C:\d", true), (r"C:\e", false)] {
+            let current = value.to_str().map(String::from);
+            value = if append {
+                appended(current.as_deref(), OsStr::new(entry), ';')
+            } else {
+                prepended(current.as_deref(), OsStr::new(entry))
+            };
+        }
+        assert_eq!(value, r"C:\e;C:\c;C:\a;C:\b;C:\d");
+        assert_eq!(appended(Some("a,b"), OsStr::new("c"), ','), "a,b,c");
+        assert_eq!(prepended(Some(""), OsStr::new(r"C:\a")), r"C:\a");
+    }
+}
@@ -0,0 +1,199 @@
+//! Persisting the windows user's variables from inside
+//! WSL by running PowerShell through interop.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+use crate::plan;
+use crate::windows::{self, RegistryType};
+use crate::{Backend, EnvBackend, Quoting};
+
+/// Whether we're running inside WSL with windows
+/// interop, so windows programs can be run.
+pub fn is_wsl() -> bool {
+    Path::new("/proc/sys/fs/binfmt_misc/WSLInterop").exists()
+        || fs::read_to_string("/proc/sys/kernel/osrelease").is_ok_and(|release| release.to_lowercase().contains("microsoft"))
+}
+
+/// The windows user's `HKCU\Environment`, written with
+/// `powershell.exe` so values keep their type and the
+/// change is broadcast like the registry backend does.
+#[derive(Debug, Clone)]
+pub(crate) struct Interop {
+    pub(crate) kind: RegistryType,
+    pub(crate) quoting: Quoting,
+}
+
+impl EnvBackend for Interop {
+    fn set(&self, var: &str, value: &str) -> io::Result<()> {
+        self.write(var, windows::literal(self.quoting, OsStr::new(value)))
+    }
+
+    fn append(&self, var: &str, value: &str) -> io::Result<()> {
+        self.append_with(var, value, ';')
+    }
+
+    fn append_with(&self, var: &str, value: &str, separator: char) -> io::Result<()> {
+        let previous = self.get(var)?;
+        let value = windows::literal(self.quoting, OsStr::new(value));
+        self.write(var, &windows::join(previous.as_deref().map(OsStr::new), Some(value), separator))
+    }
+
+    fn prepend(&self, var: &str, value: &str) -> io::Result<()> {
+        let previous = self.get(var)?;
+        let value = windows::literal(self.quoting, OsStr::new(value));
+        self.write(var, &windows::join(Some(value), previous.as_deref().map(OsStr::new), ';'))
+    }
+
+    fn remove(&self, var: &str) -> io::Result<()> {
+        if plan::planning() {
+            return Ok(());
+        }
+        powershell(&format!("{}$key.DeleteValue({}, $false)\n{}", OPEN, quote(var), BROADCAST)).map(drop)
+    }
+
+    // Windows lists are separated with `;`
+    // even though this is linux
+    fn remove_entry(&self, var: &str, entry: &str) -> io::Result<()> {
+        let current = match self.get(var)? {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        let kept: Vec<&str> = current.split(';').filter(|part| *part != entry).collect();
+        if kept.len() == current.split(';').count() {
+            return Ok(());
+        }
+        self.write(var, OsStr::new(&kept.join(";")))
+    }
+
+    fn dedupe(&self, var: &str) -> io::Result<()> {
+        let current = match self.get(var)? {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+        let mut kept: Vec<&str> = Vec::new();
+        for part in current.split(';') {
+            if !kept.contains(&part) {
+                kept.push(part);
+            }
+        }
+        if kept.len() == current.split(';').count() {
+            return Ok(());
+        }
+        self.write(var, OsStr::new(&kept.join(";")))
+    }
+
+    fn get(&self, var: &str) -> io::Result<Option<String>> {
+        let script = format!(
+            "{}$value = $key.GetValue({}, $null, 'DoNotExpandEnvironmentNames')\nif ($value -is [string]) {{ [Console]::Out.Write('=' + $value) }}",
+            OPEN,
+            quote(var)
+        );
+        Ok(powershell(&script)?.strip_prefix('=').map(String::from))
+    }
+
+    fn list(&self) -> io::Result<Vec<(String, String)>> {
+        let script = format!(
+            "{}foreach ($name in $key.GetValueNames()) {{\n$value = $key.GetValue($name, $null, 'DoNotExpandEnvironmentNames')\nif ($value -is [string]) {{ [Console]::Out.Write($name + [char]0 + $value + [char]0) }}\n}}",
+            OPEN
+        );
+        let output = powershell(&script)?;
+        let parts: Vec<&str> = output.split('\0').collect();
+        Ok(parts.chunks_exact(2).map(|pair| (pair[0].to_string(), pair[1].to_string())).collect())
+    }
+
+    fn contains(&self, var: &str, entry: &str) -> io::Result<bool> {
+        Ok(self.get(var)?.is_some_and(|value| value.split(';').any(|part| part == entry)))
+    }
+}
+
+impl Interop {
+    // Writes `value` as the type `self.kind` picks, working
+    // out what Keep means on the windows side
+    fn write(&self, var: &str, value: &OsStr) -> io::Result<()> {
+        windows::fits(var, value, windows::REGISTRY_LIMIT, Backend::WslInterop)?;
+        if plan::planning() {
+            return Ok(());
+        }
+        let value = value.to_string_lossy();
+        let kind = match self.kind {
+            RegistryType::String => "'String'".to_string(),
+            RegistryType::Expandable => "'ExpandString'".to_string(),
+            RegistryType::Keep => format!(
+                "$(if ($key.GetValueNames() -contains {var}) {{ $key.GetValueKind({var}) }} elseif (${new}) {{ 'ExpandString' }} else {{ 'String' }})",
+                var = quote(var),
+                new = windows::has_reference(&value),
+            ),
+        };
+        let script = format!("{}$key.SetValue({}, {}, {})\n{}", OPEN, quote(var), quote(&value), kind, BROADCAST);
+        powershell(&script).map(drop)
+    }
+}
+
+// Opens the user's environment key as `$key`
+const OPEN: &str = "$key = [Microsoft.Win32.Registry]::CurrentUser.CreateSubKey('Environment')\n";
+
+// Sends WM_SETTINGCHANGE like setx does
+const BROADCAST: &str = r#"Add-Type -Namespace EnvPerm -Name Native -MemberDefinition '[DllImport("user32.dll", CharSet = CharSet.Unicode)] public static extern IntPtr SendMessageTimeout(IntPtr hWnd, uint msg, UIntPtr wParam, string lParam, uint flags, uint timeout, out UIntPtr result);'
+$result = [UIntPtr]::Zero
+[void][EnvPerm.Native]::SendMessageTimeout([IntPtr]0xffff, 0x1a, [UIntPtr]::Zero, 'Environment', 2, 1000, [ref]$result)"#;
+
+// Runs `script` with powershell.exe, giving what it wrote.
+// It's passed encoded so nothing on the way to windows
+// can mangle its quotes, and the output is UTF-8 rather
+// than the console's code page.
+fn powershell(script: &str) -> io::Result<String> {
+    let script = format!("$ErrorActionPreference = 'Stop'\n[Console]::OutputEncoding = [Text.Encoding]::UTF8\n{}", script);
+    let encoded: Vec<u8> = script.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    let output = Command::new("powershell.exe")
+        .args(["-NoProfile", "-NonInteractive", "-EncodedCommand"])
+        .arg(base64(&encoded))
+        .output()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => io::Error::new(
+                io::ErrorKind::NotFound,
+                "powershell.exe isn't on PATH, WSL's windows interop has to be turned on",
+            ),
+            _ => e,
+        })?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "powershell.exe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    String::from_utf8(output.stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// `text` as a PowerShell string that's taken as it is,
+// which means doubling every kind of single quote
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in text.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &byte)| n | u32::from(byte) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(char::from(ALPHABET[(n >> (18 - 6 * i) & 63) as usize]));
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
@@ -0,0 +1,135 @@
+//! Editing `WSLENV`, the list of variables that are
+//! shared between windows and WSL and how.
+
+use std::io;
+
+/// `current` with `entry`, like `GOPATH/l`, in it. An entry
+/// for the same variable with other flags is given these
+/// instead. None if it's already there as it is.
+/// Names are compared ignoring case like windows does.
+pub(crate) fn with_entry(current: &str, entry: &str) -> io::Result<Option<String>> {
+    let (var, flags) = parse(entry)?;
+    let mut entries: Vec<String> = current.split(':').filter(|part| !part.is_empty()).map(String::from).collect();
+    match entries.iter().position(|part| name(part).eq_ignore_ascii_case(var)) {
+        Some(i) if same_flags(flags_of(&entries[i]), flags) => return Ok(None),
+        Some(i) => entries[i] = entry.to_string(),
+        None => entries.push(entry.to_string()),
+    }
+    Ok(Some(entries.join(":")))
+}
+
+/// `current` without the entry for `var` in any case,
+/// or None if it doesn't have one.
+pub(crate) fn without(current: &str, var: &str) -> Option<String> {
+    let entries: Vec<&str> = current.split(':').filter(|part| !part.is_empty()).collect();
+    if !entries.iter().any(|part| name(part).eq_ignore_ascii_case(var)) {
+        return None;
+    }
+    Some(entries.into_iter().filter(|part| !name(part).eq_ignore_ascii_case(var)).collect::<Vec<_>>().join(":"))
+}
+
+// The variable and flags of `entry`, failing if WSL
+// wouldn't understand it
+fn parse(entry: &str) -> io::Result<(&str, &str)> {
+    let (var, flags) = (name(entry), flags_of(entry));
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} isn't a WSLENV entry: {}", entry, reason));
+    if var.is_empty() || var.contains(['=', ':']) {
+        return Err(invalid("it needs a variable name before the flags"));
+    }
+    if let Some(flag) = flags.chars().find(|flag| !"plwu".contains(*flag)) {
+        return Err(invalid(&format!("{:?} isn't one of the flags p, l, u or w", flag)));
+    }
+    if flags.contains('p') && flags.contains('l') {
+        return Err(invalid("p and l can't both be given"));
+    }
+    if flags.contains('u') && flags.contains('w') {
+        return Err(invalid("u and w can't both be given, leave both out to share both ways"));
+    }
+    Ok((var, flags))
+}
+
+fn name(entry: &str) -> &str {
+    entry.split('/').next().unwrap_or(entry)
+}
+
+fn flags_of(entry: &str) -> &str {
+    entry.split_once('/').map_or("", |(_, flags)| flags)
+}
+
+// Flags are the same in any order
+fn same_flags(a: &str, b: &str) -> bool {
+    let sorted = |flags: &str| {
+        let mut flags: Vec<char> = flags.chars().collect();
+        flags.sort_unstable();
+        flags.dedup();
+        flags
+    };
+    sorted(a) == sorted(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn added(current: &str, entry: &str) -> Option<String> {
+        with_entry(current, entry).unwrap()
+    }
+
+    #[test]
+    fn adds_once() {
+        assert_eq!(added("", "GOPATH/l").as_deref(), Some("GOPATH/l"));
+        assert_eq!(added("WT_SESSION", "GOPATH/l").as_deref(), Some("WT_SESSION:GOPATH/l"));
+        assert_eq!(added("WT_SESSION:GOPATH/l", "GOPATH/l"), None);
+        assert_eq!(added("GOPATH", "GOPATH"), None);
+    }
+
+    #[test]
+    fn flags_in_any_order_are_the_same() {
+        assert_eq!(added("TMP/pu", "TMP/up"), None);
+        assert_eq!(added("TMP/pu", "TMP/upu"), None);
+    }
+
+    #[test]
+    fn replaces_other_flags() {
+        assert_eq!(added("A:GOPATH/p:B", "GOPATH/l").as_deref(), Some("A:GOPATH/l:B"));
+        assert_eq!(added("GOPATH/l", "GOPATH").as_deref(), Some("GOPATH"));
+    }
+
+    #[test]
+    fn names_ignore_case() {
+        assert_eq!(added("gopath/l", "GOPATH/l"), None);
+        assert_eq!(added("A:gopath/p", "GOPATH/l").as_deref(), Some("A:GOPATH/l"));
+        assert_eq!(without("A:GoPath/l:B", "GOPATH").as_deref(), Some("A:B"));
+    }
+
+    #[test]
+    fn stray_separators_are_dropped() {
+        assert_eq!(added(":A::B:", "C/u").as_deref(), Some("A:B:C/u"));
+        assert_eq!(without("::A:GOPATH/l:", "GOPATH").as_deref(), Some("A"));
+    }
+
+    #[test]
+    fn without_missing_entry() {
+        assert_eq!(without("A:B", "GOPATH"), None);
+        assert_eq!(without("", "GOPATH"), None);
+        assert_eq!(without("GOPATHS/l", "GOPATH"), None);
+        assert_eq!(without("GOPATH/l", "GOPATH").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn bad_entries() {
+        for (entry, reason) in [
+            ("/l", "it needs a variable name before the flags"),
+            ("A=B/l", "it needs a variable name before the flags"),
+            ("A:B", "it needs a variable name before the flags"),
+            ("A/x", "'x' isn't one of the flags p, l, u or w"),
+            ("A/P", "'P' isn't one of the flags p, l, u or w"),
+            ("A/pl", "p and l can't both be given"),
+            ("A/uw", "u and w can't both be given, leave both out to share both ways"),
+        ] {
+            let e = with_entry("", entry).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(e.to_string(), format!("{:?} isn't a WSLENV entry: {}", entry, reason));
+        }
+    }
+}